@@ -0,0 +1,61 @@
+//! Cold-open benchmark: load, parse, link and index a whole vault
+//!
+//! Vault sizes default to 1k, 10k and 100k notes; override with a
+//! comma-separated list, e.g. `ARKE_BENCH_NOTES=5000 cargo bench`.
+
+use arke_core::Vault;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Write a synthetic vault of `notes` notes spread over nested folders
+fn generate_vault(notes: usize) -> TempDir {
+    let temp = TempDir::new().unwrap();
+
+    for i in 0..notes {
+        let dir = temp
+            .path()
+            .join(format!("area{}", i % 20))
+            .join(format!("topic{}", i % 200));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let content = format!(
+            "---\ntags: [bench]\n---\n# Note {i}\n\nSome text linking [[note{}]] and [[note{}|alias]].\n\n\
+             ## Tasks\n\n- [ ] Follow up 📅 2025-11-01 #bench\n- [x] Done\n\n\
+             > [!note] Callout\n> Body with $x_{i}$ math ^block{i}\n\n```rust\nfn main() {{}}\n```\n",
+            (i + 1) % notes,
+            (i * 7) % notes,
+        );
+        std::fs::write(dir.join(format!("note{}.md", i)), content).unwrap();
+    }
+
+    temp
+}
+
+fn vault_sizes() -> Vec<usize> {
+    std::env::var("ARKE_BENCH_NOTES")
+        .ok()
+        .map(|s| s.split(',').filter_map(|n| n.trim().parse().ok()).collect())
+        .unwrap_or_else(|| vec![1_000, 10_000, 100_000])
+}
+
+fn cold_open(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cold_open");
+    group.sample_size(10);
+
+    for notes in vault_sizes() {
+        let vault_dir = generate_vault(notes);
+        group.throughput(Throughput::Elements(notes as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(notes), &vault_dir, |b, dir| {
+            b.iter(|| {
+                let vault = Vault::open(Path::new(dir.path())).unwrap();
+                vault.load_snapshot().unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, cold_open);
+criterion_main!(benches);
@@ -0,0 +1,343 @@
+//! Async vault API for native hosts
+//!
+//! Blocking file I/O runs on tokio's blocking pool, so async callers (such as
+//! Tauri command handlers) never stall their executor.
+
+use crate::batch::{Batch, BatchRecovery};
+use crate::error::{ArkeError, Result};
+use crate::events::EventBus;
+use crate::shared::SharedVault;
+use crate::trash::TrashEntry;
+use crate::vault::{Note, NoteVersion, Vault, VaultConfig};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// Options for [`AsyncVault::load_all`]
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    /// Maximum number of notes read concurrently
+    pub max_concurrency: usize,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 16,
+        }
+    }
+}
+
+/// Progress of a full-vault load
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadProgress {
+    /// Notes loaded so far
+    pub loaded: usize,
+    /// Total number of notes to load
+    pub total: usize,
+    /// The note that just finished loading
+    pub path: PathBuf,
+}
+
+/// Cloneable async handle to a vault on the local filesystem
+///
+/// Each call runs the matching [`Vault`] method on tokio's blocking pool,
+/// through a [`SharedVault`], so both APIs behave the same.
+#[derive(Clone)]
+pub struct AsyncVault {
+    shared: SharedVault,
+}
+
+impl From<SharedVault> for AsyncVault {
+    fn from(shared: SharedVault) -> Self {
+        Self { shared }
+    }
+}
+
+impl AsyncVault {
+    /// Open an existing vault at the given path, loading its saved settings
+    ///
+    /// See [`Vault::open`].
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let vault = run_blocking(move || Vault::open(path)).await?;
+        Ok(SharedVault::new(vault).into())
+    }
+
+    /// The synchronous handle this wraps
+    pub fn shared(&self) -> &SharedVault {
+        &self.shared
+    }
+
+    /// Get the vault configuration
+    pub fn config(&self) -> VaultConfig {
+        self.shared.read().config().clone()
+    }
+
+    /// The bus the vault emits change events on
+    pub fn events(&self) -> EventBus {
+        self.shared.events()
+    }
+
+    /// Run `op` against the vault on the blocking pool
+    async fn blocking<T, F>(&self, op: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SharedVault) -> Result<T> + Send + 'static,
+    {
+        let shared = self.shared.clone();
+        run_blocking(move || op(&shared)).await
+    }
+
+    /// List all markdown files in the vault, skipping ignored paths
+    pub async fn list_files(&self) -> Result<Vec<PathBuf>> {
+        self.blocking(|vault| vault.list_files()).await
+    }
+
+    /// Read a note, see [`Vault::read_note`]
+    pub async fn read_note<P: AsRef<Path>>(&self, path: P) -> Result<Arc<Note>> {
+        let path = path.as_ref().to_path_buf();
+        self.blocking(move |vault| vault.read_note(path)).await
+    }
+
+    /// Write a note, see [`Vault::write_note`]
+    pub async fn write_note(&self, path: &Path, content: &str) -> Result<()> {
+        let (path, content) = (path.to_path_buf(), content.to_string());
+        self.blocking(move |vault| vault.write_note(&path, &content))
+            .await
+    }
+
+    /// Write a note only if storage still holds the `expected` version
+    ///
+    /// See [`Vault::write_note_if`].
+    pub async fn write_note_if(
+        &self,
+        path: &Path,
+        content: &str,
+        expected: &NoteVersion,
+    ) -> Result<NoteVersion> {
+        let (path, content) = (path.to_path_buf(), content.to_string());
+        let expected = expected.clone();
+        self.blocking(move |vault| vault.write().write_note_if(&path, &content, &expected))
+            .await
+    }
+
+    /// Apply a batch of changes atomically, see [`Vault::apply_batch`]
+    pub async fn apply_batch(&self, batch: &Batch) -> Result<Vec<TrashEntry>> {
+        let batch = batch.clone();
+        self.blocking(move |vault| vault.apply_batch(&batch)).await
+    }
+
+    /// Roll back, or finish if it had committed, a batch interrupted by a crash
+    pub async fn recover_batch(&self) -> Result<Option<BatchRecovery>> {
+        self.blocking(|vault| vault.write().recover_batch()).await
+    }
+
+    /// Delete a note by moving it to the trash, see [`Vault::delete_note`]
+    pub async fn delete_note(&self, path: &Path) -> Result<TrashEntry> {
+        let path = path.to_path_buf();
+        self.blocking(move |vault| vault.delete_note(&path)).await
+    }
+
+    /// Rename/move a note, see [`Vault::rename_note`]
+    pub async fn rename_note(&self, old_path: &Path, new_path: &Path) -> Result<()> {
+        let (old_path, new_path) = (old_path.to_path_buf(), new_path.to_path_buf());
+        self.blocking(move |vault| vault.rename_note(&old_path, &new_path))
+            .await
+    }
+
+    /// Handle a file watcher event, see [`Vault::handle_watch_event`]
+    ///
+    /// Changes it finds are emitted on [`AsyncVault::events`], the same bus
+    /// API writes go to.
+    pub async fn handle_watch_event(&self, event: &notify::Event) -> Result<()> {
+        let event = event.clone();
+        self.blocking(move |vault| {
+            vault.handle_watch_event(&event);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Scan the vault and read every note, at most `max_concurrency` at a time
+    ///
+    /// `progress` is called after each note is read. When `cancel` fires, no
+    /// further reads are started and [`ArkeError::Cancelled`] is returned.
+    /// Notes are returned sorted by path.
+    pub async fn load_all<F>(
+        &self,
+        options: LoadOptions,
+        cancel: CancellationToken,
+        mut progress: F,
+    ) -> Result<Vec<Arc<Note>>>
+    where
+        F: FnMut(LoadProgress) + Send,
+    {
+        let files = tokio::select! {
+            files = self.list_files() => files?,
+            _ = cancel.cancelled() => return Err(ArkeError::Cancelled),
+        };
+
+        let total = files.len();
+        let max_concurrency = options.max_concurrency.max(1);
+        let mut pending = files.into_iter();
+        let mut in_flight = JoinSet::new();
+        let mut notes = Vec::with_capacity(total);
+
+        loop {
+            while in_flight.len() < max_concurrency && !cancel.is_cancelled() {
+                let Some(path) = pending.next() else {
+                    break;
+                };
+                let shared = self.shared.clone();
+                in_flight.spawn_blocking(move || shared.read_note(path));
+            }
+
+            let joined = tokio::select! {
+                joined = in_flight.join_next() => joined,
+                _ = cancel.cancelled() => {
+                    in_flight.abort_all();
+                    return Err(ArkeError::Cancelled);
+                }
+            };
+
+            let Some(joined) = joined else {
+                break;
+            };
+            let note =
+                joined.map_err(|e| ArkeError::Unknown(format!("Storage task failed: {}", e)))??;
+
+            progress(LoadProgress {
+                loaded: notes.len() + 1,
+                total,
+                path: note.path.clone(),
+            });
+            notes.push(note);
+        }
+
+        if cancel.is_cancelled() {
+            return Err(ArkeError::Cancelled);
+        }
+
+        notes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(notes)
+    }
+}
+
+/// Run a blocking closure on tokio's blocking pool
+async fn run_blocking<T, F>(op: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(op)
+        .await
+        .map_err(|e| ArkeError::Unknown(format!("Storage task failed: {}", e)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    async fn create_test_vault(notes: usize) -> (TempDir, AsyncVault) {
+        let temp = TempDir::new().unwrap();
+        let vault = AsyncVault::open(temp.path()).await.unwrap();
+        for i in 0..notes {
+            let path = PathBuf::from(format!("dir{}/note{}.md", i % 3, i));
+            vault
+                .write_note(&path, &format!("# Note {}", i))
+                .await
+                .unwrap();
+        }
+        (temp, vault)
+    }
+
+    #[tokio::test]
+    async fn test_async_note_operations() {
+        let (_temp, vault) = create_test_vault(0).await;
+        let path = Path::new("a.md");
+
+        vault.write_note(path, "hello").await.unwrap();
+        assert_eq!(vault.read_note(path).await.unwrap().content, "hello");
+
+        vault
+            .rename_note(path, Path::new("sub/b.md"))
+            .await
+            .unwrap();
+        assert_eq!(
+            vault.list_files().await.unwrap(),
+            vec![PathBuf::from("sub/b.md")]
+        );
+
+        vault.delete_note(Path::new("sub/b.md")).await.unwrap();
+        assert!(matches!(
+            vault.read_note("sub/b.md").await,
+            Err(ArkeError::FileNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_async_writes_go_through_the_vault() {
+        let (temp, vault) = create_test_vault(0).await;
+        let path = Path::new("a.md");
+        vault.write_note(path, "one").await.unwrap();
+        let version = vault.read_note(path).await.unwrap().version();
+
+        // A change on disk is caught by the same version check `Vault` uses
+        std::fs::write(temp.path().join(path), "theirs").unwrap();
+        assert!(matches!(
+            vault.write_note_if(path, "two", &version).await,
+            Err(ArkeError::Conflict { .. })
+        ));
+
+        // And the shared handle sees the same vault, events included
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        vault
+            .events()
+            .subscribe(move |event| sink.lock().unwrap().push(event.clone()));
+        vault.shared().write_note(path, "three").unwrap();
+        vault.write_note(Path::new("b.md"), "").await.unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_load_all_reports_progress() {
+        let (_temp, vault) = create_test_vault(20).await;
+        let mut updates = Vec::new();
+
+        let notes = vault
+            .load_all(
+                LoadOptions { max_concurrency: 4 },
+                CancellationToken::new(),
+                |p| updates.push(p),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(notes.len(), 20);
+        assert!(notes.windows(2).all(|w| w[0].path < w[1].path));
+        assert_eq!(updates.len(), 20);
+        assert_eq!(updates.last().unwrap().loaded, 20);
+        assert!(updates.iter().all(|p| p.total == 20));
+    }
+
+    #[tokio::test]
+    async fn test_load_all_cancellation() {
+        let (_temp, vault) = create_test_vault(20).await;
+        let cancel = CancellationToken::new();
+        let token = cancel.clone();
+
+        let result = vault
+            .load_all(LoadOptions { max_concurrency: 1 }, cancel, move |p| {
+                if p.loaded == 5 {
+                    token.cancel();
+                }
+            })
+            .await;
+
+        assert!(matches!(result, Err(ArkeError::Cancelled)));
+    }
+}
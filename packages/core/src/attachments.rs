@@ -0,0 +1,383 @@
+//! Attachments: images, PDFs and other non-markdown files in the vault
+//!
+//! Notes reference attachments with embeds such as `![[image.png]]` or
+//! markdown links such as `![](assets/x.png)`.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+/// How an attachment is referenced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReferenceStyle {
+    /// `[[file.pdf]]` or `![[image.png]]`, resolved by file name
+    Wikilink,
+    /// `[text](path)` or `![alt](path)`, resolved relative to the note
+    Markdown,
+}
+
+/// A reference from a note to an attachment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachmentRef {
+    /// The target as written, without any `#fragment` or `|size`
+    pub target: String,
+    /// Whether the reference embeds the file (`!` prefix)
+    pub embed: bool,
+    /// Link syntax used
+    pub style: ReferenceStyle,
+    /// Position in the source text
+    pub position: usize,
+}
+
+/// An attachment reference that doesn't resolve to any file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingAttachment {
+    /// Note containing the reference
+    pub note: PathBuf,
+    /// The target as written
+    pub target: String,
+}
+
+/// Attachments no note references, and references to attachments that don't exist
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachmentReport {
+    /// Attachments no note references, sorted
+    pub orphaned: Vec<PathBuf>,
+    /// References that don't resolve, sorted by note
+    pub missing: Vec<MissingAttachment>,
+}
+
+/// Extracts attachment references from markdown
+pub struct AttachmentExtractor {
+    wikilink_regex: Regex,
+    markdown_regex: Regex,
+}
+
+impl AttachmentExtractor {
+    /// Create a new attachment extractor
+    pub fn new() -> Self {
+        // Matches [[target]], ![[target|300]] and the like
+        let wikilink_regex =
+            Regex::new(r"(!?)\[\[([^\]|]+)(?:\|[^\]]*)?\]\]").expect("Invalid wikilink regex");
+        // Matches [text](target) and ![alt](<target> "title")
+        let markdown_regex =
+            Regex::new(r#"(!?)\[[^\]]*\]\(\s*(<[^>]+>|[^)\s]+)(?:\s+"[^"]*")?\s*\)"#)
+                .expect("Invalid markdown link regex");
+
+        Self {
+            wikilink_regex,
+            markdown_regex,
+        }
+    }
+
+    /// Extract every reference to a non-markdown file, in source order
+    pub fn extract(&self, content: &str) -> Vec<AttachmentRef> {
+        let mut refs = Vec::new();
+
+        for cap in self.wikilink_regex.captures_iter(content) {
+            let target = strip_fragment(cap.get(2).unwrap().as_str()).trim();
+            if has_attachment_extension(target) {
+                refs.push(AttachmentRef {
+                    target: target.to_string(),
+                    embed: !cap[1].is_empty(),
+                    style: ReferenceStyle::Wikilink,
+                    position: cap.get(0).unwrap().start(),
+                });
+            }
+        }
+
+        refs.extend(
+            self.markdown_links(content)
+                .into_iter()
+                .filter(|r| has_attachment_extension(&r.target)),
+        );
+
+        refs.sort_by_key(|r| r.position);
+        refs
+    }
+
+    /// Every markdown link or embed to a local file, notes included, in source order
+    pub(crate) fn markdown_links(&self, content: &str) -> Vec<AttachmentRef> {
+        let mut refs = Vec::new();
+        for cap in self.markdown_regex.captures_iter(content) {
+            let raw = cap.get(2).unwrap().as_str();
+            let raw = raw
+                .strip_prefix('<')
+                .and_then(|r| r.strip_suffix('>'))
+                .unwrap_or(raw);
+            if is_external(raw) {
+                continue;
+            }
+
+            refs.push(AttachmentRef {
+                target: percent_decode(strip_fragment(raw)),
+                embed: !cap[1].is_empty(),
+                style: ReferenceStyle::Markdown,
+                position: cap.get(0).unwrap().start(),
+            });
+        }
+        refs
+    }
+}
+
+impl Default for AttachmentExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Lookup table resolving attachment references
+#[derive(Debug, Clone, Default)]
+pub struct AttachmentIndex {
+    paths: HashSet<PathBuf>,
+    by_name: HashMap<String, Vec<PathBuf>>,
+}
+
+impl AttachmentIndex {
+    /// Build an index over the given attachment paths
+    pub fn new(attachments: &[PathBuf]) -> Self {
+        let mut index = Self::default();
+
+        for path in attachments {
+            index.paths.insert(path.clone());
+            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                index
+                    .by_name
+                    .entry(name.to_lowercase())
+                    .or_default()
+                    .push(path.clone());
+            }
+        }
+
+        // Prefer the shallowest match, as Obsidian does
+        for paths in index.by_name.values_mut() {
+            paths.sort_by_key(|p| (p.components().count(), p.clone()));
+        }
+
+        index
+    }
+
+    /// Resolve a reference made from `note`
+    ///
+    /// Markdown links are tried relative to the note, then to the vault root.
+    /// Wikilinks are tried as a vault path, then matched by file name.
+    pub fn resolve(&self, reference: &AttachmentRef, note: &Path) -> Option<&PathBuf> {
+        let target = reference.target.as_str();
+
+        if reference.style == ReferenceStyle::Markdown && !target.starts_with('/') {
+            let dir = note.parent().unwrap_or(Path::new(""));
+            if let Some(path) = normalize(&dir.join(target)).and_then(|p| self.paths.get(&p)) {
+                return Some(path);
+            }
+        }
+
+        if let Some(path) =
+            normalize(Path::new(target.trim_start_matches('/'))).and_then(|p| self.paths.get(&p))
+        {
+            return Some(path);
+        }
+
+        if reference.style == ReferenceStyle::Wikilink {
+            let name = Path::new(target).file_name()?.to_str()?.to_lowercase();
+            let suffix = Path::new(target);
+            return self
+                .by_name
+                .get(&name)?
+                .iter()
+                .find(|p| p.ends_with(suffix))
+                .or_else(|| self.by_name.get(&name)?.first());
+        }
+
+        None
+    }
+}
+
+/// Whether a path names an attachment rather than a markdown note
+pub fn is_attachment(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) != Some("md")
+}
+
+/// Pick a path for `name` inside `folder` that `exists` doesn't reject
+///
+/// Collisions get a numeric suffix: `image.png`, `image 1.png`, `image 2.png`, ...
+pub(crate) fn unique_path(folder: &Path, name: &str, exists: impl Fn(&Path) -> bool) -> PathBuf {
+    let candidate = folder.join(name);
+    if !exists(&candidate) {
+        return candidate;
+    }
+
+    let file = Path::new(name);
+    let stem = file.file_stem().and_then(|s| s.to_str()).unwrap_or(name);
+    let ext = file.extension().and_then(|e| e.to_str());
+
+    (1..)
+        .map(|n| match ext {
+            Some(ext) => folder.join(format!("{} {}.{}", stem, n, ext)),
+            None => folder.join(format!("{} {}", stem, n)),
+        })
+        .find(|p| !exists(p))
+        .unwrap()
+}
+
+/// Drop a `#fragment` or `?query` from a link target
+pub(crate) fn strip_fragment(target: &str) -> &str {
+    target.split(['#', '?']).next().unwrap_or(target)
+}
+
+/// Whether a link target points outside the vault (`https:`, `mailto:`, ...)
+pub(crate) fn is_external(target: &str) -> bool {
+    target.starts_with("//")
+        || target.split_once(':').is_some_and(|(scheme, _)| {
+            scheme.len() > 1
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+')
+        })
+}
+
+/// Whether a target has a file extension other than `.md`
+///
+/// The extension must contain a letter, so names like `Meeting 2024.01.05`
+/// still count as notes.
+fn has_attachment_extension(target: &str) -> bool {
+    Path::new(target)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| {
+            !ext.eq_ignore_ascii_case("md")
+                && ext.chars().all(|c| c.is_ascii_alphanumeric())
+                && ext.chars().any(|c| c.is_ascii_alphabetic())
+        })
+}
+
+/// Decode `%XX` escapes in a markdown link target
+pub(crate) fn percent_decode(target: &str) -> String {
+    let bytes = target.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = target
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8(out).unwrap_or_else(|_| target.to_string())
+}
+
+/// Resolve `.` and `..` components; `None` if the path escapes the vault root
+pub(crate) fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => out.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_references() {
+        let extractor = AttachmentExtractor::new();
+        let content = "![[photo.png|300]] [[Meeting 2024.01.05]] [[spec.pdf#page=2]]\n\
+                       ![alt](assets/my%20chart.svg \"Chart\") [note](other.md) \
+                       [site](https://example.com/a.png) ![x](<file name.jpg>)";
+        let refs = extractor.extract(content);
+
+        let targets: Vec<(&str, bool, ReferenceStyle)> = refs
+            .iter()
+            .map(|r| (r.target.as_str(), r.embed, r.style))
+            .collect();
+        assert_eq!(
+            targets,
+            vec![
+                ("photo.png", true, ReferenceStyle::Wikilink),
+                ("spec.pdf", false, ReferenceStyle::Wikilink),
+                ("assets/my chart.svg", true, ReferenceStyle::Markdown),
+                ("file name.jpg", true, ReferenceStyle::Markdown),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_references() {
+        let index = AttachmentIndex::new(&[
+            PathBuf::from("assets/deep/photo.png"),
+            PathBuf::from("assets/photo.png"),
+            PathBuf::from("notes/img/local.png"),
+        ]);
+        let reference = |target: &str, style| AttachmentRef {
+            target: target.to_string(),
+            embed: true,
+            style,
+            position: 0,
+        };
+        let note = Path::new("notes/today.md");
+
+        let resolve = |target, style| index.resolve(&reference(target, style), note).cloned();
+        assert_eq!(
+            resolve("PHOTO.png", ReferenceStyle::Wikilink),
+            Some(PathBuf::from("assets/photo.png"))
+        );
+        assert_eq!(
+            resolve("deep/photo.png", ReferenceStyle::Wikilink),
+            Some(PathBuf::from("assets/deep/photo.png"))
+        );
+        assert_eq!(
+            resolve("img/local.png", ReferenceStyle::Markdown),
+            Some(PathBuf::from("notes/img/local.png"))
+        );
+        assert_eq!(
+            resolve("../assets/photo.png", ReferenceStyle::Markdown),
+            Some(PathBuf::from("assets/photo.png"))
+        );
+        assert_eq!(
+            resolve("/assets/photo.png", ReferenceStyle::Markdown),
+            Some(PathBuf::from("assets/photo.png"))
+        );
+        assert_eq!(resolve("local.png", ReferenceStyle::Markdown), None);
+    }
+
+    #[test]
+    fn test_unique_path() {
+        let taken = [
+            PathBuf::from("assets/a.png"),
+            PathBuf::from("assets/a 1.png"),
+        ];
+        let exists = |p: &Path| taken.iter().any(|t| t == p);
+
+        assert_eq!(
+            unique_path(Path::new("assets"), "b.png", exists),
+            PathBuf::from("assets/b.png")
+        );
+        assert_eq!(
+            unique_path(Path::new("assets"), "a.png", exists),
+            PathBuf::from("assets/a 2.png")
+        );
+    }
+}
@@ -0,0 +1,528 @@
+//! Multi-file changes applied all or nothing
+//!
+//! A [`Batch`] stages writes, renames and deletes. While it is applied, an
+//! undo journal in `.arke/journal` records each step and keeps what the step
+//! replaces, so a failed batch is rolled back and a batch interrupted by a
+//! crash is rolled back (or, once committed, finished) by [`recover`].
+
+use crate::config::HistorySettings;
+use crate::error::{ArkeError, Result};
+use crate::history;
+use crate::storage::Storage;
+use crate::trash::{self, TrashEntry};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Journal directory, relative to the vault root
+pub const JOURNAL_DIR: &str = ".arke/journal";
+
+const JOURNAL_FILE: &str = ".arke/journal/journal.json";
+
+/// A staged change to a single file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum BatchOp {
+    /// Create or replace a note
+    Write { path: PathBuf, content: String },
+    /// Rename or move a file or directory, replacing anything at `to`
+    ///
+    /// Unlike [`Vault::rename_note`](crate::Vault::rename_note), note history stays at `from`.
+    Rename { from: PathBuf, to: PathBuf },
+    /// Move a file to the vault trash
+    Delete { path: PathBuf },
+}
+
+/// Changes to apply together, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Batch {
+    pub ops: Vec<BatchOp>,
+}
+
+impl Batch {
+    /// Create an empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stage a write
+    pub fn write(&mut self, path: impl Into<PathBuf>, content: impl Into<String>) -> &mut Self {
+        self.ops.push(BatchOp::Write {
+            path: path.into(),
+            content: content.into(),
+        });
+        self
+    }
+
+    /// Stage a rename
+    pub fn rename(&mut self, from: impl Into<PathBuf>, to: impl Into<PathBuf>) -> &mut Self {
+        self.ops.push(BatchOp::Rename {
+            from: from.into(),
+            to: to.into(),
+        });
+        self
+    }
+
+    /// Stage a delete
+    pub fn delete(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.ops.push(BatchOp::Delete { path: path.into() });
+        self
+    }
+
+    /// Whether nothing is staged
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Every path the batch touches
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.ops.iter().flat_map(|op| match op {
+            BatchOp::Write { path, .. } | BatchOp::Delete { path } => vec![path.as_path()],
+            BatchOp::Rename { from, to } => vec![from.as_path(), to.as_path()],
+        })
+    }
+}
+
+/// What [`recover`] did with an interrupted batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchRecovery {
+    /// The batch hadn't finished applying and was undone
+    RolledBack,
+    /// The batch had been applied; its cleanup was finished
+    Completed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Step {
+    op: BatchOp,
+    /// Whether the step saved what it replaces to its backup file
+    backup: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    steps: Vec<Step>,
+    /// The last step that may have started
+    current: usize,
+    committed: bool,
+}
+
+/// Apply every change in `batch`, or none of them
+///
+/// Returns the trash entries of deleted files. On failure the changes made
+/// so far are undone and the original error is returned. If undoing fails
+/// too, the journal stays for [`recover`]. Once the batch has committed it
+/// is reported as applied, even if recording history or trashing fails.
+pub(crate) fn apply<S: Storage + ?Sized>(
+    storage: &S,
+    history: &HistorySettings,
+    batch: &Batch,
+) -> Result<Vec<TrashEntry>> {
+    if batch.is_empty() {
+        return Ok(Vec::new());
+    }
+    if storage.exists(Path::new(JOURNAL_FILE)) {
+        return Err(ArkeError::Vault(
+            "An interrupted batch must be recovered first".to_string(),
+        ));
+    }
+
+    let mut journal = Journal {
+        steps: Vec::with_capacity(batch.ops.len()),
+        current: 0,
+        committed: false,
+    };
+
+    let mut result = Ok(());
+    for (k, op) in batch.ops.iter().enumerate() {
+        result = prepare(storage, k, op).and_then(|backup| {
+            journal.steps.push(Step {
+                op: op.clone(),
+                backup,
+            });
+            journal.current = k;
+            write_journal(storage, &journal)?;
+            execute(storage, k, &journal.steps[k])
+        });
+        if result.is_err() {
+            break;
+        }
+    }
+
+    if result.is_ok() {
+        journal.committed = true;
+        result = write_journal(storage, &journal);
+    }
+    if let Err(e) = result {
+        if rollback(storage, &journal).is_ok() {
+            let _ = cleanup(storage);
+        }
+        return Err(e);
+    }
+
+    Ok(finish(storage, history, &journal))
+}
+
+/// Roll back or finish a batch interrupted by a crash, if there is one
+pub(crate) fn recover<S: Storage + ?Sized>(
+    storage: &S,
+    history: &HistorySettings,
+) -> Result<Option<BatchRecovery>> {
+    let journal: Journal = match storage.read_to_string(Path::new(JOURNAL_FILE)) {
+        Ok(json) => serde_json::from_str(&json)?,
+        Err(ArkeError::FileNotFound(_)) => {
+            // Backups left by a batch that crashed before its journal was written
+            cleanup(storage)?;
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+
+    if journal.committed {
+        finish(storage, history, &journal);
+        Ok(Some(BatchRecovery::Completed))
+    } else {
+        rollback(storage, &journal)?;
+        cleanup(storage)?;
+        Ok(Some(BatchRecovery::RolledBack))
+    }
+}
+
+/// Check a step can run and save what it overwrites; returns whether a backup is needed
+fn prepare<S: Storage + ?Sized>(storage: &S, k: usize, op: &BatchOp) -> Result<bool> {
+    match op {
+        BatchOp::Write { path, .. } => match storage.read(path) {
+            Ok(original) => {
+                storage.write(&backup_path(k), &original)?;
+                Ok(true)
+            }
+            Err(ArkeError::FileNotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        },
+        BatchOp::Rename { from, to } => {
+            if from == to {
+                return Err(ArkeError::Vault(format!(
+                    "Cannot rename {} to itself",
+                    from.display()
+                )));
+            }
+            storage.metadata(from)?;
+            Ok(storage.exists(to))
+        }
+        BatchOp::Delete { path } => {
+            require_file(storage, path)?;
+            Ok(true)
+        }
+    }
+}
+
+fn execute<S: Storage + ?Sized>(storage: &S, k: usize, step: &Step) -> Result<()> {
+    match &step.op {
+        BatchOp::Write { path, content } => storage.write(path, content.as_bytes()),
+        BatchOp::Rename { from, to } => {
+            if step.backup {
+                storage.rename(to, &backup_path(k))?;
+            }
+            storage.rename(from, to)
+        }
+        BatchOp::Delete { path } => storage.rename(path, &backup_path(k)),
+    }
+}
+
+/// Undo every step that may have started, newest first
+///
+/// Each undo checks what is actually in storage, so it is safe whether or
+/// not its step ran, and safe to repeat.
+fn rollback<S: Storage + ?Sized>(storage: &S, journal: &Journal) -> Result<()> {
+    for (k, step) in journal
+        .steps
+        .iter()
+        .enumerate()
+        .take(journal.current + 1)
+        .rev()
+    {
+        let backup = backup_path(k);
+        match &step.op {
+            BatchOp::Write { path, .. } => {
+                if step.backup {
+                    storage.write(path, &storage.read(&backup)?)?;
+                } else if storage.exists(path) {
+                    storage.remove(path)?;
+                }
+            }
+            BatchOp::Rename { from, to } => {
+                // `from` existed when the step was prepared, so if it's gone the rename ran
+                if !storage.exists(from) && storage.exists(to) {
+                    storage.rename(to, from)?;
+                }
+                if step.backup && storage.exists(&backup) {
+                    storage.rename(&backup, to)?;
+                }
+            }
+            BatchOp::Delete { path } => {
+                if storage.exists(&backup) {
+                    storage.rename(&backup, path)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Record history, move deleted files to the trash and clear the journal of a committed batch
+///
+/// Nothing here can undo the batch, so failures are not errors. History is
+/// best-effort; if a deleted file can't be trashed, the journal and backups
+/// stay so [`recover`] can try again.
+fn finish<S: Storage + ?Sized>(
+    storage: &S,
+    history: &HistorySettings,
+    journal: &Journal,
+) -> Vec<TrashEntry> {
+    let mut trashed = Vec::new();
+    let mut done = true;
+    for (k, step) in journal.steps.iter().enumerate() {
+        let backup = backup_path(k);
+        match &step.op {
+            BatchOp::Write { path, content } if step.backup => {
+                if let Ok(previous) = storage.read(&backup) {
+                    let _ = history::record_previous(
+                        storage,
+                        history,
+                        path,
+                        &previous,
+                        content.as_bytes(),
+                    );
+                }
+            }
+            BatchOp::Delete { path } if storage.exists(&backup) => {
+                match trash::move_to_trash_as(storage, &backup, path) {
+                    Ok(entry) => trashed.push(entry),
+                    Err(_) => done = false,
+                }
+            }
+            _ => {}
+        }
+    }
+    if done {
+        let _ = cleanup(storage);
+    }
+    trashed
+}
+
+/// Remove the journal, then any backups
+fn cleanup<S: Storage + ?Sized>(storage: &S) -> Result<()> {
+    match storage.remove(Path::new(JOURNAL_FILE)) {
+        Ok(()) | Err(ArkeError::FileNotFound(_)) => {}
+        Err(e) => return Err(e),
+    }
+    let entries = match storage.list(Path::new(JOURNAL_DIR)) {
+        Ok(entries) => entries,
+        Err(ArkeError::FileNotFound(_)) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries.into_iter().filter(|e| !e.is_dir) {
+        storage.remove(&entry.path)?;
+    }
+    Ok(())
+}
+
+fn require_file<S: Storage + ?Sized>(storage: &S, path: &Path) -> Result<()> {
+    if storage.metadata(path)?.is_dir {
+        return Err(ArkeError::Vault(format!(
+            "Expected a file, found a directory: {}",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+fn write_journal<S: Storage + ?Sized>(storage: &S, journal: &Journal) -> Result<()> {
+    storage.write(Path::new(JOURNAL_FILE), &serde_json::to_vec(journal)?)
+}
+
+fn backup_path(k: usize) -> PathBuf {
+    Path::new(JOURNAL_DIR).join(k.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{DirEntry, FileMetadata, MemoryStorage};
+    use std::cell::Cell;
+
+    /// Storage whose changes start failing after `budget` of them, as if the process died
+    struct Crashing<'a> {
+        inner: &'a MemoryStorage,
+        budget: Cell<usize>,
+    }
+
+    impl Crashing<'_> {
+        fn spend(&self) -> Result<()> {
+            match self.budget.get() {
+                0 => Err(ArkeError::Unknown("crashed".to_string())),
+                n => {
+                    self.budget.set(n - 1);
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    impl Storage for Crashing<'_> {
+        fn list(&self, dir: &Path) -> Result<Vec<DirEntry>> {
+            self.inner.list(dir)
+        }
+        fn read(&self, path: &Path) -> Result<Vec<u8>> {
+            self.inner.read(path)
+        }
+        fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+            self.spend()?;
+            self.inner.write(path, contents)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+            self.spend()?;
+            self.inner.rename(from, to)
+        }
+        fn remove(&self, path: &Path) -> Result<()> {
+            self.spend()?;
+            self.inner.remove(path)
+        }
+        fn create_dir(&self, path: &Path) -> Result<()> {
+            self.spend()?;
+            self.inner.create_dir(path)
+        }
+        fn remove_dir(&self, path: &Path) -> Result<()> {
+            self.spend()?;
+            self.inner.remove_dir(path)
+        }
+        fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+            self.inner.metadata(path)
+        }
+    }
+
+    fn vault_files(storage: &MemoryStorage) -> Vec<(String, String)> {
+        let mut files: Vec<(String, String)> = ["a.md", "b.md", "c.md", "d.md", "e.md"]
+            .iter()
+            .filter(|p| storage.exists(Path::new(p)))
+            .map(|p| {
+                let content = storage.read_to_string(Path::new(p)).unwrap();
+                (p.to_string(), content)
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn setup() -> (MemoryStorage, Batch) {
+        let storage = MemoryStorage::new();
+        for (path, content) in [("a.md", "A"), ("b.md", "B"), ("c.md", "C")] {
+            storage.write(Path::new(path), content.as_bytes()).unwrap();
+        }
+
+        let mut batch = Batch::new();
+        batch
+            .write("a.md", "A2")
+            .write("d.md", "D")
+            .rename("b.md", "c.md")
+            .delete("a.md")
+            .rename("d.md", "e.md");
+        (storage, batch)
+    }
+
+    #[test]
+    fn test_apply() {
+        let (storage, batch) = setup();
+        let deleted = apply(&storage, &HistorySettings::default(), &batch).unwrap();
+
+        assert_eq!(
+            vault_files(&storage),
+            vec![
+                ("c.md".to_string(), "B".to_string()),
+                ("e.md".to_string(), "D".to_string()),
+            ]
+        );
+        assert_eq!(trash::list(&storage).unwrap(), deleted);
+        assert_eq!(deleted[0].original_path, PathBuf::from("a.md"));
+        assert!(storage
+            .list(Path::new(JOURNAL_DIR))
+            .unwrap_or_default()
+            .is_empty());
+        assert_eq!(
+            recover(&storage, &HistorySettings::default()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_failed_step_rolls_back() {
+        let (storage, mut batch) = setup();
+        let original = vault_files(&storage);
+        batch.rename("missing.md", "f.md");
+
+        assert!(matches!(
+            apply(&storage, &HistorySettings::default(), &batch),
+            Err(ArkeError::FileNotFound(_))
+        ));
+        assert_eq!(vault_files(&storage), original);
+        assert!(trash::list(&storage).unwrap().is_empty());
+        assert!(!storage.exists(Path::new(JOURNAL_FILE)));
+    }
+
+    #[test]
+    fn test_recover_after_crash_at_any_point() {
+        let (storage, batch) = setup();
+        let original = vault_files(&storage);
+        apply(&storage, &HistorySettings::default(), &batch).unwrap();
+        let applied = vault_files(&storage);
+
+        for budget in 0.. {
+            let (storage, batch) = setup();
+            let crashing = Crashing {
+                inner: &storage,
+                budget: Cell::new(budget),
+            };
+            let result = apply(&crashing, &HistorySettings::default(), &batch);
+
+            let recovered = recover(&storage, &HistorySettings::default()).unwrap();
+            let files = vault_files(&storage);
+            if files == applied {
+                assert_ne!(recovered, Some(BatchRecovery::RolledBack));
+            } else {
+                assert_eq!(files, original, "crash after {} changes", budget);
+            }
+            assert!(storage
+                .list(Path::new(JOURNAL_DIR))
+                .unwrap_or_default()
+                .is_empty());
+
+            // Stop once the batch, cleanup included, ran without crashing
+            if crashing.budget.get() > 0 {
+                assert!(result.is_ok());
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_history_is_recorded_on_commit_only() {
+        let history = HistorySettings {
+            enabled: true,
+            ..Default::default()
+        };
+
+        let (storage, mut batch) = setup();
+        batch.rename("missing.md", "f.md");
+        assert!(apply(&storage, &history, &batch).is_err());
+        assert!(history::revisions(&storage, Path::new("a.md"))
+            .unwrap()
+            .is_empty());
+
+        let (storage, batch) = setup();
+        apply(&storage, &history, &batch).unwrap();
+        let revisions = history::revisions(&storage, Path::new("a.md")).unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].size, 1);
+    }
+}
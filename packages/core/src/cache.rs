@@ -0,0 +1,246 @@
+//! Bounded cache of parsed notes
+//!
+//! Entries are evicted least recently used first once their content exceeds
+//! the memory budget. Each entry remembers the file's modification time and
+//! size when it was read; a lookup with a different stamp misses, so edits
+//! made outside the vault are picked up even without a file watcher.
+
+use crate::storage::FileMetadata;
+use crate::vault::Note;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Default memory budget of a vault's note cache, in bytes
+pub const DEFAULT_CACHE_BUDGET: usize = 64 * 1024 * 1024;
+
+/// Identifies a version of a file on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    modified: SystemTime,
+    size: u64,
+}
+
+impl Stamp {
+    /// The stamp of a file, if the backend tracks modification times
+    pub(crate) fn of(metadata: &FileMetadata) -> Option<Self> {
+        Some(Self {
+            modified: metadata.modified?,
+            size: metadata.size,
+        })
+    }
+}
+
+struct Entry {
+    note: Arc<Note>,
+    stamp: Stamp,
+    cost: usize,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct State {
+    budget: usize,
+    used: usize,
+    clock: u64,
+    entries: HashMap<PathBuf, Entry>,
+    /// Paths by last use, oldest first
+    order: BTreeMap<u64, PathBuf>,
+}
+
+impl State {
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.order.remove(&entry.last_used);
+            self.used -= entry.cost;
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.used > self.budget {
+            let Some((_, path)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&path) {
+                self.used -= entry.cost;
+            }
+        }
+    }
+}
+
+/// LRU cache of notes, safe to share between threads
+pub(crate) struct NoteCache {
+    state: Mutex<State>,
+}
+
+impl NoteCache {
+    pub(crate) fn new(budget: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                budget,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// The cached note at `path`, if it was read at `stamp`
+    pub(crate) fn get(&self, path: &Path, stamp: Stamp) -> Option<Arc<Note>> {
+        let mut state = self.state.lock().unwrap();
+        let entry = state.entries.get(path)?;
+        if entry.stamp != stamp {
+            state.remove(path);
+            return None;
+        }
+
+        let previous = entry.last_used;
+        state.clock += 1;
+        let now = state.clock;
+        state.order.remove(&previous);
+        state.order.insert(now, path.to_path_buf());
+        let entry = state.entries.get_mut(path).unwrap();
+        entry.last_used = now;
+        Some(Arc::clone(&entry.note))
+    }
+
+    /// Cache a note read at `stamp`, evicting others to stay within budget
+    ///
+    /// Notes larger than the whole budget aren't cached.
+    pub(crate) fn insert(&self, note: Arc<Note>, stamp: Stamp) {
+        let mut state = self.state.lock().unwrap();
+        let path = note.path.clone();
+        state.remove(&path);
+
+        let cost = note.content.len() + path.as_os_str().len();
+        if cost > state.budget {
+            return;
+        }
+
+        state.clock += 1;
+        let now = state.clock;
+        state.order.insert(now, path.clone());
+        state.entries.insert(
+            path,
+            Entry {
+                note,
+                stamp,
+                cost,
+                last_used: now,
+            },
+        );
+        state.used += cost;
+        state.evict();
+    }
+
+    /// The cached note at `path` and its stamp, current or not, without counting as a use
+    #[cfg(feature = "native")]
+    pub(crate) fn peek(&self, path: &Path) -> Option<(Arc<Note>, Stamp)> {
+        let state = self.state.lock().unwrap();
+        let entry = state.entries.get(path)?;
+        Some((Arc::clone(&entry.note), entry.stamp))
+    }
+
+    /// Drop `path` and, if it's a folder, everything beneath it
+    pub(crate) fn invalidate(&self, path: &Path) {
+        let mut state = self.state.lock().unwrap();
+        let stale: Vec<PathBuf> = state
+            .entries
+            .keys()
+            .filter(|p| p.starts_with(path))
+            .cloned()
+            .collect();
+        for path in stale {
+            state.remove(&path);
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        let budget = state.budget;
+        *state = State {
+            budget,
+            ..Default::default()
+        };
+    }
+
+    /// Change the memory budget, evicting entries if it shrank
+    pub(crate) fn set_budget(&self, budget: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.budget = budget;
+        state.evict();
+    }
+
+    /// Number of cached notes and the bytes they use
+    #[cfg(test)]
+    pub(crate) fn usage(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.entries.len(), state.used)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn note(path: &str, content: &str) -> Arc<Note> {
+        Arc::new(Note {
+            path: PathBuf::from(path),
+            content: content.to_string(),
+            metadata: HashMap::new(),
+            modified: None,
+            hash: String::new(),
+        })
+    }
+
+    fn stamp(secs: u64, size: u64) -> Stamp {
+        Stamp {
+            modified: UNIX_EPOCH + Duration::from_secs(secs),
+            size,
+        }
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        // Each entry costs 10 bytes of content plus 4 of path
+        let cache = NoteCache::new(30);
+        cache.insert(note("a.md", "aaaaaaaaaa"), stamp(1, 10));
+        cache.insert(note("b.md", "bbbbbbbbbb"), stamp(1, 10));
+        assert!(cache.get(Path::new("a.md"), stamp(1, 10)).is_some());
+
+        cache.insert(note("c.md", "cccccccccc"), stamp(1, 10));
+        assert!(cache.get(Path::new("b.md"), stamp(1, 10)).is_none());
+        assert!(cache.get(Path::new("a.md"), stamp(1, 10)).is_some());
+        assert_eq!(cache.usage(), (2, 28));
+
+        cache.set_budget(14);
+        assert_eq!(cache.usage(), (1, 14));
+        assert!(cache.get(Path::new("a.md"), stamp(1, 10)).is_some());
+
+        cache.insert(note("big.md", &"x".repeat(100)), stamp(1, 100));
+        assert!(cache.get(Path::new("big.md"), stamp(1, 100)).is_none());
+    }
+
+    #[test]
+    fn test_stale_entries_miss() {
+        let cache = NoteCache::new(1024);
+        cache.insert(note("a.md", "one"), stamp(1, 3));
+
+        assert!(cache.get(Path::new("a.md"), stamp(2, 3)).is_none());
+        // The stale entry was dropped
+        assert!(cache.get(Path::new("a.md"), stamp(1, 3)).is_none());
+        assert_eq!(cache.usage(), (0, 0));
+    }
+
+    #[test]
+    fn test_invalidate_folder() {
+        let cache = NoteCache::new(1024);
+        cache.insert(note("dir/a.md", "a"), stamp(1, 1));
+        cache.insert(note("dir/sub/b.md", "b"), stamp(1, 1));
+        cache.insert(note("dirt.md", "c"), stamp(1, 1));
+
+        cache.invalidate(Path::new("dir"));
+        assert_eq!(cache.usage().0, 1);
+        assert!(cache.get(Path::new("dirt.md"), stamp(1, 1)).is_some());
+    }
+}
@@ -0,0 +1,326 @@
+//! Persisted vault settings
+//!
+//! Settings live in `.arke/config.json` inside the vault. The file carries a
+//! schema `version`; older files are migrated on load and missing keys fall
+//! back to their defaults.
+
+use crate::error::{ArkeError, Result};
+use crate::storage::Storage;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// Directory holding Arke's per-vault data
+pub const CONFIG_DIR: &str = ".arke";
+
+/// Settings file, relative to the vault root
+pub const CONFIG_FILE: &str = ".arke/config.json";
+
+/// Current settings schema version
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Upgrades a settings object by one schema version
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// `MIGRATIONS[i]` upgrades a file from version `i + 1` to `i + 2`
+const MIGRATIONS: &[Migration] = &[move_daily_note_format];
+
+/// How new links are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStyle {
+    /// `[[Note]]`
+    #[default]
+    Wikilink,
+    /// `[Note](Note.md)`
+    Markdown,
+}
+
+/// Markdown parser options
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct ParserOptions {
+    /// Render single newlines as line breaks
+    pub hard_breaks: bool,
+    /// Parse `$inline$` and `$$display$$` math
+    pub math: bool,
+    /// Parse footnotes
+    pub footnotes: bool,
+    /// Convert straight quotes and dashes to typographic ones
+    pub smart_punctuation: bool,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        Self {
+            hard_breaks: false,
+            math: true,
+            footnotes: true,
+            smart_punctuation: false,
+        }
+    }
+}
+
+/// Local version history of notes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct HistorySettings {
+    /// Snapshot a note's previous content on every write
+    pub enabled: bool,
+    /// Versions kept per note; older ones are dropped first
+    pub max_versions: usize,
+    /// Drop versions older than this many days, or keep them regardless of age if `None`
+    pub max_age_days: Option<u32>,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_versions: 50,
+            max_age_days: Some(90),
+        }
+    }
+}
+
+/// Where notes of one period live and how they're named
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct PeriodSettings {
+    /// Folder the notes are created in, relative to the vault root
+    pub folder: String,
+    /// strftime-style file name format, without `.md`; `/` makes subfolders
+    ///
+    /// Defaults to `%Y-%m-%d`, `%G-W%V`, `%Y-%m` or `%Y` depending on the period.
+    pub format: Option<String>,
+    /// Template new notes are created from, by name within the template folder
+    pub template: Option<String>,
+}
+
+/// Daily, weekly, monthly and yearly notes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct PeriodicSettings {
+    pub daily: PeriodSettings,
+    pub weekly: PeriodSettings,
+    pub monthly: PeriodSettings,
+    pub yearly: PeriodSettings,
+}
+
+/// User-facing settings for a vault
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct VaultSettings {
+    /// Schema version the settings were written with
+    pub version: u32,
+    /// Gitignore-style patterns excluded from the vault, on top of `.arkeignore` files
+    pub ignore: Vec<String>,
+    /// Folder new attachments are saved to, relative to the vault root
+    pub attachment_folder: String,
+    /// Folder note templates are read from, relative to the vault root
+    pub template_folder: String,
+    /// Folder new notes are created in, relative to the vault root; empty for the root
+    pub new_note_folder: String,
+    /// Daily, weekly, monthly and yearly notes
+    pub periodic: PeriodicSettings,
+    /// How new links are written
+    pub link_style: LinkStyle,
+    /// Markdown parser options
+    pub parser: ParserOptions,
+    /// Local version history of notes
+    pub history: HistorySettings,
+}
+
+impl Default for VaultSettings {
+    fn default() -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            ignore: Vec::new(),
+            attachment_folder: "assets".to_string(),
+            template_folder: "templates".to_string(),
+            new_note_folder: String::new(),
+            periodic: PeriodicSettings::default(),
+            link_style: LinkStyle::default(),
+            parser: ParserOptions::default(),
+            history: HistorySettings::default(),
+        }
+    }
+}
+
+impl VaultSettings {
+    /// Load settings from a vault's storage, or the defaults if there is no settings file
+    pub fn load<S: Storage + ?Sized>(storage: &S) -> Result<Self> {
+        match storage.read_to_string(Path::new(CONFIG_FILE)) {
+            Ok(json) => Self::from_json(&json),
+            Err(ArkeError::FileNotFound(_)) => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Save settings to a vault's storage
+    pub fn save<S: Storage + ?Sized>(&self, storage: &S) -> Result<()> {
+        storage.write(Path::new(CONFIG_FILE), self.to_json()?.as_bytes())
+    }
+
+    /// Parse settings JSON, migrating it from older schema versions
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json)?;
+        Self::from_value(value, MIGRATIONS)
+    }
+
+    /// Serialize settings as pretty-printed JSON at the current schema version
+    pub fn to_json(&self) -> Result<String> {
+        let settings = Self {
+            version: SCHEMA_VERSION,
+            ..self.clone()
+        };
+        Ok(serde_json::to_string_pretty(&settings)?)
+    }
+
+    fn from_value(value: Value, migrations: &[Migration]) -> Result<Self> {
+        let Value::Object(mut map) = value else {
+            return Err(ArkeError::Parse(format!(
+                "{} must contain a JSON object",
+                CONFIG_FILE
+            )));
+        };
+
+        let current = migrations.len() as u32 + 1;
+        let version = match map.get("version") {
+            None => 1,
+            Some(v) => v
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .filter(|v| *v >= 1)
+                .ok_or_else(|| invalid_key("version", "expected a positive integer"))?,
+        };
+        if version > current {
+            return Err(invalid_key(
+                "version",
+                &format!(
+                    "version {} is newer than this release supports ({})",
+                    version, current
+                ),
+            ));
+        }
+
+        for migrate in &migrations[version as usize - 1..] {
+            migrate(&mut map)?;
+        }
+        map.insert("version".to_string(), current.into());
+
+        serde_path_to_error::deserialize(Value::Object(map)).map_err(|e| {
+            let key = e.path().to_string();
+            let message = e.into_inner().to_string();
+            invalid_key(&key, &message)
+        })
+    }
+}
+
+/// Version 2 moved `dailyNoteFormat` into `periodic.daily.format`
+fn move_daily_note_format(map: &mut Map<String, Value>) -> Result<()> {
+    let Some(format) = map.remove("dailyNoteFormat") else {
+        return Ok(());
+    };
+    let periodic = map
+        .entry("periodic")
+        .or_insert_with(|| Value::Object(Map::new()));
+    let daily = periodic
+        .as_object_mut()
+        .ok_or_else(|| invalid_key("periodic", "expected an object"))?
+        .entry("daily")
+        .or_insert_with(|| Value::Object(Map::new()));
+    daily
+        .as_object_mut()
+        .ok_or_else(|| invalid_key("periodic.daily", "expected an object"))?
+        .insert("format".to_string(), format);
+    Ok(())
+}
+
+fn invalid_key(key: &str, message: &str) -> ArkeError {
+    ArkeError::Config {
+        key: key.to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_missing_keys_use_defaults() {
+        let settings = VaultSettings::from_json(r#"{"linkStyle": "markdown"}"#).unwrap();
+
+        assert_eq!(settings.link_style, LinkStyle::Markdown);
+        assert_eq!(settings.attachment_folder, "assets");
+        assert_eq!(settings.parser, ParserOptions::default());
+        assert_eq!(settings.version, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_errors_name_the_key() {
+        let err = VaultSettings::from_json(r#"{"parser": {"math": "yes"}}"#).unwrap_err();
+        assert!(matches!(&err, ArkeError::Config { key, .. } if key == "parser.math"));
+
+        let err = VaultSettings::from_json(r#"{"dailyNoteFormt": "%Y"}"#).unwrap_err();
+        assert!(matches!(&err, ArkeError::Config { key, .. } if key == "dailyNoteFormt"));
+
+        let err = VaultSettings::from_json(r#"{"version": 99}"#).unwrap_err();
+        assert!(matches!(&err, ArkeError::Config { key, .. } if key == "version"));
+    }
+
+    #[test]
+    fn test_migrations_run_in_order() {
+        fn rename_folder(map: &mut Map<String, Value>) -> Result<()> {
+            if let Some(folder) = map.remove("attachments") {
+                map.insert("attachmentFolder".to_string(), folder);
+            }
+            Ok(())
+        }
+        fn drop_legacy(map: &mut Map<String, Value>) -> Result<()> {
+            map.remove("legacy");
+            Ok(())
+        }
+
+        let migrations: &[Migration] = &[rename_folder, drop_legacy];
+        let old = serde_json::json!({"version": 1, "attachments": "files", "legacy": true});
+        let settings = VaultSettings::from_value(old, migrations).unwrap();
+        assert_eq!(settings.attachment_folder, "files");
+        assert_eq!(settings.version, 3);
+
+        let newer = serde_json::json!({"version": 2, "legacy": true});
+        assert!(VaultSettings::from_value(newer, migrations).is_ok());
+    }
+
+    #[test]
+    fn test_daily_note_format_migrates() {
+        let settings =
+            VaultSettings::from_json(r#"{"version": 1, "dailyNoteFormat": "%Y/%m/%d"}"#).unwrap();
+        assert_eq!(settings.periodic.daily.format.as_deref(), Some("%Y/%m/%d"));
+        assert_eq!(settings.version, 2);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let storage = MemoryStorage::new();
+        assert_eq!(
+            VaultSettings::load(&storage).unwrap(),
+            VaultSettings::default()
+        );
+
+        let mut settings = VaultSettings {
+            ignore: vec!["templates/".to_string()],
+            ..Default::default()
+        };
+        settings.periodic.daily.format = Some("%Y/%m/%d".to_string());
+        settings.save(&storage).unwrap();
+
+        assert!(storage
+            .read_to_string(Path::new(CONFIG_FILE))
+            .unwrap()
+            .contains("\"periodic\""));
+        assert_eq!(VaultSettings::load(&storage).unwrap(), settings);
+    }
+}
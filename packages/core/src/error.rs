@@ -24,10 +24,46 @@ pub enum ArkeError {
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
+    #[error("Invalid config key `{key}`: {message}")]
+    Config { key: String, message: String },
+
     #[cfg(feature = "native")]
     #[error("Index error: {0}")]
     Index(String),
 
+    #[error("Operation cancelled")]
+    Cancelled,
+
+    /// The note changed on disk since it was read; carries both versions
+    #[error("Conflict: {path} was changed since it was read")]
+    Conflict {
+        path: String,
+        /// The content that was being written
+        ours: String,
+        /// The content now in storage, or `None` if the note was deleted
+        theirs: Option<String>,
+    },
+
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
+
+impl ArkeError {
+    /// Stable machine-readable code for this error kind
+    pub fn code(&self) -> &'static str {
+        match self {
+            ArkeError::Vault(_) => "VAULT",
+            ArkeError::FileNotFound(_) => "FILE_NOT_FOUND",
+            ArkeError::Io(_) => "IO",
+            ArkeError::Parse(_) => "PARSE",
+            ArkeError::InvalidWikilink(_) => "INVALID_WIKILINK",
+            ArkeError::Serialization(_) => "SERIALIZATION",
+            ArkeError::Config { .. } => "CONFIG",
+            #[cfg(feature = "native")]
+            ArkeError::Index(_) => "INDEX",
+            ArkeError::Cancelled => "CANCELLED",
+            ArkeError::Conflict { .. } => "CONFLICT",
+            ArkeError::Unknown(_) => "UNKNOWN",
+        }
+    }
+}
@@ -0,0 +1,427 @@
+//! Vault change events
+//!
+//! A vault emits a [`VaultEvent`] for every change to its notes, whether made
+//! through its API or picked up from disk by `Vault::handle_watch_event`.
+//! Search, the link graph, plugins and UIs subscribe to the vault's
+//! [`EventBus`] instead of polling. Events serialize to JSON tagged by `kind`,
+//! so bindings can forward them unchanged.
+
+use crate::batch::{Batch, BatchOp};
+use crate::error::{ArkeError, Result};
+use crate::links::LinkExtractor;
+use crate::merge::{frontmatter_entries, split_frontmatter};
+use crate::storage::Storage;
+use crate::vault::{files_under, is_markdown};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, TryLockError};
+
+/// A change to a vault
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum VaultEvent {
+    NoteCreated {
+        path: PathBuf,
+    },
+    NoteUpdated {
+        path: PathBuf,
+    },
+    NoteRenamed {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    NoteDeleted {
+        path: PathBuf,
+    },
+    /// Top-level frontmatter keys were added, removed or changed
+    FrontmatterChanged {
+        path: PathBuf,
+        /// The affected keys, sorted
+        keys: Vec<String>,
+    },
+    /// The set of wikilink targets in a note changed
+    LinksChanged {
+        path: PathBuf,
+        /// Targets the note now links to and didn't before, sorted
+        added: Vec<String>,
+        /// Targets the note no longer links to, sorted
+        removed: Vec<String>,
+    },
+    /// A search index was rebuilt or brought up to date
+    IndexUpdated {
+        /// Number of notes in the index
+        notes: usize,
+    },
+}
+
+/// Identifies a subscription, for [`EventBus::unsubscribe`]
+pub type SubscriptionId = u64;
+
+type Listener = Arc<dyn Fn(&VaultEvent) + Send + Sync>;
+
+#[derive(Default)]
+struct Listeners {
+    next_id: SubscriptionId,
+    listeners: Vec<(SubscriptionId, Listener)>,
+}
+
+/// Events held back until the emitter has released its locks
+#[derive(Default)]
+struct Deferred {
+    enabled: AtomicBool,
+    pending: Mutex<VecDeque<VaultEvent>>,
+    /// Held by whichever thread is delivering `pending`, so events keep their order
+    delivering: Mutex<()>,
+}
+
+/// Delivers vault events to subscribers
+///
+/// Cloning gives another handle to the same bus.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    inner: Arc<Mutex<Listeners>>,
+    deferred: Arc<Deferred>,
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let subscribers = self.inner.lock().unwrap().listeners.len();
+        f.debug_struct("EventBus")
+            .field("subscribers", &subscribers)
+            .finish()
+    }
+}
+
+impl EventBus {
+    /// Create a bus with no subscribers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call `listener` with every event from now on
+    pub fn subscribe<F>(&self, listener: F) -> SubscriptionId
+    where
+        F: Fn(&VaultEvent) + Send + Sync + 'static,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_id += 1;
+        let id = inner.next_id;
+        inner.listeners.push((id, Arc::new(listener)));
+        id
+    }
+
+    /// Stop a subscription; returns whether it existed
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.listeners.len();
+        inner.listeners.retain(|(i, _)| *i != id);
+        inner.listeners.len() != before
+    }
+
+    /// Whether anyone is listening, so callers can skip working out events nobody gets
+    pub fn has_subscribers(&self) -> bool {
+        !self.inner.lock().unwrap().listeners.is_empty()
+    }
+
+    /// Deliver an event to every subscriber, in subscription order
+    ///
+    /// Listeners run on the emitting thread and may subscribe, unsubscribe
+    /// or emit themselves. On a deferred bus the event is queued for
+    /// [`EventBus::flush`] instead.
+    pub fn emit(&self, event: &VaultEvent) {
+        if self.deferred.enabled.load(Ordering::Acquire) {
+            self.deferred
+                .pending
+                .lock()
+                .unwrap()
+                .push_back(event.clone());
+        } else {
+            self.deliver(event);
+        }
+    }
+
+    /// Queue events from now on until [`EventBus::flush`] is called
+    ///
+    /// [`SharedVault`](crate::SharedVault) uses this so listeners run after
+    /// its lock is released, and can use the vault themselves.
+    pub(crate) fn defer(&self) {
+        self.deferred.enabled.store(true, Ordering::Release);
+    }
+
+    /// Deliver queued events, unless another call is already delivering them
+    ///
+    /// That call picks up events queued meanwhile, including those emitted
+    /// by its own listeners, so each event is delivered once and in order.
+    pub(crate) fn flush(&self) {
+        let deferred = &self.deferred;
+        loop {
+            let delivering = match deferred.delivering.try_lock() {
+                Ok(guard) => guard,
+                // A listener panicked mid-delivery; the queue itself is fine
+                Err(TryLockError::Poisoned(e)) => e.into_inner(),
+                Err(TryLockError::WouldBlock) => return,
+            };
+            loop {
+                let next = deferred.pending.lock().unwrap().pop_front();
+                match next {
+                    Some(event) => self.deliver(&event),
+                    None => break,
+                }
+            }
+            drop(delivering);
+            // Events queued after the last check but before the unlock would otherwise wait
+            if deferred.pending.lock().unwrap().is_empty() {
+                return;
+            }
+        }
+    }
+
+    fn deliver(&self, event: &VaultEvent) {
+        let listeners: Vec<Listener> = self
+            .inner
+            .lock()
+            .unwrap()
+            .listeners
+            .iter()
+            .map(|(_, l)| Arc::clone(l))
+            .collect();
+        for listener in listeners {
+            listener(event);
+        }
+    }
+}
+
+/// Events for a note whose content went from `old` (`None` if it's new) to `new`
+pub(crate) fn write_events(path: &Path, old: Option<&str>, new: &str) -> Vec<VaultEvent> {
+    if old == Some(new) {
+        return Vec::new();
+    }
+
+    let path = path.to_path_buf();
+    let mut events = vec![match old {
+        Some(_) => VaultEvent::NoteUpdated { path: path.clone() },
+        None => VaultEvent::NoteCreated { path: path.clone() },
+    }];
+    let old = old.unwrap_or_default();
+
+    let keys = changed_keys(old, new);
+    if !keys.is_empty() {
+        events.push(VaultEvent::FrontmatterChanged {
+            path: path.clone(),
+            keys,
+        });
+    }
+
+    let (before, after) = (link_targets(old), link_targets(new));
+    if before != after {
+        events.push(VaultEvent::LinksChanged {
+            path,
+            added: after.difference(&before).cloned().collect(),
+            removed: before.difference(&after).cloned().collect(),
+        });
+    }
+    events
+}
+
+/// Events a batch will cause, worked out from storage before it's applied
+pub(crate) fn batch_events<S: Storage + ?Sized>(
+    storage: &S,
+    batch: &Batch,
+) -> Result<Vec<VaultEvent>> {
+    // Note contents as earlier operations in the batch leave them
+    let mut contents: HashMap<PathBuf, Option<String>> = HashMap::new();
+    let current = |contents: &mut HashMap<PathBuf, Option<String>>, path: &Path| {
+        if let Some(content) = contents.get(path) {
+            return Ok(content.clone());
+        }
+        match storage.read_to_string(path) {
+            Ok(content) => Ok(Some(content)),
+            Err(ArkeError::FileNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    };
+
+    let mut events = Vec::new();
+    for op in &batch.ops {
+        match op {
+            BatchOp::Write { path, content } => {
+                if is_markdown(path) {
+                    let old = current(&mut contents, path)?;
+                    events.extend(write_events(path, old.as_deref(), content));
+                    contents.insert(path.clone(), Some(content.clone()));
+                }
+            }
+            BatchOp::Delete { path } => {
+                if is_markdown(path) {
+                    events.push(VaultEvent::NoteDeleted { path: path.clone() });
+                    contents.insert(path.clone(), None);
+                }
+            }
+            BatchOp::Rename { from, to } => {
+                let moves = if storage.metadata(from).is_ok_and(|m| m.is_dir) {
+                    let mut files = Vec::new();
+                    files_under(storage, from, &mut files, &mut Vec::new())?;
+                    files.sort();
+                    files
+                        .into_iter()
+                        .map(|file| {
+                            let new = to.join(file.strip_prefix(from).unwrap());
+                            (file, new)
+                        })
+                        .collect()
+                } else {
+                    vec![(from.clone(), to.clone())]
+                };
+
+                for (from, to) in moves {
+                    match (is_markdown(&from), is_markdown(&to)) {
+                        (true, true) => events.push(VaultEvent::NoteRenamed {
+                            from: from.clone(),
+                            to: to.clone(),
+                        }),
+                        (true, false) => {
+                            events.push(VaultEvent::NoteDeleted { path: from.clone() })
+                        }
+                        (false, true) => match storage.read_to_string(&from) {
+                            Ok(content) => events.extend(write_events(&to, None, &content)),
+                            Err(_) => events.push(VaultEvent::NoteCreated { path: to.clone() }),
+                        },
+                        (false, false) => continue,
+                    }
+                    if is_markdown(&from) {
+                        let content = current(&mut contents, &from)?;
+                        contents.insert(from, None);
+                        contents.insert(to, content);
+                    }
+                }
+            }
+        }
+    }
+    Ok(events)
+}
+
+/// Top-level frontmatter keys that differ between two versions of a note
+fn changed_keys(old: &str, new: &str) -> Vec<String> {
+    let entries = |text| {
+        split_frontmatter(text)
+            .0
+            .map(frontmatter_entries)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(key, _)| !key.is_empty())
+            .collect::<HashMap<String, String>>()
+    };
+    let (old, new) = (entries(old), entries(new));
+
+    let keys: BTreeSet<&String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .collect();
+    keys.into_iter().cloned().collect()
+}
+
+fn link_targets(content: &str) -> BTreeSet<String> {
+    static EXTRACTOR: OnceLock<LinkExtractor> = OnceLock::new();
+    EXTRACTOR
+        .get_or_init(LinkExtractor::new)
+        .extract(content)
+        .into_iter()
+        .map(|link| link.target)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_bus_subscriptions() {
+        let bus = EventBus::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        let id = bus.subscribe(move |e| sink.lock().unwrap().push(e.clone()));
+        assert!(bus.has_subscribers());
+
+        let event = VaultEvent::IndexUpdated { notes: 3 };
+        bus.clone().emit(&event);
+        assert!(bus.unsubscribe(id));
+        assert!(!bus.unsubscribe(id));
+        bus.emit(&event);
+
+        assert_eq!(*seen.lock().unwrap(), vec![event]);
+    }
+
+    #[test]
+    fn test_write_events() {
+        let path = Path::new("a.md");
+        let old = "---\ntitle: A\ntags: [x]\n---\nSee [[b]] and [[c]]";
+        let new = "---\ntitle: B\ntags: [x]\nnew: 1\n---\nSee [[c]] and [[d]]";
+
+        assert_eq!(
+            write_events(path, Some(old), new),
+            vec![
+                VaultEvent::NoteUpdated { path: path.into() },
+                VaultEvent::FrontmatterChanged {
+                    path: path.into(),
+                    keys: vec!["new".into(), "title".into()],
+                },
+                VaultEvent::LinksChanged {
+                    path: path.into(),
+                    added: vec!["d".into()],
+                    removed: vec!["b".into()],
+                },
+            ]
+        );
+        assert!(write_events(path, Some(new), new).is_empty());
+        assert_eq!(
+            write_events(path, None, "plain"),
+            vec![VaultEvent::NoteCreated { path: path.into() }]
+        );
+
+        let json = serde_json::to_string(&VaultEvent::NoteRenamed {
+            from: "a.md".into(),
+            to: "b.md".into(),
+        })
+        .unwrap();
+        assert_eq!(json, r#"{"kind":"noteRenamed","from":"a.md","to":"b.md"}"#);
+    }
+
+    #[test]
+    fn test_batch_events() {
+        let storage = MemoryStorage::new();
+        storage.write(Path::new("dir/a.md"), b"[[x]]").unwrap();
+        storage.write(Path::new("dir/img.png"), b"png").unwrap();
+        storage.write(Path::new("b.md"), b"b").unwrap();
+
+        let mut batch = Batch::new();
+        batch
+            .rename("dir", "moved")
+            .write("moved/a.md", "[[x]] [[y]]")
+            .delete("b.md");
+        assert_eq!(
+            batch_events(&storage, &batch).unwrap(),
+            vec![
+                VaultEvent::NoteRenamed {
+                    from: "dir/a.md".into(),
+                    to: "moved/a.md".into(),
+                },
+                VaultEvent::NoteUpdated {
+                    path: "moved/a.md".into(),
+                },
+                VaultEvent::LinksChanged {
+                    path: "moved/a.md".into(),
+                    added: vec!["y".into()],
+                    removed: vec![],
+                },
+                VaultEvent::NoteDeleted {
+                    path: "b.md".into(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,296 @@
+//! Folder operations and the link rewriting they need
+//!
+//! Moving files breaks links that spell out a path: wikilinks such as
+//! `[[projects/plan]]` and markdown links such as `[plan](../projects/plan.md)`.
+//! Links by bare name (`[[plan]]`) keep resolving and are left alone.
+
+use crate::attachments::{is_external, normalize, percent_decode, strip_fragment};
+use crate::trash::TrashEntry;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+/// A file moved by a folder operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MovedFile {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// What a folder operation changed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderReport {
+    /// Files that moved, sorted by old path
+    pub moved: Vec<MovedFile>,
+    /// Notes whose links were rewritten, by their new path, sorted
+    pub updated: Vec<PathBuf>,
+    /// Trash entries of deleted files
+    pub trashed: Vec<TrashEntry>,
+}
+
+/// The vault's files before a change, for resolving the links it affects
+pub(crate) struct FileLookup {
+    paths: HashSet<PathBuf>,
+    by_name: HashMap<String, Vec<PathBuf>>,
+}
+
+impl FileLookup {
+    pub(crate) fn new(files: &[PathBuf]) -> Self {
+        let mut by_name: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for file in files {
+            if let Some(name) = file.file_name().and_then(|n| n.to_str()) {
+                by_name
+                    .entry(name.to_lowercase())
+                    .or_default()
+                    .push(file.clone());
+            }
+        }
+        // Prefer the shallowest match, as wikilink resolution does
+        for paths in by_name.values_mut() {
+            paths.sort_by_key(|p| (p.components().count(), p.clone()));
+        }
+
+        Self {
+            paths: files.iter().cloned().collect(),
+            by_name,
+        }
+    }
+
+    /// Resolve a path-qualified wikilink target, with or without `.md`
+    fn resolve_wikilink(&self, target: &str) -> Option<PathBuf> {
+        let target = target.trim_start_matches('/');
+        [target.to_string(), format!("{}.md", target)]
+            .iter()
+            .find_map(|candidate| {
+                let candidate = normalize(Path::new(candidate))?;
+                if self.paths.contains(&candidate) {
+                    return Some(candidate);
+                }
+                let name = candidate.file_name()?.to_str()?.to_lowercase();
+                self.by_name
+                    .get(&name)?
+                    .iter()
+                    .find(|p| p.ends_with(&candidate))
+                    .cloned()
+            })
+    }
+
+    /// Resolve a markdown link target from a note in `dir`
+    ///
+    /// Returns the file and whether the target was written relative to the vault root.
+    pub(crate) fn resolve_markdown(&self, target: &str, dir: &Path) -> Option<(PathBuf, bool)> {
+        if !target.starts_with('/') {
+            if let Some(path) = normalize(&dir.join(target)).filter(|p| self.paths.contains(p)) {
+                return Some((path, false));
+            }
+        }
+        normalize(Path::new(target.trim_start_matches('/')))
+            .filter(|p| self.paths.contains(p))
+            .map(|p| (p, true))
+    }
+}
+
+/// Rewrites links after files move
+pub(crate) struct LinkRewriter {
+    wikilink_regex: Regex,
+    markdown_regex: Regex,
+}
+
+impl LinkRewriter {
+    pub(crate) fn new() -> Self {
+        // The target of [[target]], ![[target#heading|alias]] and the like
+        let wikilink_regex = Regex::new(r"\[\[([^\]|#]+)").expect("Invalid wikilink regex");
+        // The target of [text](target) and ![alt](<target> "title")
+        let markdown_regex = Regex::new(r#"\[[^\]]*\]\(\s*(<[^>]+>|[^)\s]+)(?:\s+"[^"]*")?\s*\)"#)
+            .expect("Invalid markdown link regex");
+
+        Self {
+            wikilink_regex,
+            markdown_regex,
+        }
+    }
+
+    /// Rewrite the links in a note that moved from `old_note` to `new_note`
+    ///
+    /// `moved` maps an old file path to its new one, or `None` if the file
+    /// stayed put. Returns `None` if no link changed.
+    pub(crate) fn rewrite(
+        &self,
+        content: &str,
+        old_note: &Path,
+        new_note: &Path,
+        files: &FileLookup,
+        moved: impl Fn(&Path) -> Option<PathBuf>,
+    ) -> Option<String> {
+        let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+
+        for cap in self.wikilink_regex.captures_iter(content) {
+            let m = cap.get(1).unwrap();
+            let target = m.as_str().trim();
+            if !target.contains('/') {
+                continue;
+            }
+            let Some(new) = files.resolve_wikilink(target).and_then(|old| moved(&old)) else {
+                continue;
+            };
+
+            let mut text = to_slash(&new);
+            if !target.to_lowercase().ends_with(".md") {
+                if let Some(stem) = text.strip_suffix(".md") {
+                    text = stem.to_string();
+                }
+            }
+            edits.push((m.range(), text));
+        }
+
+        let old_dir = old_note.parent().unwrap_or(Path::new(""));
+        let new_dir = new_note.parent().unwrap_or(Path::new(""));
+        for cap in self.markdown_regex.captures_iter(content) {
+            let m = cap.get(1).unwrap();
+            let raw = m.as_str();
+            let wrapped = raw.starts_with('<');
+            let inner = if wrapped { &raw[1..raw.len() - 1] } else { raw };
+            if is_external(inner) {
+                continue;
+            }
+
+            let path_part = strip_fragment(inner);
+            let suffix = &inner[path_part.len()..];
+            let decoded = percent_decode(path_part);
+            if decoded.is_empty() {
+                continue;
+            }
+            let Some((old_target, from_root)) = files.resolve_markdown(&decoded, old_dir) else {
+                continue;
+            };
+
+            let new_target = moved(&old_target).unwrap_or_else(|| old_target.clone());
+            let mut text = if decoded.starts_with('/') {
+                format!("/{}", to_slash(&new_target))
+            } else if from_root {
+                to_slash(&new_target)
+            } else {
+                relative_path(new_dir, &new_target)
+            };
+            if path_part.contains('%') {
+                text = text.replace(' ', "%20");
+            }
+            text.push_str(suffix);
+            if wrapped {
+                text = format!("<{}>", text);
+            }
+
+            if text != raw {
+                edits.push((m.range(), text));
+            }
+        }
+
+        if edits.is_empty() {
+            return None;
+        }
+        edits.sort_by_key(|(range, _)| range.start);
+
+        let mut out = String::with_capacity(content.len());
+        let mut last = 0;
+        for (range, text) in edits {
+            if range.start < last {
+                continue;
+            }
+            out.push_str(&content[last..range.start]);
+            out.push_str(&text);
+            last = range.end;
+        }
+        out.push_str(&content[last..]);
+        Some(out)
+    }
+}
+
+/// Path of `target` relative to the directory `dir`, with `/` separators
+fn relative_path(dir: &Path, target: &Path) -> String {
+    let dir: Vec<Component> = dir.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = dir.iter().zip(&target).take_while(|(a, b)| a == b).count();
+
+    let mut parts: Vec<String> = vec!["..".to_string(); dir.len() - common];
+    parts.extend(
+        target[common..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+    );
+    parts.join("/")
+}
+
+fn to_slash(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(content: &str, old_note: &str, new_note: &str) -> Option<String> {
+        let files = FileLookup::new(&[
+            PathBuf::from("projects/plan.md"),
+            PathBuf::from("projects/img/chart one.png"),
+            PathBuf::from("notes/today.md"),
+            PathBuf::from("other.md"),
+        ]);
+        let moved = |p: &Path| {
+            p.strip_prefix("projects")
+                .ok()
+                .map(|rest| Path::new("archive/2024").join(rest))
+        };
+        LinkRewriter::new().rewrite(
+            content,
+            Path::new(old_note),
+            Path::new(new_note),
+            &files,
+            moved,
+        )
+    }
+
+    #[test]
+    fn test_rewrite_links_to_moved_files() {
+        let content = "[[projects/plan#Goals|the plan]] [[plan]] ![[projects/img/chart one.png]]\n\
+                       [plan](../projects/plan.md#goals) ![](<../projects/img/chart one.png>)\n\
+                       ![](../projects/img/chart%20one.png) [web](https://projects/plan.md) [[other]]";
+        assert_eq!(
+            rewrite(content, "notes/today.md", "notes/today.md").unwrap(),
+            "[[archive/2024/plan#Goals|the plan]] [[plan]] ![[archive/2024/img/chart one.png]]\n\
+             [plan](../archive/2024/plan.md#goals) ![](<../archive/2024/img/chart one.png>)\n\
+             ![](../archive/2024/img/chart%20one.png) [web](https://projects/plan.md) [[other]]"
+        );
+        assert_eq!(
+            rewrite("[[plan]] [[other]]", "notes/today.md", "notes/today.md"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rewrite_links_in_moved_note() {
+        // Relative links out of a note that moved deeper need more `..`
+        assert_eq!(
+            rewrite(
+                "[today](../notes/today.md) [img](img/chart%20one.png) [root](other.md)",
+                "projects/plan.md",
+                "archive/2024/plan.md"
+            )
+            .unwrap(),
+            "[today](../../notes/today.md) [img](img/chart%20one.png) [root](other.md)"
+        );
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("a/b"), Path::new("a/c/x.md")),
+            "../c/x.md"
+        );
+        assert_eq!(relative_path(Path::new(""), Path::new("x.md")), "x.md");
+        assert_eq!(relative_path(Path::new("a"), Path::new("a/x.md")), "x.md");
+    }
+}
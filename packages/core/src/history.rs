@@ -0,0 +1,308 @@
+//! Local version history of notes
+//!
+//! When enabled, each write first snapshots the note's previous content into
+//! `.arke/history/<note path>/`. The directory holds a `log.json` listing the
+//! versions and one lz4-compressed object per distinct content, named by its
+//! hash, so versions with identical content share storage.
+
+use crate::config::HistorySettings;
+use crate::error::{ArkeError, Result};
+use crate::storage::{now_secs, Storage};
+use crate::vault::content_hash;
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// History directory, relative to the vault root
+pub const HISTORY_DIR: &str = ".arke/history";
+
+const LOG_FILE: &str = "log.json";
+
+/// A recorded version of a note
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Revision {
+    /// Identifies the version within the note's history; increases with each snapshot
+    pub id: u64,
+    /// Hash of the content, see [`content_hash`]
+    pub hash: String,
+    /// Snapshot time in seconds since the Unix epoch, if the platform has a clock
+    pub saved_at: Option<u64>,
+    /// Content length in bytes
+    pub size: u64,
+}
+
+/// Kind of a line in a diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// A line of a diff between two versions, without its line ending
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffLine {
+    pub kind: DiffKind,
+    pub text: String,
+}
+
+/// A note's version log, oldest first
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Log {
+    next_id: u64,
+    revisions: Vec<Revision>,
+}
+
+/// Snapshot the current content of `path` before it is replaced by `new_content`
+///
+/// Does nothing if history is disabled, the note doesn't exist yet, or its
+/// content is unchanged. Retention limits are applied afterwards.
+pub(crate) fn record<S: Storage + ?Sized>(
+    storage: &S,
+    settings: &HistorySettings,
+    path: &Path,
+    new_content: &[u8],
+) -> Result<()> {
+    if !settings.enabled {
+        return Ok(());
+    }
+    let previous = match storage.read(path) {
+        Ok(previous) => previous,
+        Err(ArkeError::FileNotFound(_)) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    record_previous(storage, settings, path, &previous, new_content)
+}
+
+/// Snapshot `previous`, the content `path` had before `new_content` replaced it
+///
+/// Skipped if `previous` is already the newest version, so repeating a call
+/// records it once.
+pub(crate) fn record_previous<S: Storage + ?Sized>(
+    storage: &S,
+    settings: &HistorySettings,
+    path: &Path,
+    previous: &[u8],
+    new_content: &[u8],
+) -> Result<()> {
+    if !settings.enabled || previous == new_content {
+        return Ok(());
+    }
+
+    let dir = note_dir(path);
+    let mut log = read_log(storage, &dir)?;
+    let hash = content_hash(previous);
+    if log.revisions.last().is_some_and(|r| r.hash == hash) {
+        return Ok(());
+    }
+
+    let object = dir.join(&hash);
+    if !storage.exists(&object) {
+        storage.write(&object, &lz4_flex::compress_prepend_size(previous))?;
+    }
+
+    log.next_id += 1;
+    log.revisions.push(Revision {
+        id: log.next_id,
+        hash,
+        saved_at: now_secs(),
+        size: previous.len() as u64,
+    });
+    prune(storage, settings, &dir, &mut log)?;
+    storage.write(&dir.join(LOG_FILE), &serde_json::to_vec_pretty(&log)?)
+}
+
+/// Recorded versions of a note, newest first
+pub(crate) fn revisions<S: Storage + ?Sized>(storage: &S, path: &Path) -> Result<Vec<Revision>> {
+    let mut revisions = read_log(storage, &note_dir(path))?.revisions;
+    revisions.reverse();
+    Ok(revisions)
+}
+
+/// Content of a recorded version
+pub(crate) fn read_revision<S: Storage + ?Sized>(
+    storage: &S,
+    path: &Path,
+    id: u64,
+) -> Result<String> {
+    let dir = note_dir(path);
+    let revision = read_log(storage, &dir)?
+        .revisions
+        .into_iter()
+        .find(|r| r.id == id)
+        .ok_or_else(|| ArkeError::FileNotFound(format!("{} (version {})", path.display(), id)))?;
+
+    let compressed = storage.read(&dir.join(&revision.hash))?;
+    let bytes = lz4_flex::decompress_size_prepended(&compressed).map_err(|e| {
+        ArkeError::Parse(format!(
+            "Corrupt history for {} (version {}): {}",
+            path.display(),
+            id,
+            e
+        ))
+    })?;
+    String::from_utf8(bytes).map_err(|e| {
+        ArkeError::Parse(format!(
+            "History for {} is not valid UTF-8: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+/// Move a note's history along with the note
+pub(crate) fn rename<S: Storage + ?Sized>(storage: &S, from: &Path, to: &Path) -> Result<()> {
+    let from = note_dir(from);
+    if storage.exists(&from) {
+        storage.rename(&from, &note_dir(to))?;
+    }
+    Ok(())
+}
+
+/// Line diff from `old` to `new`
+pub fn diff(old: &str, new: &str) -> Vec<DiffLine> {
+    TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            kind: match change.tag() {
+                ChangeTag::Equal => DiffKind::Equal,
+                ChangeTag::Insert => DiffKind::Insert,
+                ChangeTag::Delete => DiffKind::Delete,
+            },
+            text: change
+                .value()
+                .trim_end_matches('\n')
+                .trim_end_matches('\r')
+                .to_string(),
+        })
+        .collect()
+}
+
+/// Drop versions beyond the retention limits, then objects no version uses
+fn prune<S: Storage + ?Sized>(
+    storage: &S,
+    settings: &HistorySettings,
+    dir: &Path,
+    log: &mut Log,
+) -> Result<()> {
+    let excess = log.revisions.len().saturating_sub(settings.max_versions);
+    log.revisions.drain(..excess);
+
+    if let (Some(days), Some(now)) = (settings.max_age_days, now_secs()) {
+        let cutoff = now.saturating_sub(days as u64 * 86_400);
+        log.revisions
+            .retain(|r| r.saved_at.is_none_or(|t| t >= cutoff));
+    }
+
+    let used: HashSet<&str> = log.revisions.iter().map(|r| r.hash.as_str()).collect();
+    for entry in storage.list(dir)? {
+        let name = entry.path.file_name().and_then(|n| n.to_str());
+        if !entry.is_dir && name.is_some_and(|n| n != LOG_FILE && !used.contains(n)) {
+            storage.remove(&entry.path)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_log<S: Storage + ?Sized>(storage: &S, dir: &Path) -> Result<Log> {
+    match storage.read_to_string(&dir.join(LOG_FILE)) {
+        Ok(json) => Ok(serde_json::from_str(&json)?),
+        Err(ArkeError::FileNotFound(_)) => Ok(Log::default()),
+        Err(e) => Err(e),
+    }
+}
+
+fn note_dir(path: &Path) -> PathBuf {
+    Path::new(HISTORY_DIR).join(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn settings(max_versions: usize) -> HistorySettings {
+        HistorySettings {
+            enabled: true,
+            max_versions,
+            max_age_days: None,
+        }
+    }
+
+    fn write(storage: &MemoryStorage, settings: &HistorySettings, content: &str) {
+        let path = Path::new("a.md");
+        record(storage, settings, path, content.as_bytes()).unwrap();
+        storage.write(path, content.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_record_and_read() {
+        let storage = MemoryStorage::new();
+        let settings = settings(10);
+        for content in ["one", "two", "two", "one", "three"] {
+            write(&storage, &settings, content);
+        }
+
+        // The unchanged write isn't recorded, and "one" is stored once
+        let revisions = revisions(&storage, Path::new("a.md")).unwrap();
+        let ids: Vec<u64> = revisions.iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+        assert_eq!(revisions[0].hash, revisions[2].hash);
+        assert_eq!(storage.list(&note_dir(Path::new("a.md"))).unwrap().len(), 3);
+
+        assert_eq!(
+            read_revision(&storage, Path::new("a.md"), 2).unwrap(),
+            "two"
+        );
+        assert!(matches!(
+            read_revision(&storage, Path::new("a.md"), 9),
+            Err(ArkeError::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_retention() {
+        let storage = MemoryStorage::new();
+        let settings = settings(2);
+        for content in ["one", "two", "three", "four"] {
+            write(&storage, &settings, content);
+        }
+
+        let revisions = revisions(&storage, Path::new("a.md")).unwrap();
+        let ids: Vec<u64> = revisions.iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![3, 2]);
+        // Objects for pruned versions are removed
+        assert!(!storage.exists(&note_dir(Path::new("a.md")).join(content_hash(b"one"))));
+
+        let disabled = HistorySettings {
+            enabled: false,
+            ..settings
+        };
+        write(&storage, &disabled, "five");
+        assert_eq!(
+            super::revisions(&storage, Path::new("a.md")).unwrap().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let lines = diff("a\nb\nc\n", "a\nc\nd\n");
+        let kinds: Vec<(DiffKind, &str)> =
+            lines.iter().map(|l| (l.kind, l.text.as_str())).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (DiffKind::Equal, "a"),
+                (DiffKind::Delete, "b"),
+                (DiffKind::Equal, "c"),
+                (DiffKind::Insert, "d"),
+            ]
+        );
+    }
+}
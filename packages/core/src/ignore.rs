@@ -0,0 +1,326 @@
+//! Gitignore-style rules for excluding files from the vault
+//!
+//! Patterns come from [`VaultConfig::ignore`](crate::VaultConfig) and from
+//! `.arkeignore` files, which apply to their own directory and everything
+//! beneath it. As in git, the last matching pattern wins, `!` re-includes a
+//! path, and nothing inside an ignored directory can be re-included.
+
+use crate::error::Result;
+use crate::storage::{DirEntry, Storage};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+/// Name of per-directory ignore files
+pub const IGNORE_FILE: &str = ".arkeignore";
+
+/// Built-in patterns, applied before any others so they can be negated
+const DEFAULT_PATTERNS: &[&str] = &[".*", "node_modules/"];
+
+/// A single compiled pattern
+#[derive(Debug, Clone)]
+struct Rule {
+    /// Directory the pattern is relative to
+    base: PathBuf,
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// An ordered set of ignore patterns
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    /// Create an empty rule set that ignores nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a rule set with the built-in patterns (hidden files and directories, and `node_modules`)
+    pub fn with_defaults() -> Self {
+        let mut rules = Self::new();
+        for pattern in DEFAULT_PATTERNS {
+            rules.add_pattern(Path::new(""), pattern);
+        }
+        rules
+    }
+
+    /// Add one pattern, relative to the `base` directory
+    ///
+    /// Blank lines, comments, and invalid patterns are skipped.
+    pub fn add_pattern(&mut self, base: &Path, pattern: &str) {
+        if let Some(rule) = compile(base, pattern) {
+            self.rules.push(rule);
+        }
+    }
+
+    /// Add every pattern in an ignore file's contents
+    pub fn add_patterns(&mut self, base: &Path, contents: &str) {
+        for line in contents.lines() {
+            self.add_pattern(base, line);
+        }
+    }
+
+    /// Whether `path` itself matches, without checking its parent directories
+    pub fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        for rule in self.rules.iter().rev() {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(&rule.base) else {
+                continue;
+            };
+            if rule.regex.is_match(&to_slash(relative)) {
+                return !rule.negated;
+            }
+        }
+        false
+    }
+
+    /// Extend the rules with a directory's ignore file, if `entries` contains one
+    pub(crate) fn scoped<S: Storage + ?Sized>(
+        &self,
+        storage: &S,
+        dir: &Path,
+        entries: &[DirEntry],
+    ) -> Result<Option<Self>> {
+        let has_file = entries
+            .iter()
+            .any(|e| !e.is_dir && e.path.file_name().is_some_and(|n| n == IGNORE_FILE));
+        if !has_file {
+            return Ok(None);
+        }
+
+        let contents = storage.read_to_string(&dir.join(IGNORE_FILE))?;
+        let mut scoped = self.clone();
+        scoped.add_patterns(dir, &contents);
+        Ok(Some(scoped))
+    }
+
+    /// Whether `path` or any of its parent directories is ignored
+    ///
+    /// Reads the `.arkeignore` files along the way from `storage`, so this
+    /// agrees with a full directory walk for a single path.
+    pub(crate) fn is_ignored<S: Storage + ?Sized>(&self, storage: &S, path: &Path) -> bool {
+        let mut rules = self.clone();
+        let mut dir = PathBuf::new();
+
+        let components: Vec<_> = path.components().collect();
+        for (i, component) in components.iter().enumerate() {
+            if let Ok(contents) = storage.read_to_string(&dir.join(IGNORE_FILE)) {
+                rules.add_patterns(&dir, &contents);
+            }
+
+            dir.push(component);
+            let is_dir = i + 1 < components.len() || storage.metadata(&dir).is_ok_and(|m| m.is_dir);
+            if rules.matches(&dir, is_dir) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Join path components with `/` on every platform
+fn to_slash(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Compile one gitignore line into a rule
+fn compile(base: &Path, line: &str) -> Option<Rule> {
+    // Trailing spaces are ignored unless escaped
+    let mut pattern = line.trim_end_matches(['\r', '\n']);
+    while pattern.ends_with(' ') && !pattern.ends_with("\\ ") {
+        pattern = &pattern[..pattern.len() - 1];
+    }
+
+    if pattern.is_empty() || pattern.starts_with('#') {
+        return None;
+    }
+
+    let negated = pattern.starts_with('!');
+    if negated {
+        pattern = &pattern[1..];
+    }
+
+    let dir_only = pattern.ends_with('/');
+    let pattern = pattern.trim_end_matches('/');
+
+    // A slash anywhere but the end anchors the pattern to `base`
+    let anchored = pattern.contains('/');
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+    if pattern.is_empty() {
+        return None;
+    }
+
+    let mut regex = String::from("^");
+    if !anchored {
+        regex.push_str("(?:.*/)?");
+    }
+    regex.push_str(&glob_to_regex(pattern));
+    regex.push('$');
+
+    Some(Rule {
+        base: base.to_path_buf(),
+        regex: Regex::new(&regex).ok()?,
+        negated,
+        dir_only,
+    })
+}
+
+/// Translate a gitignore glob into a regex fragment
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_start = i == 0 || chars[i - 1] == '/';
+                let at_end = i + 2 == chars.len() || chars[i + 2] == '/';
+                if at_start && chars.get(i + 2) == Some(&'/') {
+                    // `**/` matches zero or more directories
+                    out.push_str("(?:.*/)?");
+                    i += 3;
+                } else if at_start && at_end {
+                    // Trailing `/**` matches everything inside
+                    out.push_str(".*");
+                    i += 2;
+                } else {
+                    out.push_str("[^/]*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => match class_to_regex(&chars[i..]) {
+                Some((class, len)) => {
+                    out.push_str(&class);
+                    i += len;
+                    continue;
+                }
+                None => out.push_str(r"\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                out.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            c => out.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+
+    out
+}
+
+/// Translate a `[...]` class starting at `chars[0]`, returning it and its length
+fn class_to_regex(chars: &[char]) -> Option<(String, usize)> {
+    let mut out = String::from("[");
+    let mut i = 1;
+
+    if matches!(chars.get(i), Some('!') | Some('^')) {
+        out.push('^');
+        i += 1;
+    }
+
+    let start = i;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ']' && i > start {
+            out.push(']');
+            return Some((out, i + 1));
+        }
+        if c == '/' {
+            return None;
+        }
+        if matches!(c, '\\' | '[' | ']' | '&' | '~' | '^') {
+            out.push('\\');
+        }
+        out.push(c);
+        i += 1;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn rules(patterns: &str) -> IgnoreRules {
+        let mut rules = IgnoreRules::new();
+        rules.add_patterns(Path::new(""), patterns);
+        rules
+    }
+
+    #[test]
+    fn test_basename_and_anchored_patterns() {
+        let rules = rules("# comment\n\n*.tmp\n/build\ndocs/draft.md\n");
+
+        assert!(rules.matches(Path::new("a.tmp"), false));
+        assert!(rules.matches(Path::new("deep/dir/b.tmp"), false));
+        assert!(rules.matches(Path::new("build"), true));
+        assert!(!rules.matches(Path::new("sub/build"), true));
+        assert!(rules.matches(Path::new("docs/draft.md"), false));
+        assert!(!rules.matches(Path::new("sub/docs/draft.md"), false));
+        assert!(!rules.matches(Path::new("note.md"), false));
+    }
+
+    #[test]
+    fn test_directory_only_and_negation() {
+        let rules = rules("templates/\narchive/*.md\n!archive/keep.md\n");
+
+        assert!(rules.matches(Path::new("templates"), true));
+        assert!(rules.matches(Path::new("sub/templates"), true));
+        assert!(!rules.matches(Path::new("templates"), false));
+        assert!(rules.matches(Path::new("archive/old.md"), false));
+        assert!(!rules.matches(Path::new("archive/keep.md"), false));
+    }
+
+    #[test]
+    fn test_double_star_and_classes() {
+        let rules = rules("**/out/**\nlogs/**/*.log\nnote[0-9].md\n");
+
+        assert!(rules.matches(Path::new("out/a.md"), false));
+        assert!(rules.matches(Path::new("x/out/y/a.md"), false));
+        assert!(rules.matches(Path::new("logs/a.log"), false));
+        assert!(rules.matches(Path::new("logs/x/y/a.log"), false));
+        assert!(rules.matches(Path::new("note7.md"), false));
+        assert!(!rules.matches(Path::new("notes.md"), false));
+    }
+
+    #[test]
+    fn test_defaults_can_be_negated() {
+        let mut rules = IgnoreRules::with_defaults();
+        assert!(rules.matches(Path::new(".obsidian"), true));
+        assert!(rules.matches(Path::new("a/node_modules"), true));
+
+        rules.add_pattern(Path::new(""), "!.public/");
+        assert!(!rules.matches(Path::new(".public"), true));
+    }
+
+    #[test]
+    fn test_is_ignored_reads_nested_ignore_files() {
+        let storage = MemoryStorage::new();
+        storage
+            .write(Path::new("a/.arkeignore"), b"*.md\n!keep.md")
+            .unwrap();
+        storage.write(Path::new("a/drop.md"), b"").unwrap();
+        storage.write(Path::new("a/keep.md"), b"").unwrap();
+        storage.write(Path::new("b/drop.md"), b"").unwrap();
+
+        let rules = IgnoreRules::with_defaults();
+        assert!(rules.is_ignored(&storage, Path::new("a/drop.md")));
+        assert!(!rules.is_ignored(&storage, Path::new("a/keep.md")));
+        assert!(!rules.is_ignored(&storage, Path::new("b/drop.md")));
+        assert!(rules.is_ignored(&storage, Path::new(".git/config")));
+    }
+}
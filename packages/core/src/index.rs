@@ -1,9 +1,30 @@
-use crate::error::Result;
+//! Full-text search over note contents
+//!
+//! Backed by an in-memory tantivy index. Documents are added through a writer
+//! that tokenizes on several threads; changes become searchable once committed,
+//! which [`SearchIndex::search`] does on demand.
+
+use crate::error::{ArkeError, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tantivy::collector::TopDocs;
+use tantivy::query::QueryParser;
+use tantivy::schema::{Field, Schema, Value, STORED, STRING, TEXT};
+use tantivy::snippet::SnippetGenerator;
+use tantivy::{Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+
+/// Memory the writer may buffer before flushing a segment, shared by its threads
+const WRITER_BUDGET: usize = 64_000_000;
+
+/// Most results returned by [`SearchIndex::search`]
+const MAX_RESULTS: usize = 50;
 
 /// Search result
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct SearchResult {
     /// Path to the file
     pub path: PathBuf,
@@ -14,46 +35,131 @@ pub struct SearchResult {
 }
 
 /// Search index for native platforms (using tantivy)
-/// This is a placeholder for Phase 0 - full implementation in Phase 1
 pub struct SearchIndex {
-    indexed_files: Vec<PathBuf>,
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    /// Whether there are changes the reader hasn't seen yet
+    pending: AtomicBool,
+    path_field: Field,
+    content_field: Field,
+    indexed_files: HashSet<PathBuf>,
 }
 
 impl SearchIndex {
     /// Create a new search index
     pub fn new() -> Result<Self> {
+        let mut schema = Schema::builder();
+        let path_field = schema.add_text_field("path", STRING | STORED);
+        let content_field = schema.add_text_field("content", TEXT | STORED);
+
+        let index = Index::create_in_ram(schema.build());
+        let writer = index.writer(WRITER_BUDGET).map_err(index_error)?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .map_err(index_error)?;
+
         Ok(Self {
-            indexed_files: Vec::new(),
+            index,
+            reader,
+            writer: Mutex::new(writer),
+            pending: AtomicBool::new(false),
+            path_field,
+            content_field,
+            indexed_files: HashSet::new(),
         })
     }
 
-    /// Add a file to the index
-    pub fn index_file(&mut self, path: PathBuf, _content: &str) -> Result<()> {
-        // Placeholder: just track indexed files
-        if !self.indexed_files.contains(&path) {
-            self.indexed_files.push(path);
+    /// Add a file to the index, replacing any earlier version of it
+    pub fn index_file(&mut self, path: PathBuf, content: &str) -> Result<()> {
+        let writer = self.writer.get_mut().unwrap();
+        let key = path.to_string_lossy();
+        if self.indexed_files.contains(&path) {
+            writer.delete_term(Term::from_field_text(self.path_field, &key));
         }
+
+        let mut doc = TantivyDocument::new();
+        doc.add_text(self.path_field, &key);
+        doc.add_text(self.content_field, content);
+        writer.add_document(doc).map_err(index_error)?;
+
+        self.indexed_files.insert(path);
+        self.pending.store(true, Ordering::Release);
         Ok(())
     }
 
     /// Remove a file from the index
     pub fn remove_file(&mut self, path: &Path) -> Result<()> {
-        self.indexed_files.retain(|p| p != path);
+        if self.indexed_files.remove(path) {
+            let writer = self.writer.get_mut().unwrap();
+            writer.delete_term(Term::from_field_text(
+                self.path_field,
+                &path.to_string_lossy(),
+            ));
+            self.pending.store(true, Ordering::Release);
+        }
         Ok(())
     }
 
-    /// Search the index
-    pub fn search(&self, _query: &str) -> Result<Vec<SearchResult>> {
-        // Placeholder: return empty results
-        // Full implementation with tantivy will come in Phase 1
-        Ok(Vec::new())
+    /// Make every change so far visible to searches
+    pub fn commit(&self) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        if self.pending.swap(false, Ordering::AcqRel) {
+            writer.commit().map_err(index_error)?;
+            self.reader.reload().map_err(index_error)?;
+        }
+        Ok(())
+    }
+
+    /// Search the index, best matches first
+    ///
+    /// The query uses tantivy's syntax (`a AND b`, `"a phrase"`, `-excluded`);
+    /// malformed parts are ignored rather than rejected.
+    pub fn search(&self, query: &str) -> Result<Vec<SearchResult>> {
+        self.commit()?;
+
+        let parser = QueryParser::for_index(&self.index, vec![self.content_field]);
+        let (query, _) = parser.parse_query_lenient(query);
+        let searcher = self.reader.searcher();
+        let top = searcher
+            .search(&query, &TopDocs::with_limit(MAX_RESULTS))
+            .map_err(index_error)?;
+        let snippets =
+            SnippetGenerator::create(&searcher, &query, self.content_field).map_err(index_error)?;
+
+        top.into_iter()
+            .map(|(score, address)| {
+                let doc: TantivyDocument = searcher.doc(address).map_err(index_error)?;
+                let path = doc
+                    .get_first(self.path_field)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default();
+                Ok(SearchResult {
+                    path: PathBuf::from(path),
+                    score,
+                    snippet: snippets.snippet_from_doc(&doc).fragment().to_string(),
+                })
+            })
+            .collect()
     }
 
     /// Get stats about the index
+    ///
+    /// Terms are counted per segment, so a term in several segments counts more than once.
     pub fn stats(&self) -> IndexStats {
+        let num_terms = self
+            .reader
+            .searcher()
+            .segment_readers()
+            .iter()
+            .filter_map(|segment| segment.inverted_index(self.content_field).ok())
+            .map(|inverted| inverted.terms().num_terms())
+            .sum();
         IndexStats {
             num_files: self.indexed_files.len(),
-            num_terms: 0,
+            num_terms,
         }
     }
 }
@@ -64,8 +170,13 @@ impl Default for SearchIndex {
     }
 }
 
+fn index_error(err: tantivy::TantivyError) -> ArkeError {
+    ArkeError::Index(err.to_string())
+}
+
 /// Statistics about the search index
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct IndexStats {
     pub num_files: usize,
     pub num_terms: usize,
@@ -103,4 +214,27 @@ mod tests {
         let stats = index.stats();
         assert_eq!(stats.num_files, 0);
     }
+
+    #[test]
+    fn test_search() {
+        let mut index = SearchIndex::new().unwrap();
+        index
+            .index_file("a.md".into(), "Rust ownership and borrowing")
+            .unwrap();
+        index
+            .index_file("b.md".into(), "Gardening notes, nothing about code")
+            .unwrap();
+
+        let results = index.search("borrowing").unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, PathBuf::from("a.md"));
+        assert!(results[0].snippet.contains("borrowing"));
+
+        // Re-indexing replaces the old content
+        index.index_file("a.md".into(), "Something else").unwrap();
+        assert!(index.search("borrowing").unwrap().is_empty());
+        index.remove_file(Path::new("b.md")).unwrap();
+        assert!(index.search("gardening").unwrap().is_empty());
+        assert!(index.stats().num_terms > 0);
+    }
 }
@@ -9,19 +9,58 @@
 //!
 //! The library compiles to both native (via Rust) and WASM (for web).
 
+pub mod attachments;
+pub mod batch;
+pub mod cache;
+pub mod config;
 pub mod error;
+pub mod events;
+pub mod folders;
+pub mod history;
+pub mod ignore;
 pub mod links;
+pub mod merge;
+pub mod naming;
 pub mod parser;
+pub mod periodic;
+pub mod shared;
+pub mod storage;
+pub mod tasks;
+pub mod templates;
+pub mod trash;
 pub mod vault;
 
+#[cfg(feature = "native")]
+pub mod async_vault;
 #[cfg(feature = "native")]
 pub mod index;
+#[cfg(feature = "native")]
+pub mod snapshot;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 // Re-export commonly used types
+pub use batch::Batch;
+pub use config::VaultSettings;
 pub use error::{ArkeError, Result};
+pub use events::{EventBus, VaultEvent};
+pub use ignore::IgnoreRules;
 pub use links::{BacklinksMap, WikiLink};
+pub use naming::NewNote;
 pub use parser::MarkdownParser;
-pub use vault::{Vault, VaultConfig};
+pub use periodic::Period;
+pub use shared::SharedVault;
+pub use storage::{MemoryStorage, NativeStorage, Storage};
+pub use tasks::{Task, VaultTask};
+pub use templates::TemplateValues;
+pub use trash::TrashEntry;
+pub use vault::{Note, NoteVersion, Vault, VaultConfig};
+
+#[cfg(feature = "native")]
+pub use async_vault::AsyncVault;
+#[cfg(feature = "native")]
+pub use snapshot::VaultSnapshot;
 
 /// Library version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
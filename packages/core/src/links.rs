@@ -1,4 +1,3 @@
-use crate::error::Result;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -6,6 +5,7 @@ use std::path::{Path, PathBuf};
 
 /// Represents a wikilink [[link]]
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct WikiLink {
     /// The raw link text (e.g., "note" from [[note]])
     pub target: String,
@@ -21,6 +21,62 @@ pub type LinksMap = HashMap<PathBuf, Vec<WikiLink>>;
 /// Maps files to their inbound links (backlinks)
 pub type BacklinksMap = HashMap<PathBuf, Vec<PathBuf>>;
 
+/// Lookup table resolving wikilink targets in constant time
+///
+/// Resolution matches [`LinkExtractor::resolve_link`]: a case-insensitive file
+/// stem match first, then a file name match with and without an added `.md`;
+/// the first file listed wins.
+#[derive(Debug, Clone, Default)]
+pub struct LinkIndex {
+    by_stem: HashMap<String, PathBuf>,
+    by_name: HashMap<String, PathBuf>,
+}
+
+impl LinkIndex {
+    /// Build an index over the given vault files
+    pub fn new(vault_files: &[PathBuf]) -> Self {
+        let mut index = Self::default();
+
+        for file in vault_files {
+            if let Some(stem) = file.file_stem().and_then(|s| s.to_str()) {
+                index
+                    .by_stem
+                    .entry(stem.to_lowercase())
+                    .or_insert_with(|| file.clone());
+            }
+            if let Some(name) = file.file_name().and_then(|n| n.to_str()) {
+                index
+                    .by_name
+                    .entry(name.to_lowercase())
+                    .or_insert_with(|| file.clone());
+            }
+        }
+
+        index
+    }
+
+    /// Resolve a wikilink target to a file path
+    pub fn resolve(&self, target: &str) -> Option<&PathBuf> {
+        let target = target.split('#').next().unwrap_or(target).trim();
+        let target_lower = target.to_lowercase();
+
+        if let Some(path) = self.by_stem.get(&target_lower) {
+            return Some(path);
+        }
+
+        if let Some(path) = self.by_name.get(&target_lower) {
+            return Some(path);
+        }
+
+        let target_with_ext = if !target_lower.ends_with(".md") {
+            format!("{}.md", target_lower)
+        } else {
+            target_lower
+        };
+        self.by_name.get(&target_with_ext)
+    }
+}
+
 /// Extracts and manages wikilinks
 pub struct LinkExtractor {
     wikilink_regex: Regex,
@@ -56,8 +112,9 @@ impl LinkExtractor {
     }
 
     /// Resolve a wikilink target to a file path
-    /// Tries .md extension if not present
+    /// Tries .md extension if not present; `#heading` and `#^block` subpaths are ignored
     pub fn resolve_link(&self, target: &str, vault_files: &[PathBuf]) -> Option<PathBuf> {
+        let target = target.split('#').next().unwrap_or(target).trim();
         let target_lower = target.to_lowercase();
 
         // Try exact match first
@@ -69,6 +126,15 @@ impl LinkExtractor {
             }
         }
 
+        // Try the exact file name, for links to attachments
+        for file in vault_files {
+            if let Some(name) = file.file_name().and_then(|n| n.to_str()) {
+                if name.to_lowercase() == target_lower {
+                    return Some(file.clone());
+                }
+            }
+        }
+
         // Try with .md extension
         let target_with_ext = if !target.ends_with(".md") {
             format!("{}.md", target)
@@ -107,14 +173,15 @@ impl LinkExtractor {
         links_map: &LinksMap,
         vault_files: &[PathBuf],
     ) -> BacklinksMap {
+        let index = LinkIndex::new(vault_files);
         let mut backlinks: BacklinksMap = HashMap::new();
 
         for (source_path, links) in links_map {
             for link in links {
-                if let Some(target_path) = self.resolve_link(&link.target, vault_files) {
+                if let Some(target_path) = index.resolve(&link.target) {
                     backlinks
-                        .entry(target_path)
-                        .or_insert_with(Vec::new)
+                        .entry(target_path.clone())
+                        .or_default()
                         .push(source_path.clone());
                 }
             }
@@ -140,13 +207,14 @@ impl LinkExtractor {
         links_map: &LinksMap,
         vault_files: &[PathBuf],
     ) -> HashMap<PathBuf, Vec<String>> {
+        let index = LinkIndex::new(vault_files);
         let mut broken = HashMap::new();
 
         for (source_path, links) in links_map {
             let mut broken_targets = Vec::new();
 
             for link in links {
-                if self.resolve_link(&link.target, vault_files).is_none() {
+                if index.resolve(&link.target).is_none() {
                     broken_targets.push(link.target.clone());
                 }
             }
@@ -221,11 +289,47 @@ mod tests {
         let resolved = extractor.resolve_link("readme.md", &vault_files);
         assert_eq!(resolved, Some(PathBuf::from("docs/readme.md")));
 
+        // Block and heading subpaths resolve to the note
+        let resolved = extractor.resolve_link("test#^abc123", &vault_files);
+        assert_eq!(resolved, Some(PathBuf::from("notes/test.md")));
+
         // Non-existent file
         let resolved = extractor.resolve_link("nonexistent", &vault_files);
         assert_eq!(resolved, None);
     }
 
+    #[test]
+    fn test_link_index_matches_resolve_link() {
+        let extractor = LinkExtractor::new();
+        let vault_files = vec![
+            PathBuf::from("a/Note.md"),
+            PathBuf::from("b/note.md"),
+            PathBuf::from("docs/readme.md"),
+            PathBuf::from("v1.2.md"),
+            PathBuf::from("assets/image.png"),
+        ];
+        let index = LinkIndex::new(&vault_files);
+
+        for target in [
+            "note",
+            "NOTE",
+            "readme.md",
+            "v1.2",
+            "v1.2.md",
+            "image.png",
+            "image",
+            "missing",
+            "note#^x",
+        ] {
+            assert_eq!(
+                index.resolve(target).cloned(),
+                extractor.resolve_link(target, &vault_files),
+                "target {}",
+                target
+            );
+        }
+    }
+
     #[test]
     fn test_build_backlinks_map() {
         let extractor = LinkExtractor::new();
@@ -0,0 +1,448 @@
+//! Three-way merge of note versions
+//!
+//! Bodies are merged line by line: hunks changed on only one side, or changed
+//! identically on both, resolve automatically, and the rest become conflict
+//! regions. YAML frontmatter is merged per top-level key instead.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use similar::{capture_diff_slices, Algorithm, DiffTag};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// A stretch of merged output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum MergeRegion {
+    /// Text both sides agree on, or that only one side changed
+    Resolved { text: String },
+    /// Text both sides changed differently
+    Conflict {
+        /// Frontmatter key the conflict is about, if it is in frontmatter
+        key: Option<String>,
+        base: String,
+        ours: String,
+        theirs: String,
+    },
+}
+
+/// The outcome of a merge, as regions in document order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeResult {
+    pub regions: Vec<MergeRegion>,
+}
+
+impl MergeResult {
+    /// Whether the merge needs no manual resolution
+    pub fn is_clean(&self) -> bool {
+        self.conflicts() == 0
+    }
+
+    /// Number of conflict regions
+    pub fn conflicts(&self) -> usize {
+        self.regions
+            .iter()
+            .filter(|r| matches!(r, MergeRegion::Conflict { .. }))
+            .count()
+    }
+
+    /// The merged text, if there were no conflicts
+    pub fn merged(&self) -> Option<String> {
+        self.is_clean().then(|| self.with_markers())
+    }
+
+    /// The merged text with git-style `<<<<<<<` markers around conflicts
+    pub fn with_markers(&self) -> String {
+        let mut out = String::new();
+        for region in &self.regions {
+            match region {
+                MergeRegion::Resolved { text } => out.push_str(text),
+                MergeRegion::Conflict { ours, theirs, .. } => {
+                    out.push_str("<<<<<<< ours\n");
+                    push_line_block(&mut out, ours);
+                    out.push_str("=======\n");
+                    push_line_block(&mut out, theirs);
+                    out.push_str(">>>>>>> theirs\n");
+                }
+            }
+        }
+        out
+    }
+
+    fn push_resolved(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if let Some(MergeRegion::Resolved { text: last }) = self.regions.last_mut() {
+            last.push_str(text);
+        } else {
+            self.regions.push(MergeRegion::Resolved {
+                text: text.to_string(),
+            });
+        }
+    }
+}
+
+/// Merge `ours` and `theirs`, which both started from `base`
+pub fn merge(base: &str, ours: &str, theirs: &str) -> MergeResult {
+    let (base_fm, base_body) = split_frontmatter(base);
+    let (ours_fm, ours_body) = split_frontmatter(ours);
+    let (theirs_fm, theirs_body) = split_frontmatter(theirs);
+
+    let mut result = MergeResult::default();
+    if ours_fm.is_some() || theirs_fm.is_some() {
+        merge_frontmatter(
+            base_fm.unwrap_or_default(),
+            ours_fm.unwrap_or_default(),
+            theirs_fm.unwrap_or_default(),
+            &mut result,
+        );
+    }
+    merge_lines(base_body, ours_body, theirs_body, &mut result);
+    result
+}
+
+/// Merge two versions with no known common ancestor, such as a sync conflict copy
+///
+/// The lines both versions share act as the base, so lines present on only
+/// one side are kept and only competing edits in the same place conflict.
+pub fn merge_two_way(ours: &str, theirs: &str) -> MergeResult {
+    let ours_lines = lines(ours);
+    let theirs_lines = lines(theirs);
+
+    let mut base = String::new();
+    for op in capture_diff_slices(Algorithm::Myers, &ours_lines, &theirs_lines) {
+        let (tag, range, _) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            base.push_str(&ours_lines[range].concat());
+        }
+    }
+
+    merge(&base, ours, theirs)
+}
+
+/// A change to a range of base lines
+struct Hunk {
+    base: Range<usize>,
+    side: Range<usize>,
+}
+
+/// Line-based diff3
+fn merge_lines(base: &str, ours: &str, theirs: &str, result: &mut MergeResult) {
+    let base = lines(base);
+    let ours = lines(ours);
+    let theirs = lines(theirs);
+    let ours_hunks = hunks(&base, &ours);
+    let theirs_hunks = hunks(&base, &theirs);
+
+    let (mut a, mut b) = (0, 0);
+    let mut base_pos = 0;
+
+    while a < ours_hunks.len() || b < theirs_hunks.len() {
+        // Start a group at the earliest hunk, then absorb every hunk that touches it
+        let start = match (ours_hunks.get(a), theirs_hunks.get(b)) {
+            (Some(x), Some(y)) => x.base.start.min(y.base.start),
+            (Some(x), None) => x.base.start,
+            (None, Some(y)) => y.base.start,
+            (None, None) => unreachable!(),
+        };
+        let mut end = start;
+        let (first_a, first_b) = (a, b);
+        loop {
+            if let Some(h) = ours_hunks.get(a).filter(|h| h.base.start <= end) {
+                end = end.max(h.base.end);
+                a += 1;
+            } else if let Some(h) = theirs_hunks.get(b).filter(|h| h.base.start <= end) {
+                end = end.max(h.base.end);
+                b += 1;
+            } else {
+                break;
+            }
+        }
+
+        result.push_resolved(&base[base_pos..start].concat());
+        base_pos = end;
+
+        let ours_text = apply(&base, &ours, &ours_hunks[first_a..a], start..end);
+        let theirs_text = apply(&base, &theirs, &theirs_hunks[first_b..b], start..end);
+        if first_b == b || ours_text == theirs_text {
+            result.push_resolved(&ours_text);
+        } else if first_a == a {
+            result.push_resolved(&theirs_text);
+        } else {
+            result.regions.push(MergeRegion::Conflict {
+                key: None,
+                base: base[start..end].concat(),
+                ours: ours_text,
+                theirs: theirs_text,
+            });
+        }
+    }
+
+    result.push_resolved(&base[base_pos..].concat());
+}
+
+/// Split text into lines, keeping line endings
+fn lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// Changed regions between `base` and `side`, in base order
+fn hunks(base: &[&str], side: &[&str]) -> Vec<Hunk> {
+    capture_diff_slices(Algorithm::Myers, base, side)
+        .iter()
+        .map(|op| op.as_tag_tuple())
+        .filter(|(tag, _, _)| *tag != DiffTag::Equal)
+        .map(|(_, base, side)| Hunk { base, side })
+        .collect()
+}
+
+/// One side's text for the base range `range`, given its hunks within it
+fn apply(base: &[&str], side: &[&str], hunks: &[Hunk], range: Range<usize>) -> String {
+    let mut out = String::new();
+    let mut pos = range.start;
+    for hunk in hunks {
+        out.push_str(&base[pos..hunk.base.start].concat());
+        out.push_str(&side[hunk.side.clone()].concat());
+        pos = hunk.base.end;
+    }
+    out.push_str(&base[pos..range.end].concat());
+    out
+}
+
+/// Split a note into its frontmatter body (between the `---` fences) and the rest
+pub(crate) fn split_frontmatter(text: &str) -> (Option<&str>, &str) {
+    let Some(rest) = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    else {
+        return (None, text);
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (None, text)
+}
+
+/// Top-level frontmatter entries: `(key, full text of the entry)`
+///
+/// Lines before the first key are kept under the empty key.
+pub(crate) fn frontmatter_entries(frontmatter: &str) -> Vec<(String, String)> {
+    static KEY: OnceLock<Regex> = OnceLock::new();
+    let key_regex = KEY.get_or_init(|| {
+        Regex::new(r#"^(?:"([^"]+)"|'([^']+)'|([^\s#'"\-][^:]*?))\s*:(?:\s|$)"#)
+            .expect("Invalid frontmatter key regex")
+    });
+
+    let mut entries: Vec<(String, String)> = Vec::new();
+    for line in frontmatter.split_inclusive('\n') {
+        let key = key_regex
+            .captures(line)
+            .and_then(|c| c.get(1).or(c.get(2)).or(c.get(3)))
+            .map(|m| m.as_str().to_string());
+
+        match (key, entries.last_mut()) {
+            (Some(key), _) => entries.push((key, line.to_string())),
+            (None, Some((_, text))) => text.push_str(line),
+            (None, None) => entries.push((String::new(), line.to_string())),
+        }
+    }
+    entries
+}
+
+/// Merge frontmatter key by key and emit it, fences included
+fn merge_frontmatter(base: &str, ours: &str, theirs: &str, result: &mut MergeResult) {
+    let base = frontmatter_entries(base);
+    let ours = frontmatter_entries(ours);
+    let theirs = frontmatter_entries(theirs);
+    let find = |entries: &[(String, String)], key: &str| {
+        entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, text)| text.clone())
+    };
+
+    // Our key order, then keys they added, or changed where we deleted them
+    let mut keys: Vec<&String> = ours.iter().map(|(k, _)| k).collect();
+    for (key, text) in &theirs {
+        if !keys.contains(&key) && find(&base, key).as_ref() != Some(text) {
+            keys.push(key);
+        }
+    }
+    if keys.is_empty() {
+        return;
+    }
+
+    result.push_resolved("---\n");
+    for key in keys {
+        let base_text = find(&base, key);
+        let ours_text = find(&ours, key);
+        let theirs_text = find(&theirs, key);
+
+        if ours_text == theirs_text || theirs_text == base_text {
+            result.push_resolved(&ours_text.unwrap_or_default());
+        } else if ours_text == base_text {
+            result.push_resolved(&theirs_text.unwrap_or_default());
+        } else {
+            result.regions.push(MergeRegion::Conflict {
+                key: Some(key.clone()),
+                base: base_text.unwrap_or_default(),
+                ours: ours_text.unwrap_or_default(),
+                theirs: theirs_text.unwrap_or_default(),
+            });
+        }
+    }
+    result.push_resolved("---\n");
+}
+
+/// Append `text` as whole lines
+fn push_line_block(out: &mut String, text: &str) {
+    out.push_str(text);
+    if !text.is_empty() && !text.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// A copy of a note created by a sync service when two edits collided
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConflictCopy {
+    /// The note the copy diverged from
+    pub original: PathBuf,
+    /// The conflict copy
+    pub copy: PathBuf,
+}
+
+/// The note a sync conflict copy belongs to, if `path` names one
+///
+/// Recognizes Dropbox-style `note (conflicted copy).md` and
+/// `note (Ana's conflicted copy 2024-01-05).md`, and Syncthing-style
+/// `note.sync-conflict-20240105-101500-ABCDEF1.md`.
+pub fn conflict_copy_original(path: &Path) -> Option<PathBuf> {
+    static PATTERNS: OnceLock<[Regex; 2]> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        [
+            Regex::new(r"(?i)^(.+?) \([^()]*conflicted copy[^()]*\)$")
+                .expect("Invalid conflicted copy regex"),
+            Regex::new(r"^(.+?)\.sync-conflict-\d{8}-\d{6}(?:-[A-Z0-9]+)?$")
+                .expect("Invalid sync conflict regex"),
+        ]
+    });
+
+    let stem = path.file_stem()?.to_str()?;
+    let original = patterns
+        .iter()
+        .find_map(|re| re.captures(stem))?
+        .get(1)?
+        .as_str();
+
+    let mut name = original.to_string();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        name.push('.');
+        name.push_str(ext);
+    }
+    Some(path.with_file_name(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_non_overlapping_changes_merge_cleanly() {
+        let base = "one\ntwo\nthree\nfour\nfive\n";
+        let ours = "ONE\ntwo\nthree\nfour\nfive\n";
+        let theirs = "one\ntwo\nthree\nfour\nFIVE\nsix\n";
+
+        let result = merge(base, ours, theirs);
+        assert!(result.is_clean());
+        assert_eq!(
+            result.merged().unwrap(),
+            "ONE\ntwo\nthree\nfour\nFIVE\nsix\n"
+        );
+    }
+
+    #[test]
+    fn test_overlapping_changes_conflict() {
+        let base = "title\nshared line\nend\n";
+        let ours = "title\nour edit\nend\n";
+        let theirs = "title\ntheir edit\nend\n";
+
+        let result = merge(base, ours, theirs);
+        assert_eq!(result.conflicts(), 1);
+        assert_eq!(
+            result.regions[1],
+            MergeRegion::Conflict {
+                key: None,
+                base: "shared line\n".to_string(),
+                ours: "our edit\n".to_string(),
+                theirs: "their edit\n".to_string(),
+            }
+        );
+        assert_eq!(
+            result.with_markers(),
+            "title\n<<<<<<< ours\nour edit\n=======\ntheir edit\n>>>>>>> theirs\nend\n"
+        );
+
+        // The same edit on both sides is not a conflict
+        assert!(merge(base, ours, ours).is_clean());
+    }
+
+    #[test]
+    fn test_frontmatter_merges_by_key() {
+        let base = "---\ntitle: Plan\ntags:\n  - a\nstatus: draft\n---\nBody\n";
+        let ours = "---\ntitle: Plan v2\ntags:\n  - a\nstatus: draft\n---\nBody\n";
+        let theirs =
+            "---\ntitle: Plan\ntags:\n  - a\n  - b\nstatus: draft\nowner: sam\n---\nBody\n";
+
+        let result = merge(base, ours, theirs);
+        assert_eq!(
+            result.merged().unwrap(),
+            "---\ntitle: Plan v2\ntags:\n  - a\n  - b\nstatus: draft\nowner: sam\n---\nBody\n"
+        );
+
+        let theirs = "---\ntitle: Plan v3\ntags:\n  - a\nstatus: done\n---\nBody\n";
+        let result = merge(base, ours, theirs);
+        assert_eq!(result.conflicts(), 1);
+        assert!(matches!(
+            &result.regions[1],
+            MergeRegion::Conflict { key: Some(key), .. } if key == "title"
+        ));
+        assert!(result.with_markers().contains("status: done\n"));
+    }
+
+    #[test]
+    fn test_two_way_merge_keeps_both_sides() {
+        let ours = "a\nb\nours\nc\n";
+        let theirs = "a\nb\nc\ntheirs\n";
+
+        let result = merge_two_way(ours, theirs);
+        assert_eq!(result.merged().unwrap(), "a\nb\nours\nc\ntheirs\n");
+    }
+
+    #[test]
+    fn test_conflict_copy_original() {
+        let original = |p: &str| conflict_copy_original(Path::new(p));
+
+        assert_eq!(
+            original("dir/note (conflicted copy).md"),
+            Some(PathBuf::from("dir/note.md"))
+        );
+        assert_eq!(
+            original("note (Ana's conflicted copy 2024-01-05).md"),
+            Some(PathBuf::from("note.md"))
+        );
+        assert_eq!(
+            original("note.sync-conflict-20240105-101500-ABCDEF1.md"),
+            Some(PathBuf::from("note.md"))
+        );
+        assert_eq!(original("note (draft).md"), None);
+    }
+}
@@ -0,0 +1,349 @@
+//! Turning note titles into file names
+//!
+//! A title may contain anything; a file name has to work on every platform a
+//! vault syncs to and inside a wikilink. Names are cleaned of characters
+//! Windows, macOS or links can't take, kept clear of reserved Windows device
+//! names, and cut to a length all common filesystems accept.
+
+use crate::attachments;
+use crate::config::LinkStyle;
+use crate::error::{ArkeError, Result};
+use crate::storage::Storage;
+use crate::vault::{is_user_folder, NoteVersion, Vault};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Longest file name stem in bytes, leaving room for a collision suffix and `.md`
+///
+/// Filesystems allow 255 bytes (or UTF-16 units) per name.
+const MAX_STEM_BYTES: usize = 200;
+
+/// Characters not allowed in file names on some platform, or that break wikilinks
+const FORBIDDEN: &[char] = &[
+    '/', '\\', ':', '*', '?', '"', '<', '>', '|', '#', '^', '[', ']',
+];
+
+/// Device names Windows reserves, with or without an extension
+const RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$", "COM0", "COM1", "COM2", "COM3", "COM4",
+    "COM5", "COM6", "COM7", "COM8", "COM9", "COM¹", "COM²", "COM³", "LPT0", "LPT1", "LPT2", "LPT3",
+    "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9", "LPT¹", "LPT²", "LPT³",
+];
+
+/// How many times a new note picks another name after losing one to a concurrent write
+const MAX_CREATE_ATTEMPTS: usize = 8;
+
+/// A note made by [`Vault::create_note`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewNote {
+    pub path: PathBuf,
+    /// Link to the note in the vault's link style, showing the original title
+    pub link: String,
+}
+
+/// A file name stem for a note titled `title`, valid on every platform
+///
+/// Forbidden characters become spaces, runs of whitespace collapse, leading
+/// dots (which hide files) and trailing dots and spaces (which Windows drops)
+/// are removed. An empty result becomes `Untitled`.
+pub fn sanitize_file_name(title: &str) -> String {
+    let title = title.trim();
+    let title = title
+        .len()
+        .checked_sub(3)
+        .filter(|&i| title.is_char_boundary(i) && title[i..].eq_ignore_ascii_case(".md"))
+        .map_or(title, |i| &title[..i]);
+
+    let cleaned: String = title
+        .chars()
+        .map(|c| {
+            if FORBIDDEN.contains(&c) || c.is_control() {
+                ' '
+            } else {
+                c
+            }
+        })
+        .collect();
+    let mut name = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if name.len() > MAX_STEM_BYTES {
+        let mut end = MAX_STEM_BYTES;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+    }
+    let mut name = name
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' '])
+        .to_string();
+    if name.is_empty() {
+        return "Untitled".to_string();
+    }
+
+    // `CON`, `con.notes` and `Con .x` are all the console device on Windows
+    let base_len = name.split('.').next().unwrap_or_default().trim_end().len();
+    if RESERVED
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(&name[..base_len]))
+    {
+        name.insert(base_len, '_');
+    }
+    name
+}
+
+impl<S: Storage> Vault<S> {
+    /// Create a note titled `title` in the default new note folder
+    ///
+    /// See [`Vault::create_note_in`].
+    pub fn create_note(&mut self, title: &str, content: &str) -> Result<NewNote> {
+        let folder = PathBuf::from(&self.settings().new_note_folder);
+        if !is_user_folder(&folder) {
+            return Err(ArkeError::Config {
+                key: "newNoteFolder".to_string(),
+                message: format!("{:?} must be a folder inside the vault", folder),
+            });
+        }
+        self.create_note_in(&folder, title, content)
+    }
+
+    /// Create a note titled `title` in `folder`, never overwriting an existing file
+    ///
+    /// The file name is the sanitized title (see [`sanitize_file_name`]),
+    /// with a number added if it's taken (`Title 1.md`). Names differing only
+    /// in case count as taken, since they clash on macOS and Windows.
+    pub fn create_note_in(&mut self, folder: &Path, title: &str, content: &str) -> Result<NewNote> {
+        let path = self.write_new_note(folder, &sanitize_file_name(title), |_, _| {
+            Ok(content.to_string())
+        })?;
+        let link = self.link_to(&path, title.trim())?;
+        Ok(NewNote { path, link })
+    }
+
+    /// Write a new note named `stem` in `folder` with the content `render` gives for its path
+    ///
+    /// If another writer takes the chosen name first, the next free one is
+    /// tried, so an existing file is never overwritten.
+    pub(crate) fn write_new_note<F>(
+        &mut self,
+        folder: &Path,
+        stem: &str,
+        render: F,
+    ) -> Result<PathBuf>
+    where
+        F: Fn(&Self, &Path) -> Result<String>,
+    {
+        if !is_user_folder(folder) {
+            return Err(ArkeError::Vault(format!(
+                "Notes can't be created in {:?}",
+                folder
+            )));
+        }
+        let mut attempts = 0;
+        loop {
+            let path = self.free_note_path(folder, stem)?;
+            let content = render(self, &path)?;
+            match self.write_note_if(&path, &content, &NoteVersion::Absent) {
+                Err(ArkeError::Conflict { .. }) if attempts + 1 < MAX_CREATE_ATTEMPTS => {
+                    attempts += 1;
+                }
+                result => return result.map(|_| path),
+            }
+        }
+    }
+
+    /// A path for a new note named `stem` in `folder` that no file takes, in any case
+    pub(crate) fn free_note_path(&self, folder: &Path, stem: &str) -> Result<PathBuf> {
+        let taken: HashSet<String> = match self.storage().list(folder) {
+            Ok(entries) => entries
+                .iter()
+                .filter_map(|e| e.path.file_name()?.to_str())
+                .map(str::to_lowercase)
+                .collect(),
+            Err(ArkeError::FileNotFound(_)) => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(attachments::unique_path(
+            folder,
+            &format!("{}.md", stem),
+            |path| {
+                path.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| taken.contains(&n.to_lowercase()))
+            },
+        ))
+    }
+
+    /// How a wikilink names `path`: by file name if that's unambiguous, otherwise by path
+    pub(crate) fn wikilink_target(&self, path: &Path) -> Result<String> {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase());
+        let shared = self.list_files()?.iter().any(|other| {
+            other != path
+                && other
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_lowercase())
+                    == name
+        });
+        let slash_path = path.to_string_lossy().replace('\\', "/");
+        Ok(if shared {
+            slash_path.trim_end_matches(".md").to_string()
+        } else {
+            path.file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Link text for a note in the vault's link style, displaying `title`
+    fn link_to(&self, path: &Path, title: &str) -> Result<String> {
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+
+        match self.settings().link_style {
+            LinkStyle::Wikilink => {
+                let target = self.wikilink_target(path)?;
+                let aliased =
+                    title != stem && !title.is_empty() && !title.contains(['[', ']', '|']);
+                Ok(if aliased {
+                    format!("[[{}|{}]]", target, title)
+                } else {
+                    format!("[[{}]]", target)
+                })
+            }
+            LinkStyle::Markdown => {
+                let text = if title.is_empty() || title.contains(['[', ']']) {
+                    stem
+                } else {
+                    title
+                };
+                let target = path
+                    .to_string_lossy()
+                    .replace('\\', "/")
+                    .replace('%', "%25")
+                    .replace(' ', "%20")
+                    .replace('(', "%28")
+                    .replace(')', "%29");
+                Ok(format!("[{}]({})", text, target))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::VaultSettings;
+    use crate::storage::{MemoryStorage, RacingStorage};
+    use crate::vault::VaultConfig;
+
+    fn memory_vault(settings: VaultSettings) -> Vault<MemoryStorage> {
+        let config = VaultConfig {
+            path: PathBuf::from("/memory"),
+            name: "Memory".to_string(),
+            watch: false,
+            settings,
+        };
+        Vault::with_storage(config, MemoryStorage::new())
+    }
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("Q3: plans / ideas?"), "Q3 plans ideas");
+        assert_eq!(sanitize_file_name("[[C#]] *notes*"), "C notes");
+        assert_eq!(sanitize_file_name("..hidden. "), "hidden");
+        assert_eq!(sanitize_file_name("Plan.md"), "Plan");
+        assert_eq!(sanitize_file_name(" ?/ "), "Untitled");
+        assert_eq!(sanitize_file_name("con"), "con_");
+        assert_eq!(sanitize_file_name("LPT1.notes"), "LPT1_.notes");
+        assert_eq!(sanitize_file_name("Console"), "Console");
+        assert_eq!(sanitize_file_name("com0"), "com0_");
+        assert_eq!(sanitize_file_name("LPT0.txt"), "LPT0_.txt");
+        assert_eq!(sanitize_file_name("conin$"), "conin$_");
+        assert_eq!(sanitize_file_name("CONOUT$"), "CONOUT$_");
+        assert_eq!(sanitize_file_name("COM¹"), "COM¹_");
+        assert_eq!(sanitize_file_name("lpt³.log"), "lpt³_.log");
+        assert_eq!(sanitize_file_name("COM10"), "COM10");
+
+        let long = sanitize_file_name(&"é".repeat(300));
+        assert!(long.len() <= MAX_STEM_BYTES);
+        assert!(long.chars().all(|c| c == 'é'));
+    }
+
+    #[test]
+    fn test_create_note() {
+        let mut vault = memory_vault(VaultSettings {
+            new_note_folder: "inbox".to_string(),
+            ..Default::default()
+        });
+
+        let first = vault.create_note("What? Why", "one").unwrap();
+        assert_eq!(first.path, PathBuf::from("inbox/What Why.md"));
+        assert_eq!(first.link, "[[What Why|What? Why]]");
+
+        // Taken names, in any case, get a number rather than being overwritten
+        let second = vault.create_note("what why", "two").unwrap();
+        assert_eq!(second.path, PathBuf::from("inbox/what why 1.md"));
+        assert_eq!(vault.read_note(&first.path).unwrap().content, "one");
+
+        // A name shared with a note elsewhere is linked by path
+        let other = vault
+            .create_note_in(Path::new("archive"), "What Why", "")
+            .unwrap();
+        assert_eq!(other.link, "[[archive/What Why]]");
+    }
+
+    #[test]
+    fn test_create_note_never_overwrites() {
+        // Another client saves `Plan.md` between the folder listing and the write
+        let config = VaultConfig {
+            path: PathBuf::from("/memory"),
+            name: "Memory".to_string(),
+            watch: false,
+            settings: Default::default(),
+        };
+        let storage = RacingStorage::new("Plan.md", b"theirs");
+        let mut vault = Vault::with_storage(config, storage);
+
+        let note = vault.create_note("Plan", "ours").unwrap();
+        assert_eq!(note.path, PathBuf::from("Plan 1.md"));
+        assert_eq!(vault.read_note("Plan.md").unwrap().content, "theirs");
+        assert_eq!(vault.read_note(&note.path).unwrap().content, "ours");
+    }
+
+    #[test]
+    fn test_create_note_stays_in_the_vault() {
+        for folder in ["../elsewhere", "/tmp", ".arke"] {
+            let mut vault = memory_vault(VaultSettings {
+                new_note_folder: folder.to_string(),
+                ..Default::default()
+            });
+            let err = vault.create_note("Plan", "").unwrap_err();
+            assert!(
+                matches!(err, ArkeError::Config { ref key, .. } if key == "newNoteFolder"),
+                "{}",
+                folder
+            );
+            assert!(matches!(
+                vault.create_note_in(Path::new(folder), "Plan", ""),
+                Err(ArkeError::Vault(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_markdown_links() {
+        let mut vault = memory_vault(VaultSettings {
+            link_style: LinkStyle::Markdown,
+            ..Default::default()
+        });
+
+        let note = vault
+            .create_note_in(Path::new("a b"), "Plan (draft)", "")
+            .unwrap();
+        assert_eq!(note.link, "[Plan (draft)](a%20b/Plan%20%28draft%29.md)");
+    }
+}
@@ -1,14 +1,23 @@
+use crate::config::ParserOptions;
 use crate::error::Result;
-use comrak::{markdown_to_html, ComrakOptions};
+use crate::tasks::{Task, TaskMetadataParser};
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::{format_html, parse_document, Arena, ComrakOptions};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
 /// Markdown parser using comrak
 pub struct MarkdownParser {
     options: ComrakOptions<'static>,
+    callout_regex: Regex,
+    block_id_regex: Regex,
+    task_metadata: TaskMetadataParser,
 }
 
 /// Parsed markdown document
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ParsedMarkdown {
     /// Original markdown content
     pub raw: String,
@@ -18,10 +27,37 @@ pub struct ParsedMarkdown {
     pub headings: Vec<Heading>,
     /// Extracted code blocks
     pub code_blocks: Vec<CodeBlock>,
+    /// Extracted math expressions
+    pub math: Vec<MathExpression>,
+    /// Extracted callouts
+    pub callouts: Vec<Callout>,
+    /// Extracted task list items
+    pub tasks: Vec<Task>,
+    /// Extracted `^block-id` markers
+    pub block_ids: Vec<BlockId>,
+}
+
+/// Structure extracted from a document, without rendered HTML
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentOutline {
+    /// Extracted headings
+    pub headings: Vec<Heading>,
+    /// Extracted code blocks
+    pub code_blocks: Vec<CodeBlock>,
+    /// Extracted math expressions
+    pub math: Vec<MathExpression>,
+    /// Extracted callouts
+    pub callouts: Vec<Callout>,
+    /// Extracted task list items
+    pub tasks: Vec<Task>,
+    /// Extracted `^block-id` markers
+    pub block_ids: Vec<BlockId>,
 }
 
 /// A heading in the document
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Heading {
     pub level: u8,
     pub text: String,
@@ -30,14 +66,120 @@ pub struct Heading {
 
 /// A code block in the document
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CodeBlock {
+    /// Language tag (first word of the info string)
     pub language: Option<String>,
+    /// Remaining info string attributes (e.g. `title="x.rs"`)
+    pub attributes: Option<String>,
+    /// Literal code contents
     pub code: String,
+    /// Whether the block is fenced (as opposed to indented)
+    pub fenced: bool,
+    /// First source line of the block (1-based, includes the opening fence)
+    pub start_line: usize,
+    /// Last source line of the block (1-based, includes the closing fence)
+    pub end_line: usize,
+}
+
+/// A math expression in the document
+///
+/// Rendered HTML marks math with a `data-math-style` attribute (`inline` or
+/// `display`) so the preview can hand it to KaTeX.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MathExpression {
+    /// Raw TeX source
+    pub literal: String,
+    /// Whether this is display math (`$$...$$` or a ```` ```math ```` block)
+    pub display: bool,
+    /// Source line the expression starts on (1-based)
+    pub line: usize,
+}
+
+/// A callout (admonition) block in the document
+///
+/// Written either as a blockquote (`> [!warning] Title`) or as a directive
+/// (`:::warning Title` ... `:::`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Callout {
+    /// Callout type, lowercased (e.g. `note`, `warning`)
+    pub kind: String,
+    /// Title text; defaults to the capitalized type when none is given
+    pub title: String,
+    /// Fold state, if the callout is foldable
+    pub fold: Option<CalloutFold>,
+    /// Markdown body with the quote markers stripped
+    pub content: String,
+    /// First source line of the callout (1-based)
+    pub start_line: usize,
+    /// Last source line of the callout (1-based)
+    pub end_line: usize,
+}
+
+/// Fold state of a foldable callout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CalloutFold {
+    /// `+`: foldable, expanded by default
+    Expanded,
+    /// `-`: foldable, collapsed by default
+    Collapsed,
+}
+
+/// A `^block-id` marker and the block it identifies
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockId {
+    /// The id, without the leading `^`
+    pub id: String,
+    /// Kind of block the id belongs to
+    pub kind: BlockKind,
+    /// Plain text of the block, without the marker
+    pub text: String,
+    /// First source line of the block (1-based)
+    pub start_line: usize,
+    /// Last source line of the block (1-based)
+    pub end_line: usize,
+}
+
+/// Kind of block a `^block-id` can identify
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BlockKind {
+    Paragraph,
+    ListItem,
+}
+
+/// A block that can carry a `^block-id`, whether or not it has one yet
+#[derive(Debug, Clone)]
+pub(crate) struct Block {
+    pub id: Option<String>,
+    pub kind: BlockKind,
+    pub text: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    /// Last line of the block's own text, where its marker goes
+    pub marker_line: usize,
+}
+
+/// Callout marker parsed from the first line of a blockquote
+struct CalloutMarker {
+    kind: String,
+    fold: Option<CalloutFold>,
+    /// Byte length of the `[!type]+` marker including trailing whitespace
+    len: usize,
 }
 
 impl MarkdownParser {
     /// Create a new parser with default options
     pub fn new() -> Self {
+        Self::with_options(&ParserOptions::default())
+    }
+
+    /// Create a parser with the given options
+    pub fn with_options(parser_options: &ParserOptions) -> Self {
         let mut options = ComrakOptions::default();
 
         // Enable GitHub-flavored markdown extensions
@@ -46,94 +188,594 @@ impl MarkdownParser {
         options.extension.autolink = true;
         options.extension.tasklist = true;
         options.extension.superscript = false;
-        options.extension.footnotes = true;
+        options.extension.footnotes = parser_options.footnotes;
         options.extension.description_lists = true;
+        options.extension.math_dollars = parser_options.math;
+        options.extension.math_code = parser_options.math;
+        options.parse.smart = parser_options.smart_punctuation;
 
         // Render options
-        options.render.hardbreaks = false;
+        options.render.hardbreaks = parser_options.hard_breaks;
         options.render.github_pre_lang = true;
         options.render.full_info_string = true;
         options.render.unsafe_ = false; // Don't allow raw HTML for security
 
-        Self { options }
+        // Matches the [!type] marker of a callout, with optional fold state
+        let callout_regex = Regex::new(r"^\[!([A-Za-z][A-Za-z0-9_-]*)\]([+-]?)[ \t]*")
+            .expect("Invalid callout regex");
+        // Matches a ^block-id at the end of a block's text
+        let block_id_regex =
+            Regex::new(r"(?:^|\s)\^([A-Za-z0-9-]+)\s*$").expect("Invalid block id regex");
+
+        Self {
+            options,
+            callout_regex,
+            block_id_regex,
+            task_metadata: TaskMetadataParser::new(),
+        }
     }
 
     /// Parse markdown to HTML
     pub fn parse_to_html(&self, markdown: &str) -> String {
-        markdown_to_html(markdown, &self.options)
+        let source = expand_directives(markdown);
+        let arena = Arena::new();
+        let root = parse_document(&arena, &source, &self.options);
+        self.render_html(&arena, root)
     }
 
     /// Parse markdown and extract structure
     pub fn parse(&self, markdown: &str) -> Result<ParsedMarkdown> {
-        let html = self.parse_to_html(markdown);
+        let source = expand_directives(markdown);
+        let arena = Arena::new();
+        let root = parse_document(&arena, &source, &self.options);
+
+        let outline = self.extract_outline(root, &source);
+
+        // Rendering rewrites callout nodes, so it runs after extraction
+        let html = self.render_html(&arena, root);
 
         Ok(ParsedMarkdown {
             raw: markdown.to_string(),
             html,
-            headings: self.extract_headings(markdown),
-            code_blocks: self.extract_code_blocks(markdown),
+            headings: outline.headings,
+            code_blocks: outline.code_blocks,
+            math: outline.math,
+            callouts: outline.callouts,
+            tasks: outline.tasks,
+            block_ids: outline.block_ids,
         })
     }
 
-    /// Extract headings from markdown
-    fn extract_headings(&self, markdown: &str) -> Vec<Heading> {
-        let mut headings = Vec::new();
-        let mut id_counter = 0;
+    /// Extract document structure without rendering HTML
+    pub fn parse_outline(&self, markdown: &str) -> DocumentOutline {
+        let source = expand_directives(markdown);
+        let arena = Arena::new();
+        let root = parse_document(&arena, &source, &self.options);
+        self.extract_outline(root, &source)
+    }
 
-        for line in markdown.lines() {
-            let trimmed = line.trim();
-            if let Some(stripped) = trimmed.strip_prefix('#') {
-                let level = stripped.chars().take_while(|c| *c == '#').count() as u8 + 1;
+    /// Extract all structure from the parsed AST
+    fn extract_outline<'a>(&self, root: &'a AstNode<'a>, source: &str) -> DocumentOutline {
+        let block_ids = self
+            .extract_blocks(root)
+            .into_iter()
+            .filter_map(|block| {
+                Some(BlockId {
+                    id: block.id?,
+                    kind: block.kind,
+                    text: block.text,
+                    start_line: block.start_line,
+                    end_line: block.end_line,
+                })
+            })
+            .collect();
 
-                if level <= 6 {
-                    let text = stripped.trim_start_matches('#').trim().to_string();
+        DocumentOutline {
+            headings: self.extract_headings(root),
+            code_blocks: self.extract_code_blocks(root),
+            math: self.extract_math(root),
+            callouts: self.extract_callouts(root, source),
+            tasks: self.extract_tasks(root),
+            block_ids,
+        }
+    }
+
+    /// Extract only the task list items, without rendering HTML
+    pub fn parse_tasks(&self, markdown: &str) -> Vec<Task> {
+        let source = expand_directives(markdown);
+        let arena = Arena::new();
+        let root = parse_document(&arena, &source, &self.options);
+        self.extract_tasks(root)
+    }
 
-                    let id = format!("heading-{}", id_counter);
-                    id_counter += 1;
+    /// Render a parsed document to HTML, turning callouts into structured blocks
+    fn render_html<'a>(&self, arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>) -> String {
+        let callouts = self.find_callouts(root);
+        let mut rendered = vec![String::new(); callouts.len()];
 
-                    headings.push(Heading { level, text, id });
+        // Innermost callouts first, so each body already holds its nested placeholders
+        for (i, (node, marker)) in callouts.iter().enumerate().rev() {
+            rendered[i] = self.render_callout(node, marker);
+
+            let placeholder = arena.alloc(NodeValue::Paragraph.into());
+            placeholder.append(arena.alloc(NodeValue::Text(callout_placeholder(i)).into()));
+            node.insert_before(placeholder);
+            node.detach();
+        }
+
+        let mut html = self.format_node(root);
+        for (i, callout_html) in rendered.iter().enumerate() {
+            let placeholder = callout_placeholder(i);
+            let paragraph = format!("<p>{}</p>\n", placeholder);
+            if html.contains(&paragraph) {
+                html = html.replacen(&paragraph, callout_html, 1);
+            } else {
+                // Paragraphs in tight lists render without <p>
+                html = html.replacen(&placeholder, callout_html, 1);
+            }
+        }
+
+        html
+    }
+
+    /// Render a single callout blockquote (detaching its title inlines)
+    fn render_callout<'a>(&self, node: &'a AstNode<'a>, marker: &CalloutMarker) -> String {
+        let mut title_html = String::new();
+
+        if let Some(paragraph) = node.first_child() {
+            if let Some(first) = paragraph.first_child() {
+                if let NodeValue::Text(ref mut text) = first.data.borrow_mut().value {
+                    text.drain(..marker.len);
                 }
             }
+
+            // The title is everything on the marker line
+            while let Some(inline) = paragraph.first_child() {
+                let is_break = matches!(
+                    inline.data.borrow().value,
+                    NodeValue::SoftBreak | NodeValue::LineBreak
+                );
+                inline.detach();
+                if is_break {
+                    break;
+                }
+                title_html.push_str(&self.format_node(inline));
+            }
+            if paragraph.first_child().is_none() {
+                paragraph.detach();
+            }
+            title_html = title_html.trim().to_string();
         }
 
-        headings
+        if title_html.is_empty() {
+            title_html = escape_html(&default_callout_title(&marker.kind));
+        }
+
+        let mut body = String::new();
+        for child in node.children() {
+            body.push_str(&self.format_node(child));
+        }
+
+        let kind = &marker.kind;
+        match marker.fold {
+            None => format!(
+                "<div class=\"callout callout-{kind}\" data-callout=\"{kind}\">\n\
+                 <div class=\"callout-title\">{title_html}</div>\n\
+                 <div class=\"callout-content\">\n{body}</div>\n</div>\n"
+            ),
+            Some(fold) => {
+                let (state, open) = match fold {
+                    CalloutFold::Expanded => ("expanded", " open"),
+                    CalloutFold::Collapsed => ("collapsed", ""),
+                };
+                format!(
+                    "<details class=\"callout callout-{kind}\" data-callout=\"{kind}\" \
+                     data-callout-fold=\"{state}\"{open}>\n\
+                     <summary class=\"callout-title\">{title_html}</summary>\n\
+                     <div class=\"callout-content\">\n{body}</div>\n</details>\n"
+                )
+            }
+        }
     }
 
-    /// Extract code blocks from markdown
-    fn extract_code_blocks(&self, markdown: &str) -> Vec<CodeBlock> {
-        let mut code_blocks = Vec::new();
-        let mut in_code_block = false;
-        let mut current_lang = None;
-        let mut current_code = String::new();
-
-        for line in markdown.lines() {
-            if line.trim_start().starts_with("```") {
-                if in_code_block {
-                    // End of code block
-                    code_blocks.push(CodeBlock {
-                        language: current_lang.take(),
-                        code: current_code.clone(),
+    /// Format a single AST node (and its children) as HTML
+    fn format_node<'a>(&self, node: &'a AstNode<'a>) -> String {
+        let mut html = Vec::new();
+        format_html(node, &self.options, &mut html).expect("Writing to a Vec cannot fail");
+        String::from_utf8(html).expect("comrak produced invalid UTF-8")
+    }
+
+    /// Find callout blockquotes in document order
+    fn find_callouts<'a>(&self, root: &'a AstNode<'a>) -> Vec<(&'a AstNode<'a>, CalloutMarker)> {
+        let mut callouts = Vec::new();
+
+        for node in root.descendants() {
+            if !matches!(node.data.borrow().value, NodeValue::BlockQuote) {
+                continue;
+            }
+
+            let Some(first) = node.first_child().and_then(|p| p.first_child()) else {
+                continue;
+            };
+            if !matches!(
+                node.first_child().unwrap().data.borrow().value,
+                NodeValue::Paragraph
+            ) {
+                continue;
+            }
+
+            if let NodeValue::Text(ref text) = first.data.borrow().value {
+                if let Some(marker) = self.parse_callout_marker(text) {
+                    callouts.push((node, marker));
+                }
+            }
+        }
+
+        callouts
+    }
+
+    /// Parse a `[!type]+` marker at the start of some text
+    fn parse_callout_marker(&self, text: &str) -> Option<CalloutMarker> {
+        let cap = self.callout_regex.captures(text)?;
+        let fold = match cap.get(2).map(|m| m.as_str()) {
+            Some("+") => Some(CalloutFold::Expanded),
+            Some("-") => Some(CalloutFold::Collapsed),
+            _ => None,
+        };
+
+        Some(CalloutMarker {
+            kind: cap[1].to_lowercase(),
+            fold,
+            len: cap.get(0).unwrap().end(),
+        })
+    }
+
+    /// Extract task list items from the parsed AST
+    fn extract_tasks<'a>(&self, root: &'a AstNode<'a>) -> Vec<Task> {
+        let mut tasks = Vec::new();
+        let mut heading = None;
+
+        for node in root.descendants() {
+            let data = node.data.borrow();
+            match data.value {
+                NodeValue::Heading(_) => heading = Some(collect_text(node).trim().to_string()),
+                NodeValue::TaskItem(symbol) => {
+                    let text = node
+                        .first_child()
+                        .filter(|p| matches!(p.data.borrow().value, NodeValue::Paragraph))
+                        .map(|p| collect_text(p).trim().to_string())
+                        .unwrap_or_default();
+                    let depth = node
+                        .ancestors()
+                        .filter(|n| matches!(n.data.borrow().value, NodeValue::List(_)))
+                        .count()
+                        .saturating_sub(1);
+                    let metadata = self.task_metadata.parse(&text);
+
+                    tasks.push(Task {
+                        line: data.sourcepos.start.line,
+                        text,
+                        checked: symbol.is_some(),
+                        depth,
+                        heading: heading.clone(),
+                        due: metadata.due,
+                        priority: metadata.priority,
+                        tags: metadata.tags,
                     });
-                    current_code.clear();
-                    in_code_block = false;
-                } else {
-                    // Start of code block
-                    let lang = line.trim_start().trim_start_matches("```").trim();
-                    current_lang = if lang.is_empty() {
-                        None
-                    } else {
-                        Some(lang.to_string())
-                    };
-                    in_code_block = true;
                 }
-            } else if in_code_block {
-                current_code.push_str(line);
-                current_code.push('\n');
+                _ => {}
+            }
+        }
+
+        tasks
+    }
+
+    /// Extract the paragraphs and list items that can carry a block id
+    pub(crate) fn parse_blocks(&self, markdown: &str) -> Vec<Block> {
+        let source = expand_directives(markdown);
+        let arena = Arena::new();
+        let root = parse_document(&arena, &source, &self.options);
+        self.extract_blocks(root)
+    }
+
+    /// Extract referenceable blocks from the parsed AST
+    fn extract_blocks<'a>(&self, root: &'a AstNode<'a>) -> Vec<Block> {
+        let mut blocks = Vec::new();
+
+        for node in root.descendants() {
+            if !matches!(node.data.borrow().value, NodeValue::Paragraph) {
+                continue;
+            }
+
+            let paragraph_pos = node.data.borrow().sourcepos;
+            let mut text = collect_text(node).trim().to_string();
+
+            let mut id = None;
+            let last_is_text = node
+                .last_child()
+                .is_some_and(|n| matches!(n.data.borrow().value, NodeValue::Text(_)));
+            if last_is_text {
+                if let Some(cap) = self.block_id_regex.captures(&text) {
+                    id = Some(cap[1].to_string());
+                    text.truncate(cap.get(0).unwrap().start());
+                    text = text.trim_end().to_string();
+                }
+            }
+            if text.is_empty() {
+                continue;
+            }
+
+            // The first paragraph of a list item stands for the whole item
+            let item = node.parent().filter(|parent| {
+                parent
+                    .first_child()
+                    .is_some_and(|first| first.same_node(node))
+                    && matches!(
+                        parent.data.borrow().value,
+                        NodeValue::Item(_) | NodeValue::TaskItem(_)
+                    )
+            });
+            let (kind, pos) = match item {
+                Some(item) => (BlockKind::ListItem, item.data.borrow().sourcepos),
+                None => (BlockKind::Paragraph, paragraph_pos),
+            };
+
+            blocks.push(Block {
+                id,
+                kind,
+                text,
+                start_line: pos.start.line,
+                end_line: pos.end.line,
+                marker_line: paragraph_pos.end.line,
+            });
+        }
+
+        blocks
+    }
+
+    /// Extract callouts from the parsed AST
+    fn extract_callouts<'a>(&self, root: &'a AstNode<'a>, source: &str) -> Vec<Callout> {
+        let lines: Vec<&str> = source.lines().collect();
+
+        self.find_callouts(root)
+            .into_iter()
+            .map(|(node, marker)| {
+                let sourcepos = node.data.borrow().sourcepos;
+                let (start_line, end_line) = (sourcepos.start.line, sourcepos.end.line);
+
+                let title = lines
+                    .get(start_line - 1)
+                    .and_then(|line| line.find("[!").map(|i| &line[i..]))
+                    .and_then(|rest| rest.get(marker.len..))
+                    .map(|rest| rest.trim())
+                    .filter(|rest| !rest.is_empty())
+                    .map(str::to_string)
+                    .unwrap_or_else(|| default_callout_title(&marker.kind));
+
+                let content = lines
+                    .get(start_line..end_line.min(lines.len()))
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|line| strip_quote_marker(line))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                Callout {
+                    kind: marker.kind,
+                    title,
+                    fold: marker.fold,
+                    content,
+                    start_line,
+                    end_line,
+                }
+            })
+            .collect()
+    }
+
+    /// Extract headings from the parsed AST
+    fn extract_headings<'a>(&self, root: &'a AstNode<'a>) -> Vec<Heading> {
+        let mut headings = Vec::new();
+
+        for node in root.descendants() {
+            let level = match node.data.borrow().value {
+                NodeValue::Heading(ref heading) => heading.level,
+                _ => continue,
+            };
+            headings.push(Heading {
+                level,
+                text: collect_text(node).trim().to_string(),
+                id: format!("heading-{}", headings.len()),
+            });
+        }
+
+        headings
+    }
+
+    /// Extract code blocks from the parsed AST
+    fn extract_code_blocks<'a>(&self, root: &'a AstNode<'a>) -> Vec<CodeBlock> {
+        let mut code_blocks = Vec::new();
+
+        for node in root.descendants() {
+            let data = node.data.borrow();
+            if let NodeValue::CodeBlock(ref block) = data.value {
+                let (language, attributes) = split_info_string(&block.info);
+
+                code_blocks.push(CodeBlock {
+                    language,
+                    attributes,
+                    code: block.literal.clone(),
+                    fenced: block.fenced,
+                    start_line: data.sourcepos.start.line,
+                    end_line: data.sourcepos.end.line,
+                });
             }
         }
 
         code_blocks
     }
+
+    /// Extract inline, display, and fenced math from the parsed AST
+    fn extract_math<'a>(&self, root: &'a AstNode<'a>) -> Vec<MathExpression> {
+        let mut math = Vec::new();
+
+        for node in root.descendants() {
+            let data = node.data.borrow();
+            match data.value {
+                NodeValue::Math(ref m) => math.push(MathExpression {
+                    literal: m.literal.clone(),
+                    display: m.display_math,
+                    line: data.sourcepos.start.line,
+                }),
+                NodeValue::CodeBlock(ref block) if block.info.trim() == "math" => {
+                    math.push(MathExpression {
+                        literal: block.literal.clone(),
+                        display: true,
+                        line: data.sourcepos.start.line,
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        math
+    }
+}
+
+/// Rewrite `:::type Title` ... `:::` directive blocks as `> [!type] Title` callouts
+///
+/// Line numbers are preserved so source positions still refer to the original text.
+fn expand_directives(markdown: &str) -> Cow<'_, str> {
+    if !markdown.contains(":::") {
+        return Cow::Borrowed(markdown);
+    }
+
+    let mut out = String::with_capacity(markdown.len() + 64);
+    // Colon counts of the currently open directives
+    let mut open: Vec<usize> = Vec::new();
+    // Character and length of the currently open code fence
+    let mut fence: Option<(char, usize)> = None;
+
+    for line in markdown.split_inclusive('\n') {
+        let (text, ending) = match line.strip_suffix('\n') {
+            Some(text) => match text.strip_suffix('\r') {
+                Some(text) => (text, "\r\n"),
+                None => (text, "\n"),
+            },
+            None => (line, ""),
+        };
+        let trimmed = text.trim_start();
+
+        if let Some((ch, len)) = fence {
+            let run = trimmed.chars().take_while(|c| *c == ch).count();
+            if run >= len && trimmed[run..].trim().is_empty() {
+                fence = None;
+            }
+        } else if let Some(ch) = ['`', '~'].into_iter().find(|ch| trimmed.starts_with(*ch)) {
+            let run = trimmed.chars().take_while(|c| *c == ch).count();
+            if run >= 3 {
+                fence = Some((ch, run));
+            }
+        } else if trimmed.starts_with(":::") {
+            let colons = trimmed.chars().take_while(|c| *c == ':').count();
+            let rest = trimmed[colons..].trim();
+
+            if rest.is_empty() {
+                if open.last().is_some_and(|n| colons >= *n) {
+                    open.pop();
+                    out.push_str(&"> ".repeat(open.len()));
+                    out.push('>');
+                    out.push_str(ending);
+                    continue;
+                }
+            } else {
+                let (kind, title) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let valid = kind.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && kind
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+                if valid {
+                    out.push_str(&"> ".repeat(open.len()));
+                    out.push_str(&format!("> [!{}] {}", kind, title.trim()));
+                    out.push_str(ending);
+                    open.push(colons);
+                    continue;
+                }
+            }
+        }
+
+        out.push_str(&"> ".repeat(open.len()));
+        out.push_str(text);
+        out.push_str(ending);
+    }
+
+    Cow::Owned(out)
+}
+
+/// Collect the plain text of a node, with line breaks as spaces
+fn collect_text<'a>(node: &'a AstNode<'a>) -> String {
+    let mut text = String::new();
+    for n in node.descendants() {
+        match n.data.borrow().value {
+            NodeValue::Text(ref literal) => text.push_str(literal),
+            NodeValue::Code(ref code) => text.push_str(&code.literal),
+            NodeValue::Math(ref math) => text.push_str(&math.literal),
+            NodeValue::SoftBreak | NodeValue::LineBreak => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Strip one level of blockquote marker from a source line
+fn strip_quote_marker(line: &str) -> &str {
+    let trimmed = line.trim_start();
+    match trimmed.strip_prefix('>') {
+        Some(rest) => rest.strip_prefix(' ').unwrap_or(rest),
+        None => line,
+    }
+}
+
+/// Default callout title: the type with its first letter capitalized
+fn default_callout_title(kind: &str) -> String {
+    let mut chars = kind.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Placeholder text standing in for a rendered callout
+fn callout_placeholder(index: usize) -> String {
+    format!("\u{E000}arke-callout-{}\u{E000}", index)
+}
+
+/// Escape text for inclusion in HTML
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Split a fenced code block info string into the language and remaining attributes
+fn split_info_string(info: &str) -> (Option<String>, Option<String>) {
+    let info = info.trim();
+    if info.is_empty() {
+        return (None, None);
+    }
+
+    match info.split_once(char::is_whitespace) {
+        Some((lang, rest)) => {
+            let rest = rest.trim();
+            let attributes = if rest.is_empty() {
+                None
+            } else {
+                Some(rest.to_string())
+            };
+            (Some(lang.to_string()), attributes)
+        }
+        None => (Some(info.to_string()), None),
+    }
 }
 
 impl Default for MarkdownParser {
@@ -165,6 +807,14 @@ mod tests {
         assert_eq!(parsed.headings[0].text, "Title");
         assert_eq!(parsed.headings[1].level, 2);
         assert_eq!(parsed.headings[2].level, 3);
+
+        // Comment lines in code and setext headings come from the AST, not line prefixes
+        let md = "```sh\n# install\n```\n\nSetext *title*\n---\n\n#tag";
+        let parsed = parser.parse(md).unwrap();
+        assert_eq!(parsed.headings.len(), 1);
+        assert_eq!(parsed.headings[0].level, 2);
+        assert_eq!(parsed.headings[0].text, "Setext title");
+        assert_eq!(parsed.headings[0].id, "heading-0");
     }
 
     #[test]
@@ -176,6 +826,51 @@ mod tests {
         assert_eq!(parsed.code_blocks.len(), 2);
         assert_eq!(parsed.code_blocks[0].language, Some("rust".to_string()));
         assert_eq!(parsed.code_blocks[1].language, Some("python".to_string()));
+        assert_eq!(parsed.code_blocks[0].code, "fn main() {}\n");
+        assert_eq!(parsed.code_blocks[0].start_line, 3);
+        assert_eq!(parsed.code_blocks[0].end_line, 5);
+    }
+
+    #[test]
+    fn test_code_block_info_string_attributes() {
+        let parser = MarkdownParser::new();
+        let md = "```rust title=\"x.rs\"\nlet x = 1;\n```";
+        let parsed = parser.parse(md).unwrap();
+
+        assert_eq!(parsed.code_blocks.len(), 1);
+        assert_eq!(parsed.code_blocks[0].language, Some("rust".to_string()));
+        assert_eq!(
+            parsed.code_blocks[0].attributes,
+            Some("title=\"x.rs\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_code_block_tildes_and_indented() {
+        let parser = MarkdownParser::new();
+        let md = "~~~python\nprint('hi')\n~~~\n\nText\n\n    indented code\n";
+        let parsed = parser.parse(md).unwrap();
+
+        assert_eq!(parsed.code_blocks.len(), 2);
+        assert_eq!(parsed.code_blocks[0].language, Some("python".to_string()));
+        assert!(parsed.code_blocks[0].fenced);
+        assert_eq!(parsed.code_blocks[1].language, None);
+        assert!(!parsed.code_blocks[1].fenced);
+        assert_eq!(parsed.code_blocks[1].code, "indented code\n");
+        assert_eq!(parsed.code_blocks[1].start_line, 7);
+    }
+
+    #[test]
+    fn test_code_block_nested_fences() {
+        let parser = MarkdownParser::new();
+        let md = "````markdown\n```rust\nfn main() {}\n```\n````\n\n```js\nx\n```";
+        let parsed = parser.parse(md).unwrap();
+
+        assert_eq!(parsed.code_blocks.len(), 2);
+        assert_eq!(parsed.code_blocks[0].language, Some("markdown".to_string()));
+        assert_eq!(parsed.code_blocks[0].code, "```rust\nfn main() {}\n```\n");
+        assert_eq!(parsed.code_blocks[0].end_line, 5);
+        assert_eq!(parsed.code_blocks[1].language, Some("js".to_string()));
     }
 
     #[test]
@@ -197,4 +892,158 @@ mod tests {
         let html = parser.parse_to_html(task_md);
         assert!(html.contains("checkbox") || html.contains("task"));
     }
+
+    #[test]
+    fn test_extract_math() {
+        let parser = MarkdownParser::new();
+        let md = "Inline $x_1 + x_2$ here.\n\n$$\\sum_i a_i$$\n\n```math\nE = mc^2\n```";
+        let parsed = parser.parse(md).unwrap();
+
+        assert_eq!(parsed.math.len(), 3);
+        assert_eq!(parsed.math[0].literal, "x_1 + x_2");
+        assert!(!parsed.math[0].display);
+        assert_eq!(parsed.math[1].literal, "\\sum_i a_i");
+        assert!(parsed.math[1].display);
+        assert_eq!(parsed.math[1].line, 3);
+        assert_eq!(parsed.math[2].literal, "E = mc^2\n");
+        assert!(parsed.math[2].display);
+
+        assert!(parsed.html.contains("data-math-style=\"inline\""));
+        assert!(parsed.html.contains("data-math-style=\"display\""));
+        assert!(!parsed.html.contains("<em>"));
+    }
+
+    #[test]
+    fn test_dollar_amounts_are_not_math() {
+        let parser = MarkdownParser::new();
+        let md = "It costs $5 and $10, or between $5-$10 total.";
+        let parsed = parser.parse(md).unwrap();
+
+        assert!(parsed.math.is_empty());
+        assert!(parsed.html.contains("$5 and $10"));
+    }
+
+    #[test]
+    fn test_extract_callouts() {
+        let parser = MarkdownParser::new();
+        let md =
+            "> [!Warning] Be careful\n> Body text\n> more\n\n> [!tip]-\n> Hidden\n\n> plain quote";
+        let parsed = parser.parse(md).unwrap();
+
+        assert_eq!(parsed.callouts.len(), 2);
+        assert_eq!(parsed.callouts[0].kind, "warning");
+        assert_eq!(parsed.callouts[0].title, "Be careful");
+        assert_eq!(parsed.callouts[0].fold, None);
+        assert_eq!(parsed.callouts[0].content, "Body text\nmore");
+        assert_eq!(parsed.callouts[0].start_line, 1);
+        assert_eq!(parsed.callouts[0].end_line, 3);
+        assert_eq!(parsed.callouts[1].kind, "tip");
+        assert_eq!(parsed.callouts[1].title, "Tip");
+        assert_eq!(parsed.callouts[1].fold, Some(CalloutFold::Collapsed));
+    }
+
+    #[test]
+    fn test_render_callouts() {
+        let parser = MarkdownParser::new();
+        let md = "> [!note]+ **Heads** up\n> Body\n\n> plain quote";
+        let html = parser.parse_to_html(md);
+
+        assert!(html.contains("class=\"callout callout-note\""));
+        assert!(html.contains("data-callout-fold=\"expanded\" open"));
+        assert!(
+            html.contains("<summary class=\"callout-title\"><strong>Heads</strong> up</summary>")
+        );
+        assert!(html.contains("<p>Body</p>"));
+        assert!(!html.contains("[!note]"));
+        assert!(html.contains("<blockquote>\n<p>plain quote</p>"));
+    }
+
+    #[test]
+    fn test_directive_callouts() {
+        let parser = MarkdownParser::new();
+        let md = "Intro\n\n:::info Read this\nInside\n\n```\n:::\n```\n:::\n\nAfter";
+        let parsed = parser.parse(md).unwrap();
+
+        assert_eq!(parsed.callouts.len(), 1);
+        assert_eq!(parsed.callouts[0].kind, "info");
+        assert_eq!(parsed.callouts[0].title, "Read this");
+        assert_eq!(parsed.callouts[0].start_line, 3);
+        assert_eq!(parsed.callouts[0].end_line, 9);
+        assert_eq!(parsed.code_blocks[0].code, ":::\n");
+        assert!(parsed
+            .html
+            .contains("<div class=\"callout-title\">Read this</div>"));
+        assert!(parsed.html.contains("<p>After</p>"));
+    }
+
+    #[test]
+    fn test_nested_callouts() {
+        let parser = MarkdownParser::new();
+        let md = "> [!note] Outer\n> > [!danger] Inner\n> > Deep";
+        let parsed = parser.parse(md).unwrap();
+
+        assert_eq!(parsed.callouts.len(), 2);
+        assert_eq!(parsed.callouts[1].kind, "danger");
+        let outer = parsed.html.find("callout-note").unwrap();
+        let inner = parsed.html.find("callout-danger").unwrap();
+        assert!(outer < inner);
+        assert!(!parsed.html.contains('\u{E000}'));
+    }
+
+    #[test]
+    fn test_extract_tasks() {
+        let parser = MarkdownParser::new();
+        let md = "# Project\n\n- [ ] Write spec 📅 2025-11-01 ⏫ #work\n  - [x] Outline\n\n## Later\n\n1. [ ] Ship `v1`\n- plain item";
+        let parsed = parser.parse(md).unwrap();
+
+        assert_eq!(parsed.tasks.len(), 3);
+        let spec = &parsed.tasks[0];
+        assert_eq!(spec.line, 3);
+        assert_eq!(spec.text, "Write spec 📅 2025-11-01 ⏫ #work");
+        assert!(!spec.checked);
+        assert_eq!(spec.depth, 0);
+        assert_eq!(spec.heading.as_deref(), Some("Project"));
+        assert_eq!(spec.due, chrono::NaiveDate::from_ymd_opt(2025, 11, 1));
+        assert_eq!(spec.priority, Some(crate::tasks::TaskPriority::High));
+        assert_eq!(spec.tags, vec!["work"]);
+
+        assert!(parsed.tasks[1].checked);
+        assert_eq!(parsed.tasks[1].depth, 1);
+        assert_eq!(parsed.tasks[1].text, "Outline");
+        assert_eq!(parsed.tasks[2].text, "Ship v1");
+        assert_eq!(parsed.tasks[2].heading.as_deref(), Some("Later"));
+    }
+
+    #[test]
+    fn test_extract_block_ids() {
+        let parser = MarkdownParser::new();
+        let md = "First paragraph\nspans lines ^intro\n\n- item one ^item-1\n  - nested\n- item two\n\nNo id here\n\n`code ^notid`";
+        let parsed = parser.parse(md).unwrap();
+
+        assert_eq!(parsed.block_ids.len(), 2);
+        assert_eq!(parsed.block_ids[0].id, "intro");
+        assert_eq!(parsed.block_ids[0].kind, BlockKind::Paragraph);
+        assert_eq!(parsed.block_ids[0].text, "First paragraph spans lines");
+        assert_eq!(parsed.block_ids[0].start_line, 1);
+        assert_eq!(parsed.block_ids[0].end_line, 2);
+        assert_eq!(parsed.block_ids[1].id, "item-1");
+        assert_eq!(parsed.block_ids[1].kind, BlockKind::ListItem);
+        assert_eq!(parsed.block_ids[1].text, "item one");
+        assert_eq!(parsed.block_ids[1].end_line, 5);
+    }
+
+    #[test]
+    fn test_parser_options() {
+        let options = ParserOptions {
+            hard_breaks: true,
+            math: false,
+            ..Default::default()
+        };
+        let parser = MarkdownParser::with_options(&options);
+        let parsed = parser.parse("one\ntwo $x$").unwrap();
+
+        assert!(parsed.html.contains("<br />"));
+        assert!(parsed.math.is_empty());
+        assert_eq!(MarkdownParser::new().parse("$x$").unwrap().math.len(), 1);
+    }
 }
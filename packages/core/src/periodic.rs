@@ -0,0 +1,439 @@
+//! Daily, weekly, monthly and yearly notes
+//!
+//! A periodic note lives at its period's folder joined with the period's first
+//! day, formatted by the configured strftime-style pattern. Formatting uses
+//! chrono's built-in English names, so `%B` gives `January` and `%a` gives
+//! `Mon` whatever the host locale is.
+
+use crate::config::{PeriodSettings, PeriodicSettings};
+use crate::error::{ArkeError, Result};
+use crate::storage::{is_contained, Storage};
+use crate::templates::TemplateValues;
+use crate::vault::{Note, NoteVersion, Vault};
+use chrono::{Datelike, Days, Months, NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The span of time a periodic note covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl Period {
+    /// First day of the period containing `date`; weeks start on Monday, like ISO weeks
+    pub fn start(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Daily => date,
+            Period::Weekly => date - Days::new(date.weekday().num_days_from_monday().into()),
+            Period::Monthly => date.with_day(1).unwrap(),
+            Period::Yearly => date.with_ordinal(1).unwrap(),
+        }
+    }
+
+    /// First day of the period after the one containing `date`
+    pub fn next(self, date: NaiveDate) -> Option<NaiveDate> {
+        let start = self.start(date);
+        match self {
+            Period::Daily => start.checked_add_days(Days::new(1)),
+            Period::Weekly => start.checked_add_days(Days::new(7)),
+            Period::Monthly => start.checked_add_months(Months::new(1)),
+            Period::Yearly => start.checked_add_months(Months::new(12)),
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            Period::Daily => "daily",
+            Period::Weekly => "weekly",
+            Period::Monthly => "monthly",
+            Period::Yearly => "yearly",
+        }
+    }
+
+    fn default_format(self) -> &'static str {
+        match self {
+            Period::Daily => "%Y-%m-%d",
+            Period::Weekly => "%G-W%V",
+            Period::Monthly => "%Y-%m",
+            Period::Yearly => "%Y",
+        }
+    }
+
+    fn settings(self, settings: &PeriodicSettings) -> &PeriodSettings {
+        match self {
+            Period::Daily => &settings.daily,
+            Period::Weekly => &settings.weekly,
+            Period::Monthly => &settings.monthly,
+            Period::Yearly => &settings.yearly,
+        }
+    }
+}
+
+/// An existing periodic note
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeriodicNote {
+    pub period: Period,
+    /// First day of the period the note covers
+    pub date: NaiveDate,
+    pub path: PathBuf,
+}
+
+/// How one period's notes are named
+pub(crate) struct PeriodFormat<'a> {
+    period: Period,
+    settings: &'a PeriodSettings,
+    format: &'a str,
+}
+
+impl<'a> PeriodFormat<'a> {
+    pub(crate) fn new(period: Period, settings: &'a PeriodicSettings) -> Self {
+        let settings = period.settings(settings);
+        Self {
+            period,
+            settings,
+            format: settings
+                .format
+                .as_deref()
+                .unwrap_or(period.default_format()),
+        }
+    }
+
+    /// Path of the note for the period containing `date`
+    pub(crate) fn path(&self, date: NaiveDate) -> Result<PathBuf> {
+        let mut name = String::new();
+        // Fails on invalid specifiers and on fields a date can't fill, such as `%H`
+        write!(name, "{}", self.period.start(date).format(self.format))
+            .map_err(|_| self.invalid("not a valid date format"))?;
+        if name.trim().is_empty() || !is_contained(Path::new(&name)) {
+            return Err(self.invalid("must produce a file name inside the folder"));
+        }
+        let folder = Path::new(&self.settings.folder);
+        if !is_contained(folder) {
+            return Err(ArkeError::Config {
+                key: format!("periodic.{}.folder", self.period.key()),
+                message: format!("{:?} must be a folder inside the vault", folder),
+            });
+        }
+        Ok(folder.join(format!("{}.md", name)))
+    }
+
+    /// The period start a note path was named for, if it matches the format
+    pub(crate) fn parse(&self, path: &Path) -> Option<NaiveDate> {
+        let name = path
+            .strip_prefix(&self.settings.folder)
+            .ok()?
+            .to_str()?
+            .strip_suffix(".md")?
+            .replace('\\', "/");
+
+        // Complete the date with the fields the period leaves out, then check
+        // the date names this very path
+        let (extra_format, extra) = match self.period {
+            Period::Daily => ("", ""),
+            Period::Weekly => ("|%u", "|1"),
+            Period::Monthly => ("|%d", "|1"),
+            Period::Yearly => ("|%m|%d", "|1|1"),
+        };
+        let date = NaiveDate::parse_from_str(
+            &format!("{}{}", name, extra),
+            &format!("{}{}", self.format, extra_format),
+        )
+        .ok()?;
+        (self.path(date).ok()? == path).then_some(date)
+    }
+
+    /// Name of the template new notes are created from
+    pub(crate) fn template(&self) -> Option<&'a str> {
+        self.settings.template.as_deref()
+    }
+
+    fn invalid(&self, message: &str) -> ArkeError {
+        ArkeError::Config {
+            key: format!("periodic.{}.format", self.period.key()),
+            message: format!("{:?} is {}", self.format, message),
+        }
+    }
+}
+
+impl<S: Storage> Vault<S> {
+    /// Path of the periodic note for the period containing `date`, whether or not it exists
+    pub fn periodic_note_path(&self, period: Period, date: NaiveDate) -> Result<PathBuf> {
+        PeriodFormat::new(period, &self.settings().periodic).path(date)
+    }
+
+    /// Open the periodic note for the period containing `date`, creating it if needed
+    ///
+    /// A new note is rendered from the period's template, if one is
+    /// configured, with `{{date}}` as the period's first day; otherwise it
+    /// starts empty.
+    pub fn open_periodic_note(&mut self, period: Period, date: NaiveDate) -> Result<Arc<Note>> {
+        let format = PeriodFormat::new(period, &self.settings().periodic);
+        let path = format.path(date)?;
+        if !self.storage().exists(&path) {
+            let content = match format.template() {
+                Some(template) => {
+                    let values = TemplateValues::new(period.start(date).and_time(NaiveTime::MIN));
+                    self.render_template(template, &path, &values)?
+                }
+                None => String::new(),
+            };
+            // Created meanwhile, by a sync client or another window; keep theirs
+            match self.write_note_if(&path, &content, &NoteVersion::Absent) {
+                Ok(_) | Err(ArkeError::Conflict { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.read_note(&path)
+    }
+
+    /// Existing periodic notes for periods overlapping `from..=to`, oldest first
+    pub fn periodic_notes(
+        &self,
+        period: Period,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<PeriodicNote>> {
+        let from = period.start(from);
+        Ok(self
+            .all_periodic_notes(period)?
+            .into_iter()
+            .filter(|n| from <= n.date && n.date <= to)
+            .collect())
+    }
+
+    /// The closest existing periodic note before the period containing `date`
+    pub fn previous_periodic_note(
+        &self,
+        period: Period,
+        date: NaiveDate,
+    ) -> Result<Option<PeriodicNote>> {
+        let start = period.start(date);
+        Ok(self
+            .all_periodic_notes(period)?
+            .into_iter()
+            .rev()
+            .find(|n| n.date < start))
+    }
+
+    /// The closest existing periodic note after the period containing `date`
+    pub fn next_periodic_note(
+        &self,
+        period: Period,
+        date: NaiveDate,
+    ) -> Result<Option<PeriodicNote>> {
+        let start = period.start(date);
+        Ok(self
+            .all_periodic_notes(period)?
+            .into_iter()
+            .find(|n| n.date > start))
+    }
+
+    /// Every existing note of a period, oldest first
+    fn all_periodic_notes(&self, period: Period) -> Result<Vec<PeriodicNote>> {
+        let format = PeriodFormat::new(period, &self.settings().periodic);
+        let mut notes: Vec<PeriodicNote> = self
+            .list_files()?
+            .into_iter()
+            .filter_map(|path| {
+                let date = format.parse(&path)?;
+                Some(PeriodicNote { period, date, path })
+            })
+            .collect();
+        notes.sort_by_key(|n| n.date);
+        Ok(notes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, RacingStorage};
+    use crate::vault::VaultConfig;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn memory_vault(periodic: PeriodicSettings) -> Vault<MemoryStorage> {
+        let config = VaultConfig {
+            path: PathBuf::from("/memory"),
+            name: "Memory".to_string(),
+            watch: false,
+            settings: crate::VaultSettings {
+                periodic,
+                ..Default::default()
+            },
+        };
+        Vault::with_storage(config, MemoryStorage::new())
+    }
+
+    #[test]
+    fn test_paths() {
+        let mut settings = PeriodicSettings::default();
+        settings.daily.folder = "journal".to_string();
+        settings.daily.format = Some("%Y/%B/%d %a".to_string());
+        let day = date(2024, 12, 31);
+
+        let path = |period| PeriodFormat::new(period, &settings).path(day).unwrap();
+        assert_eq!(
+            path(Period::Daily),
+            PathBuf::from("journal/2024/December/31 Tue.md")
+        );
+        // The last days of 2024 fall in ISO week 1 of 2025
+        assert_eq!(path(Period::Weekly), PathBuf::from("2025-W01.md"));
+        assert_eq!(path(Period::Monthly), PathBuf::from("2024-12.md"));
+        assert_eq!(path(Period::Yearly), PathBuf::from("2024.md"));
+
+        for period in [
+            Period::Daily,
+            Period::Weekly,
+            Period::Monthly,
+            Period::Yearly,
+        ] {
+            let format = PeriodFormat::new(period, &settings);
+            assert_eq!(
+                format.parse(&path(period)),
+                Some(period.start(day)),
+                "{:?}",
+                period
+            );
+        }
+        assert_eq!(
+            PeriodFormat::new(Period::Daily, &settings).parse(Path::new("journal/notes.md")),
+            None
+        );
+
+        settings.monthly.format = Some("%Y-%m %H".to_string());
+        let err = PeriodFormat::new(Period::Monthly, &settings)
+            .path(day)
+            .unwrap_err();
+        assert!(matches!(err, ArkeError::Config { key, .. } if key == "periodic.monthly.format"));
+
+        // Neither the format nor the folder may lead outside the vault
+        for format in ["/%Y", "../%Y", "%Y/../../x", "./%Y"] {
+            settings.yearly.format = Some(format.to_string());
+            let err = PeriodFormat::new(Period::Yearly, &settings)
+                .path(day)
+                .unwrap_err();
+            assert!(
+                matches!(err, ArkeError::Config { ref key, .. } if key == "periodic.yearly.format"),
+                "{}",
+                format
+            );
+        }
+        settings.yearly.format = None;
+        for folder in ["/etc", "../outside", "notes/../.."] {
+            settings.yearly.folder = folder.to_string();
+            let err = PeriodFormat::new(Period::Yearly, &settings)
+                .path(day)
+                .unwrap_err();
+            assert!(
+                matches!(err, ArkeError::Config { ref key, .. } if key == "periodic.yearly.folder"),
+                "{}",
+                folder
+            );
+        }
+    }
+
+    #[test]
+    fn test_open_creates_from_template() {
+        let mut settings = PeriodicSettings::default();
+        settings.weekly.folder = "weeks".to_string();
+        settings.weekly.template = Some("week".to_string());
+        let mut vault = memory_vault(settings);
+        vault
+            .write_note(
+                Path::new("templates/week.md"),
+                "# {{title}}\n{{date:%b %-d}}\n",
+            )
+            .unwrap();
+
+        let note = vault
+            .open_periodic_note(Period::Weekly, date(2024, 3, 14))
+            .unwrap();
+        assert_eq!(note.path, PathBuf::from("weeks/2024-W11.md"));
+        assert_eq!(note.content, "# 2024-W11\nMar 11\n");
+
+        // An existing note is opened as is
+        vault.write_note(&note.path, "edited").unwrap();
+        let note = vault
+            .open_periodic_note(Period::Weekly, date(2024, 3, 11))
+            .unwrap();
+        assert_eq!(note.content, "edited");
+    }
+
+    #[test]
+    fn test_open_keeps_a_note_created_meanwhile() {
+        let config = VaultConfig {
+            path: PathBuf::from("/memory"),
+            name: "Memory".to_string(),
+            watch: false,
+            settings: Default::default(),
+        };
+        let storage = RacingStorage::new("2024-03-14.md", b"synced");
+        let mut vault = Vault::with_storage(config, storage);
+
+        let note = vault
+            .open_periodic_note(Period::Daily, date(2024, 3, 14))
+            .unwrap();
+        assert_eq!(note.content, "synced");
+    }
+
+    #[test]
+    fn test_adjacent_and_range() {
+        let mut vault = memory_vault(PeriodicSettings::default());
+        for day in [date(2024, 1, 5), date(2024, 1, 9), date(2024, 2, 1)] {
+            vault.open_periodic_note(Period::Daily, day).unwrap();
+        }
+        vault
+            .write_note(Path::new("2024-01-07 notes.md"), "")
+            .unwrap();
+
+        let dates =
+            |notes: Vec<PeriodicNote>| notes.into_iter().map(|n| n.date).collect::<Vec<_>>();
+        assert_eq!(
+            dates(
+                vault
+                    .periodic_notes(Period::Daily, date(2024, 1, 1), date(2024, 1, 31))
+                    .unwrap()
+            ),
+            vec![date(2024, 1, 5), date(2024, 1, 9)]
+        );
+
+        let previous = vault
+            .previous_periodic_note(Period::Daily, date(2024, 1, 9))
+            .unwrap()
+            .unwrap();
+        assert_eq!(previous.path, PathBuf::from("2024-01-05.md"));
+        let next = vault
+            .next_periodic_note(Period::Daily, date(2024, 1, 9))
+            .unwrap()
+            .unwrap();
+        assert_eq!(next.date, date(2024, 2, 1));
+        assert!(vault
+            .next_periodic_note(Period::Daily, date(2024, 2, 1))
+            .unwrap()
+            .is_none());
+
+        // A monthly range includes the month the start date falls in
+        vault
+            .open_periodic_note(Period::Monthly, date(2024, 1, 20))
+            .unwrap();
+        assert_eq!(
+            dates(
+                vault
+                    .periodic_notes(Period::Monthly, date(2024, 1, 15), date(2024, 3, 1))
+                    .unwrap()
+            ),
+            vec![date(2024, 1, 1)]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! Thread-safe shared vault handle
+//!
+//! [`SharedVault`] lets command handlers, a file watcher and an indexer use one
+//! vault from different threads. Reads take a shared lock and run concurrently;
+//! they go through the vault's own note cache, which has interior locking.
+//! Writes take the exclusive lock, so they're serialized.
+
+use crate::batch::Batch;
+use crate::error::Result;
+use crate::events::EventBus;
+use crate::storage::{NativeStorage, Storage};
+use crate::trash::TrashEntry;
+use crate::vault::{Note, Vault};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Cloneable handle to a vault, usable from many threads at once
+///
+/// Change events are delivered once the lock guarding the change is
+/// released, so listeners can read or write the vault themselves.
+pub struct SharedVault<S: Storage = NativeStorage> {
+    inner: Arc<RwLock<Vault<S>>>,
+    events: EventBus,
+}
+
+impl<S: Storage> Clone for SharedVault<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<S: Storage> From<Vault<S>> for SharedVault<S> {
+    fn from(vault: Vault<S>) -> Self {
+        Self::new(vault)
+    }
+}
+
+impl<S: Storage> SharedVault<S> {
+    /// Wrap a vault; its events are delivered once each lock is released
+    pub fn new(vault: Vault<S>) -> Self {
+        let events = vault.events().clone();
+        events.defer();
+        Self {
+            inner: Arc::new(RwLock::new(vault)),
+            events,
+        }
+    }
+
+    /// Lock the vault for reading; other readers aren't blocked
+    pub fn read(&self) -> VaultReadGuard<'_, S> {
+        // A writer that panicked may have left storage half-changed (an unfinished
+        // batch keeps its journal and blocks new batches until `recover_batch`),
+        // or a cached note whose file changed, which the cache drops unless the
+        // size and mtime happen to match. That is tolerated rather than failing
+        // every later call, so poisoning is ignored.
+        VaultReadGuard {
+            guard: Some(self.inner.read().unwrap_or_else(PoisonError::into_inner)),
+            events: &self.events,
+        }
+    }
+
+    /// Lock the vault for writing, waiting for readers and other writers
+    pub fn write(&self) -> VaultWriteGuard<'_, S> {
+        VaultWriteGuard {
+            guard: Some(self.inner.write().unwrap_or_else(PoisonError::into_inner)),
+            events: &self.events,
+        }
+    }
+
+    /// The bus the vault emits change events on
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    /// List all markdown files in the vault
+    pub fn list_files(&self) -> Result<Vec<PathBuf>> {
+        self.read().list_files()
+    }
+
+    /// Read a note, see [`Vault::read_note`]
+    pub fn read_note<P: AsRef<Path>>(&self, path: P) -> Result<Arc<Note>> {
+        self.read().read_note(path)
+    }
+
+    /// Write a note, see [`Vault::write_note`]
+    pub fn write_note(&self, path: &Path, content: &str) -> Result<()> {
+        self.write().write_note(path, content)
+    }
+
+    /// Rename/move a note, see [`Vault::rename_note`]
+    pub fn rename_note(&self, old_path: &Path, new_path: &Path) -> Result<()> {
+        self.write().rename_note(old_path, new_path)
+    }
+
+    /// Delete a note by moving it to the trash, see [`Vault::delete_note`]
+    pub fn delete_note(&self, path: &Path) -> Result<TrashEntry> {
+        self.write().delete_note(path)
+    }
+
+    /// Apply a batch of changes atomically, see [`Vault::apply_batch`]
+    pub fn apply_batch(&self, batch: &Batch) -> Result<Vec<TrashEntry>> {
+        self.write().apply_batch(batch)
+    }
+
+    /// Handle a file watcher event, see [`Vault::handle_watch_event`]
+    #[cfg(feature = "native")]
+    pub fn handle_watch_event(&self, event: &notify::Event) {
+        self.read().handle_watch_event(event)
+    }
+}
+
+/// Shared access to a [`SharedVault`]; queued events are delivered when it's dropped
+pub struct VaultReadGuard<'a, S: Storage> {
+    guard: Option<RwLockReadGuard<'a, Vault<S>>>,
+    events: &'a EventBus,
+}
+
+impl<S: Storage> Deref for VaultReadGuard<'_, S> {
+    type Target = Vault<S>;
+
+    fn deref(&self) -> &Vault<S> {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<S: Storage> Drop for VaultReadGuard<'_, S> {
+    fn drop(&mut self) {
+        self.guard = None;
+        self.events.flush();
+    }
+}
+
+/// Exclusive access to a [`SharedVault`]; queued events are delivered when it's dropped
+pub struct VaultWriteGuard<'a, S: Storage> {
+    guard: Option<RwLockWriteGuard<'a, Vault<S>>>,
+    events: &'a EventBus,
+}
+
+impl<S: Storage> Deref for VaultWriteGuard<'_, S> {
+    type Target = Vault<S>;
+
+    fn deref(&self) -> &Vault<S> {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<S: Storage> DerefMut for VaultWriteGuard<'_, S> {
+    fn deref_mut(&mut self) -> &mut Vault<S> {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<S: Storage> Drop for VaultWriteGuard<'_, S> {
+    fn drop(&mut self) {
+        self.guard = None;
+        self.events.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::VaultEvent;
+    use crate::storage::MemoryStorage;
+    use crate::vault::VaultConfig;
+    use std::sync::{Barrier, Mutex};
+    use std::thread;
+
+    fn shared_vault() -> SharedVault<MemoryStorage> {
+        let config = VaultConfig {
+            path: PathBuf::from("/memory"),
+            name: "Memory".to_string(),
+            watch: false,
+            settings: Default::default(),
+        };
+        Vault::with_storage(config, MemoryStorage::new()).into()
+    }
+
+    #[test]
+    fn test_handle_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync + Clone>() {}
+        assert_send_sync::<SharedVault>();
+        assert_send_sync::<SharedVault<MemoryStorage>>();
+    }
+
+    #[test]
+    fn test_concurrent_reads_and_writes() {
+        let vault = shared_vault();
+        vault.write_note(Path::new("a.md"), "0").unwrap();
+
+        let threads = 8;
+        let barrier = Arc::new(Barrier::new(threads));
+        let handles: Vec<_> = (0..threads)
+            .map(|i| {
+                let vault = vault.clone();
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    for n in 0..50 {
+                        if i % 2 == 0 {
+                            vault
+                                .write_note(Path::new(&format!("t{}.md", i)), &n.to_string())
+                                .unwrap();
+                        }
+                        let content = vault.read_note("a.md").unwrap().content.clone();
+                        assert_eq!(content, "0");
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(vault.list_files().unwrap().len(), 1 + threads / 2);
+        assert_eq!(vault.read_note("t0.md").unwrap().content, "49");
+    }
+
+    #[test]
+    fn test_listeners_can_use_the_vault() {
+        let vault = shared_vault();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (handle, sink) = (vault.clone(), Arc::clone(&seen));
+        vault.events().subscribe(move |event| {
+            if let VaultEvent::NoteCreated { path } = event {
+                // Reading would deadlock if listeners ran under the write lock
+                let content = handle.read_note(path).unwrap().content.clone();
+                sink.lock().unwrap().push(content);
+                if path == Path::new("a.md") {
+                    handle
+                        .write_note(Path::new("b.md"), "from listener")
+                        .unwrap();
+                }
+            }
+        });
+
+        vault.write_note(Path::new("a.md"), "hello").unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec!["hello", "from listener"]);
+    }
+
+    #[test]
+    fn test_readers_do_not_block_each_other() {
+        let vault = shared_vault();
+        let first = vault.read();
+        // A second reader gets in while the first guard is held
+        let second = vault.clone();
+        let listed = thread::spawn(move || second.list_files().unwrap().len())
+            .join()
+            .unwrap();
+        assert_eq!(listed, first.list_files().unwrap().len());
+    }
+}
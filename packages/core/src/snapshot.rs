@@ -0,0 +1,188 @@
+//! Parallel full-vault loading
+//!
+//! Scans, reads, parses and link-extracts every note across all cores and
+//! gathers the results into one [`VaultSnapshot`].
+
+use crate::config::ParserOptions;
+use crate::error::Result;
+use crate::events::VaultEvent;
+use crate::ignore::IgnoreRules;
+use crate::index::SearchIndex;
+use crate::links::{BacklinksMap, LinkExtractor, LinksMap, WikiLink};
+use crate::parser::{DocumentOutline, MarkdownParser};
+use crate::storage::Storage;
+use crate::vault::{self, Note, Vault};
+use rayon::prelude::*;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+thread_local! {
+    // Parsers are neither Send nor Sync and costly to build, so each worker keeps one
+    static PARSER: RefCell<Option<(ParserOptions, MarkdownParser)>> = const { RefCell::new(None) };
+}
+
+/// Parse a note's outline with this thread's parser, rebuilding it if the options changed
+fn parse_outline(options: &ParserOptions, content: &str) -> DocumentOutline {
+    PARSER.with_borrow_mut(|cached| {
+        if !matches!(cached, Some((o, _)) if o == options) {
+            *cached = Some((options.clone(), MarkdownParser::with_options(options)));
+        }
+        cached.as_ref().unwrap().1.parse_outline(content)
+    })
+}
+
+/// A loaded note with its parsed structure
+#[derive(Debug, Clone)]
+pub struct NoteSnapshot {
+    /// The note as read from storage
+    pub note: Note,
+    /// Parsed document structure
+    pub outline: DocumentOutline,
+}
+
+/// Every note in a vault, parsed, linked and indexed
+pub struct VaultSnapshot {
+    /// Notes sorted by path
+    pub notes: Vec<NoteSnapshot>,
+    /// Outbound wikilinks per note
+    pub links: LinksMap,
+    /// Inbound links per note
+    pub backlinks: BacklinksMap,
+    /// Full-text search index over all notes, committed and ready to query
+    pub index: SearchIndex,
+}
+
+impl VaultSnapshot {
+    /// Look up a note by path
+    pub fn note(&self, path: &Path) -> Option<&NoteSnapshot> {
+        self.notes
+            .binary_search_by(|n| n.note.path.as_path().cmp(path))
+            .ok()
+            .map(|i| &self.notes[i])
+    }
+
+    /// Paths of all notes, sorted
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.notes.iter().map(|n| n.note.path.clone()).collect()
+    }
+}
+
+impl<S: Storage + Sync> Vault<S> {
+    /// Load the whole vault in parallel into a single snapshot
+    ///
+    /// The note cache is left untouched. Emits [`VaultEvent::IndexUpdated`]
+    /// once the snapshot's index is built.
+    pub fn load_snapshot(&self) -> Result<VaultSnapshot> {
+        let storage = self.storage();
+        let files = par_walk_dir(storage, Path::new(""), self.ignore_rules())?;
+
+        let options = &self.settings().parser;
+        let extractor = LinkExtractor::new();
+        let loaded: Vec<(NoteSnapshot, Vec<WikiLink>)> = files
+            .par_iter()
+            .map(|path| {
+                let note = vault::load_note(storage, path)?;
+                let outline = parse_outline(options, &note.content);
+                let links = extractor.extract(&note.content);
+                Ok((NoteSnapshot { note, outline }, links))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut notes = Vec::with_capacity(loaded.len());
+        let mut links = LinksMap::new();
+        let mut index = SearchIndex::new()?;
+        for (snapshot, note_links) in loaded {
+            index.index_file(snapshot.note.path.clone(), &snapshot.note.content)?;
+            if !note_links.is_empty() {
+                links.insert(snapshot.note.path.clone(), note_links);
+            }
+            notes.push(snapshot);
+        }
+        notes.sort_by(|a, b| a.note.path.cmp(&b.note.path));
+        index.commit()?;
+
+        let paths: Vec<PathBuf> = notes.iter().map(|n| n.note.path.clone()).collect();
+        let backlinks = extractor.build_backlinks_map(&links, &paths);
+        self.events()
+            .emit(&VaultEvent::IndexUpdated { notes: notes.len() });
+
+        Ok(VaultSnapshot {
+            notes,
+            links,
+            backlinks,
+            index,
+        })
+    }
+}
+
+/// Walk the directory tree in parallel and collect .md files that aren't ignored
+fn par_walk_dir<S: Storage + Sync + ?Sized>(
+    storage: &S,
+    dir: &Path,
+    rules: &IgnoreRules,
+) -> Result<Vec<PathBuf>> {
+    let entries = storage.list(dir)?;
+    let scoped = rules.scoped(storage, dir, &entries)?;
+    let rules = scoped.as_ref().unwrap_or(rules);
+
+    let (dirs, entries): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .filter(|e| !rules.matches(&e.path, e.is_dir))
+        .partition(|e| e.is_dir);
+
+    let mut files: Vec<PathBuf> = entries
+        .into_iter()
+        .map(|e| e.path)
+        .filter(|p| vault::is_markdown(p))
+        .collect();
+
+    let nested: Vec<Vec<PathBuf>> = dirs
+        .par_iter()
+        .map(|d| par_walk_dir(storage, &d.path, rules))
+        .collect::<Result<_>>()?;
+    files.extend(nested.into_iter().flatten());
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::vault::VaultConfig;
+
+    #[test]
+    fn test_load_snapshot() {
+        let config = VaultConfig {
+            path: PathBuf::from("memory"),
+            name: "memory".to_string(),
+            watch: false,
+            settings: Default::default(),
+        };
+        let mut vault = Vault::with_storage(config, MemoryStorage::new());
+        for i in 0..50 {
+            let path = PathBuf::from(format!("dir{}/note{}.md", i % 5, i));
+            let content = format!("# Note {}\n\n- [ ] task\n\nSee [[note{}]]", i, (i + 1) % 50);
+            vault.write_note(&path, &content).unwrap();
+        }
+        vault
+            .write_note(Path::new(".obsidian/skip.md"), "[[note0]]")
+            .unwrap();
+
+        let snapshot = vault.load_snapshot().unwrap();
+
+        assert_eq!(snapshot.notes.len(), 50);
+        assert_eq!(snapshot.index.stats().num_files, 50);
+        let hits = snapshot.index.search("note7").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, PathBuf::from("dir1/note6.md"));
+        assert_eq!(snapshot.links.len(), 50);
+
+        let note0 = snapshot.note(Path::new("dir0/note0.md")).unwrap();
+        assert_eq!(note0.outline.headings[0].text, "Note 0");
+        assert_eq!(note0.outline.tasks.len(), 1);
+
+        let backlinks = &snapshot.backlinks[&PathBuf::from("dir0/note0.md")];
+        assert_eq!(backlinks, &vec![PathBuf::from("dir4/note49.md")]);
+    }
+}
@@ -0,0 +1,682 @@
+//! Storage backends for vault files
+//!
+//! All paths passed to a [`Storage`] are relative to the vault root.
+
+use crate::error::{ArkeError, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+/// Metadata about a stored file or directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// Whether the entry is a directory
+    pub is_dir: bool,
+    /// Size in bytes (0 for directories)
+    pub size: u64,
+    /// Last modification time, if the backend tracks it
+    pub modified: Option<SystemTime>,
+}
+
+/// An entry returned by [`Storage::list`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Path relative to the vault root
+    pub path: PathBuf,
+    /// Whether the entry is a directory
+    pub is_dir: bool,
+}
+
+/// File storage underlying a vault
+pub trait Storage {
+    /// List the direct children of a directory (`""` for the vault root)
+    fn list(&self, dir: &Path) -> Result<Vec<DirEntry>>;
+
+    /// Read a file's contents
+    fn read(&self, path: &Path) -> Result<Vec<u8>>;
+
+    /// Write a file, creating parent directories as needed
+    ///
+    /// Backends should replace the file atomically, so a crash never leaves a
+    /// truncated file behind.
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()>;
+
+    /// Rename or move a file or directory, creating parent directories as needed
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Remove a file
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    /// Create a directory and any missing parents; succeeds if it already exists
+    fn create_dir(&self, path: &Path) -> Result<()>;
+
+    /// Remove an empty directory
+    fn remove_dir(&self, path: &Path) -> Result<()>;
+
+    /// Get metadata for a file or directory
+    ///
+    /// Fails with [`ArkeError::FileNotFound`] if nothing exists at `path`.
+    fn metadata(&self, path: &Path) -> Result<FileMetadata>;
+
+    /// Read a file as UTF-8 text
+    fn read_to_string(&self, path: &Path) -> Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| ArkeError::Parse(format!("{} is not valid UTF-8: {}", path.display(), e)))
+    }
+
+    /// Whether a file or directory exists at `path`
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+}
+
+impl<S: Storage + ?Sized> Storage for Box<S> {
+    fn list(&self, dir: &Path) -> Result<Vec<DirEntry>> {
+        (**self).list(dir)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        (**self).read(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        (**self).write(path, contents)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        (**self).rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        (**self).remove(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        (**self).create_dir(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        (**self).remove_dir(path)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        (**self).metadata(path)
+    }
+}
+
+/// Storage on the local filesystem, rooted at the vault directory
+#[derive(Debug, Clone)]
+pub struct NativeStorage {
+    root: PathBuf,
+}
+
+impl NativeStorage {
+    /// Create a storage rooted at `root`
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The vault root directory
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn full_path(&self, path: &Path) -> PathBuf {
+        self.root.join(path)
+    }
+}
+
+/// Replace `path` so readers see either the old contents or the new, never a mix
+///
+/// `write` fills a temp file in the same directory, which is then fsynced and
+/// renamed over `path`. The original file's permissions are kept. If anything
+/// fails, the temp file is removed and `path` is left untouched.
+fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    // Write through symlinks rather than replacing them
+    let path = &std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // Dot-prefixed, so the default ignore rules hide leftovers from a crash
+    let temp_path = dir.join(format!(
+        ".{}.{}-{}.arke-tmp",
+        name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| {
+        let mut file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        write(&mut file)?;
+
+        if let Ok(metadata) = std::fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        drop(file);
+
+        std::fs::rename(&temp_path, path)?;
+        sync_dir(dir)
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Flush a directory entry so a completed rename survives power loss
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened for syncing on this platform
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Map a missing-file IO error to [`ArkeError::FileNotFound`]
+fn not_found_as_arke(err: std::io::Error, path: &Path) -> ArkeError {
+    if err.kind() == std::io::ErrorKind::NotFound {
+        ArkeError::FileNotFound(path.display().to_string())
+    } else {
+        ArkeError::Io(err)
+    }
+}
+
+impl Storage for NativeStorage {
+    fn list(&self, dir: &Path) -> Result<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+
+        for entry in
+            std::fs::read_dir(self.full_path(dir)).map_err(|e| not_found_as_arke(e, dir))?
+        {
+            let entry = entry?;
+            entries.push(DirEntry {
+                path: dir.join(entry.file_name()),
+                is_dir: entry.path().is_dir(),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        std::fs::read(self.full_path(path)).map_err(|e| not_found_as_arke(e, path))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        let full_path = self.full_path(path);
+
+        // Create parent directories if needed
+        if let Some(parent) = full_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        write_atomic(&full_path, |file| file.write_all(contents))?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let to_full = self.full_path(to);
+
+        // Create parent directories for new path
+        if let Some(parent) = to_full.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::rename(self.full_path(from), &to_full).map_err(|e| not_found_as_arke(e, from))
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        std::fs::remove_file(self.full_path(path)).map_err(|e| not_found_as_arke(e, path))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        std::fs::create_dir_all(self.full_path(path))?;
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        std::fs::remove_dir(self.full_path(path)).map_err(|e| not_found_as_arke(e, path))
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        let metadata =
+            std::fs::metadata(self.full_path(path)).map_err(|e| not_found_as_arke(e, path))?;
+
+        Ok(FileMetadata {
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// A file held by [`MemoryStorage`]
+#[derive(Debug, Clone)]
+struct MemoryFile {
+    contents: Vec<u8>,
+    modified: Option<SystemTime>,
+}
+
+/// Storage held entirely in memory, for tests and hosts without a filesystem
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    files: RwLock<BTreeMap<PathBuf, MemoryFile>>,
+    dirs: RwLock<BTreeSet<PathBuf>>,
+}
+
+impl MemoryStorage {
+    /// Create an empty in-memory storage
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Add every ancestor directory of `path` to `dirs`
+fn add_parents(dirs: &mut BTreeSet<PathBuf>, path: &Path) {
+    for ancestor in path.ancestors().skip(1) {
+        if ancestor.as_os_str().is_empty() {
+            break;
+        }
+        dirs.insert(ancestor.to_path_buf());
+    }
+}
+
+/// Whether `path` stays inside the storage root: relative, without `.` or `..`
+pub(crate) fn is_contained(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Current time, where the platform provides a clock
+pub(crate) fn now() -> Option<SystemTime> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        Some(SystemTime::now())
+    }
+}
+
+/// Current time in seconds since the Unix epoch
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub(crate) fn now_secs() -> Option<u64> {
+    Some((js_sys::Date::now() / 1000.0) as u64)
+}
+
+/// Current time in seconds since the Unix epoch, where the platform provides a clock
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub(crate) fn now_secs() -> Option<u64> {
+    now()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+impl Storage for MemoryStorage {
+    fn list(&self, dir: &Path) -> Result<Vec<DirEntry>> {
+        let dirs = self.dirs.read().unwrap();
+        if !dir.as_os_str().is_empty() && !dirs.contains(dir) {
+            return Err(ArkeError::FileNotFound(dir.display().to_string()));
+        }
+
+        let is_child = |p: &Path| p.parent() == Some(dir);
+        let mut entries: Vec<DirEntry> = dirs
+            .iter()
+            .filter(|p| is_child(p))
+            .map(|p| DirEntry {
+                path: p.clone(),
+                is_dir: true,
+            })
+            .collect();
+        entries.extend(
+            self.files
+                .read()
+                .unwrap()
+                .keys()
+                .filter(|p| is_child(p))
+                .map(|p| DirEntry {
+                    path: p.clone(),
+                    is_dir: false,
+                }),
+        );
+
+        Ok(entries)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.files
+            .read()
+            .unwrap()
+            .get(path)
+            .map(|f| f.contents.clone())
+            .ok_or_else(|| ArkeError::FileNotFound(path.display().to_string()))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        let mut dirs = self.dirs.write().unwrap();
+        if dirs.contains(path) {
+            return Err(ArkeError::Vault(format!(
+                "Cannot write to directory: {}",
+                path.display()
+            )));
+        }
+        add_parents(&mut dirs, path);
+
+        self.files.write().unwrap().insert(
+            path.to_path_buf(),
+            MemoryFile {
+                contents: contents.to_vec(),
+                modified: now(),
+            },
+        );
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut dirs = self.dirs.write().unwrap();
+        let mut files = self.files.write().unwrap();
+
+        if let Some(file) = files.remove(from) {
+            add_parents(&mut dirs, to);
+            files.insert(to.to_path_buf(), file);
+            return Ok(());
+        }
+
+        if !dirs.contains(from) {
+            return Err(ArkeError::FileNotFound(from.display().to_string()));
+        }
+
+        // Move the directory and everything beneath it
+        let moved_dirs: Vec<PathBuf> = dirs
+            .iter()
+            .filter(|p| p.starts_with(from))
+            .cloned()
+            .collect();
+        for dir in moved_dirs {
+            dirs.remove(&dir);
+            dirs.insert(to.join(dir.strip_prefix(from).unwrap()));
+        }
+
+        let moved_files: Vec<PathBuf> = files
+            .keys()
+            .filter(|p| p.starts_with(from))
+            .cloned()
+            .collect();
+        for path in moved_files {
+            let file = files.remove(&path).unwrap();
+            files.insert(to.join(path.strip_prefix(from).unwrap()), file);
+        }
+
+        add_parents(&mut dirs, to);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.files
+            .write()
+            .unwrap()
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| ArkeError::FileNotFound(path.display().to_string()))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        if self.files.read().unwrap().contains_key(path) {
+            return Err(ArkeError::Vault(format!(
+                "A file already exists at {}",
+                path.display()
+            )));
+        }
+        if !path.as_os_str().is_empty() {
+            let mut dirs = self.dirs.write().unwrap();
+            add_parents(&mut dirs, path);
+            dirs.insert(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        let mut dirs = self.dirs.write().unwrap();
+        if !dirs.contains(path) {
+            return Err(ArkeError::FileNotFound(path.display().to_string()));
+        }
+
+        let is_child = |p: &Path| p.parent() == Some(path);
+        if dirs.iter().any(|d| is_child(d))
+            || self.files.read().unwrap().keys().any(|f| is_child(f))
+        {
+            return Err(ArkeError::Vault(format!(
+                "Directory is not empty: {}",
+                path.display()
+            )));
+        }
+        dirs.remove(path);
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        if let Some(file) = self.files.read().unwrap().get(path) {
+            return Ok(FileMetadata {
+                is_dir: false,
+                size: file.contents.len() as u64,
+                modified: file.modified,
+            });
+        }
+
+        if path.as_os_str().is_empty() || self.dirs.read().unwrap().contains(path) {
+            return Ok(FileMetadata {
+                is_dir: true,
+                size: 0,
+                modified: None,
+            });
+        }
+
+        Err(ArkeError::FileNotFound(path.display().to_string()))
+    }
+}
+
+/// In-memory storage where another client creates a file right after the vault looks for it
+///
+/// The file appears once its folder has been listed or its own metadata
+/// read, so tests can check what happens when a name that looked free is
+/// taken before the vault writes it.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct RacingStorage {
+    pub(crate) inner: MemoryStorage,
+    pub(crate) pending: std::sync::Mutex<Option<(PathBuf, Vec<u8>)>>,
+}
+
+#[cfg(test)]
+impl RacingStorage {
+    pub(crate) fn new(path: impl Into<PathBuf>, contents: &[u8]) -> Self {
+        Self {
+            inner: MemoryStorage::new(),
+            pending: std::sync::Mutex::new(Some((path.into(), contents.to_vec()))),
+        }
+    }
+
+    fn race(&self, looked_at: impl Fn(&Path) -> bool) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.as_ref().is_some_and(|(path, _)| looked_at(path)) {
+            let (path, contents) = pending.take().unwrap();
+            self.inner.write(&path, &contents).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+impl Storage for RacingStorage {
+    fn list(&self, dir: &Path) -> Result<Vec<DirEntry>> {
+        let entries = self.inner.list(dir);
+        self.race(|path| path.parent() == Some(dir));
+        entries
+    }
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.inner.read(path)
+    }
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        self.inner.write(path, contents)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.rename(from, to)
+    }
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.inner.remove(path)
+    }
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.inner.create_dir(path)
+    }
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        self.inner.remove_dir(path)
+    }
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        let metadata = self.inner.metadata(path);
+        self.race(|pending| pending == path);
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Exercise the contract every backend must satisfy
+    fn check_storage_contract(storage: &dyn Storage) {
+        storage.write(Path::new("a.md"), b"alpha").unwrap();
+        storage.write(Path::new("dir/b.md"), b"beta").unwrap();
+
+        assert_eq!(storage.read_to_string(Path::new("a.md")).unwrap(), "alpha");
+        assert!(storage.metadata(Path::new("dir")).unwrap().is_dir);
+        assert_eq!(storage.metadata(Path::new("dir/b.md")).unwrap().size, 4);
+
+        let mut root = storage.list(Path::new("")).unwrap();
+        root.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(
+            root,
+            vec![
+                DirEntry {
+                    path: PathBuf::from("a.md"),
+                    is_dir: false
+                },
+                DirEntry {
+                    path: PathBuf::from("dir"),
+                    is_dir: true
+                },
+            ]
+        );
+
+        storage
+            .rename(Path::new("dir"), Path::new("moved/dir"))
+            .unwrap();
+        assert_eq!(storage.read(Path::new("moved/dir/b.md")).unwrap(), b"beta");
+        assert!(!storage.exists(Path::new("dir/b.md")));
+
+        storage.remove(Path::new("a.md")).unwrap();
+        assert!(matches!(
+            storage.read(Path::new("a.md")),
+            Err(ArkeError::FileNotFound(_))
+        ));
+        assert!(matches!(
+            storage.metadata(Path::new("a.md")),
+            Err(ArkeError::FileNotFound(_))
+        ));
+
+        storage.create_dir(Path::new("empty/nested")).unwrap();
+        storage.create_dir(Path::new("empty/nested")).unwrap();
+        assert!(storage.metadata(Path::new("empty/nested")).unwrap().is_dir);
+        assert!(storage.remove_dir(Path::new("empty")).is_err());
+        storage.remove_dir(Path::new("empty/nested")).unwrap();
+        storage.remove_dir(Path::new("empty")).unwrap();
+        assert!(!storage.exists(Path::new("empty")));
+    }
+
+    #[test]
+    fn test_native_storage() {
+        let temp = TempDir::new().unwrap();
+        check_storage_contract(&NativeStorage::new(temp.path()));
+    }
+
+    #[test]
+    fn test_interrupted_write_keeps_original() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("note.md");
+        std::fs::write(&path, "original").unwrap();
+
+        // Crash partway through writing the new contents
+        let result = write_atomic(&path, |file| {
+            file.write_all(b"half of the new")?;
+            Err(std::io::Error::other("simulated crash"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "original");
+        assert_eq!(std::fs::read_dir(temp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_leftover_temp_file_is_hidden() {
+        let temp = TempDir::new().unwrap();
+        let storage = NativeStorage::new(temp.path());
+        storage.write(Path::new("note.md"), b"original").unwrap();
+
+        // A crash before the rename leaves a temp file beside the note
+        std::fs::write(temp.path().join(".note.md.1-0.arke-tmp"), "partial").unwrap();
+
+        let mut files = Vec::new();
+        crate::vault::walk_dir(
+            &storage,
+            Path::new(""),
+            &crate::ignore::IgnoreRules::with_defaults(),
+            crate::vault::is_markdown,
+            &mut files,
+        )
+        .unwrap();
+        assert_eq!(files, vec![PathBuf::from("note.md")]);
+        assert_eq!(
+            storage.read_to_string(Path::new("note.md")).unwrap(),
+            "original"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_preserves_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = TempDir::new().unwrap();
+        let storage = NativeStorage::new(temp.path());
+        let full_path = temp.path().join("note.md");
+        std::fs::write(&full_path, "original").unwrap();
+        std::fs::set_permissions(&full_path, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        storage.write(Path::new("note.md"), b"updated").unwrap();
+
+        let metadata = std::fs::metadata(&full_path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        assert_eq!(std::fs::read_to_string(&full_path).unwrap(), "updated");
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        check_storage_contract(&MemoryStorage::new());
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A task list item (`- [ ] ...`) in a document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Task {
+    /// Source line of the task item (1-based)
+    pub line: usize,
+    /// Task text without the list and checkbox markers
+    pub text: String,
+    /// Whether the checkbox is checked
+    pub checked: bool,
+    /// Nesting depth (0 for top-level list items)
+    pub depth: usize,
+    /// Text of the nearest heading above the task
+    pub heading: Option<String>,
+    /// Due date (`📅 2025-11-01` or `due: 2025-11-01`)
+    pub due: Option<NaiveDate>,
+    /// Priority marker
+    pub priority: Option<TaskPriority>,
+    /// Tags without the leading `#`
+    pub tags: Vec<String>,
+}
+
+/// Task priority, ordered from lowest to highest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskPriority {
+    Lowest,
+    Low,
+    Medium,
+    High,
+    Highest,
+}
+
+/// A task together with the note it belongs to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultTask {
+    /// Note path relative to vault root
+    pub path: PathBuf,
+    /// The task itself
+    #[serde(flatten)]
+    pub task: Task,
+}
+
+/// Inline metadata parsed from a task's text
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaskMetadata {
+    pub due: Option<NaiveDate>,
+    pub priority: Option<TaskPriority>,
+    pub tags: Vec<String>,
+}
+
+/// Parses inline task metadata (due dates, priorities, tags)
+pub struct TaskMetadataParser {
+    due_regex: Regex,
+    priority_regex: Regex,
+    tag_regex: Regex,
+}
+
+impl TaskMetadataParser {
+    /// Create a new task metadata parser
+    pub fn new() -> Self {
+        // Matches 📅 2025-11-01, due: 2025-11-01 or [due:: 2025-11-01]
+        let due_regex = Regex::new(r"(?:📅\x{FE0F}?|\bdue::?)\s*(\d{4}-\d{2}-\d{2})")
+            .expect("Invalid due date regex");
+        // Matches priority emoji or priority: high
+        let priority_regex =
+            Regex::new(r"(?i)(🔺|⏫|🔼|🔽|⏬)|\bpriority::?\s*(highest|high|medium|low|lowest)\b")
+                .expect("Invalid priority regex");
+        // Matches #tag and #nested/tag, but not #123 or anchors inside words
+        let tag_regex = Regex::new(r"(?:^|\s)#([\p{L}\p{N}_/-]*[\p{L}_/-][\p{L}\p{N}_/-]*)")
+            .expect("Invalid tag regex");
+
+        Self {
+            due_regex,
+            priority_regex,
+            tag_regex,
+        }
+    }
+
+    /// Parse metadata from a task's text
+    pub fn parse(&self, text: &str) -> TaskMetadata {
+        let due = self
+            .due_regex
+            .captures(text)
+            .and_then(|cap| NaiveDate::parse_from_str(&cap[1], "%Y-%m-%d").ok());
+
+        let priority = self.priority_regex.captures(text).and_then(|cap| {
+            let marker = cap.get(1).or_else(|| cap.get(2))?.as_str().to_lowercase();
+            match marker.as_str() {
+                "🔺" | "highest" => Some(TaskPriority::Highest),
+                "⏫" | "high" => Some(TaskPriority::High),
+                "🔼" | "medium" => Some(TaskPriority::Medium),
+                "🔽" | "low" => Some(TaskPriority::Low),
+                "⏬" | "lowest" => Some(TaskPriority::Lowest),
+                _ => None,
+            }
+        });
+
+        let tags = self
+            .tag_regex
+            .captures_iter(text)
+            .map(|cap| cap[1].to_string())
+            .collect();
+
+        TaskMetadata {
+            due,
+            priority,
+            tags,
+        }
+    }
+}
+
+impl Default for TaskMetadataParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Task {
+    /// Whether the task carries `tag`, or a nested tag under it
+    ///
+    /// Matching ignores case and an optional leading `#`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let tag = tag.trim_start_matches('#').to_lowercase();
+        self.tags.iter().any(|t| {
+            let t = t.to_lowercase();
+            t == tag || t.starts_with(&format!("{}/", tag))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_due_dates() {
+        let parser = TaskMetadataParser::new();
+        let expected = NaiveDate::from_ymd_opt(2025, 11, 1);
+
+        assert_eq!(parser.parse("Ship it 📅 2025-11-01").due, expected);
+        assert_eq!(parser.parse("Ship it due: 2025-11-01").due, expected);
+        assert_eq!(parser.parse("Ship it [due:: 2025-11-01]").due, expected);
+        assert_eq!(parser.parse("Ship it 2025-11-01").due, None);
+        assert_eq!(parser.parse("due: 2025-13-45").due, None);
+    }
+
+    #[test]
+    fn test_parse_priority() {
+        let parser = TaskMetadataParser::new();
+
+        assert_eq!(parser.parse("A ⏫").priority, Some(TaskPriority::High));
+        assert_eq!(parser.parse("A 🔽").priority, Some(TaskPriority::Low));
+        assert_eq!(
+            parser.parse("A priority: Highest").priority,
+            Some(TaskPriority::Highest)
+        );
+        assert_eq!(parser.parse("A").priority, None);
+        assert!(TaskPriority::High > TaskPriority::Medium);
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        let parser = TaskMetadataParser::new();
+        let meta = parser.parse("#work Call Bob about #project/alpha, not #123 or a#b");
+
+        assert_eq!(meta.tags, vec!["work", "project/alpha"]);
+    }
+}
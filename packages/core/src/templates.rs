@@ -0,0 +1,514 @@
+//! Note templates
+//!
+//! Templates are notes in the configured template folder. Tags in double
+//! braces are replaced when a note is created from one:
+//!
+//! - `{{title}}`: the new note's file name, without `.md`
+//! - `{{date}}`, `{{date:%d %B %Y}}`: the creation date, `%Y-%m-%d` by default
+//! - `{{time}}`, `{{time:%H:%M:%S}}`: the creation time, `%H:%M` by default
+//! - `{{folder}}`: the folder the note is created in
+//! - `{{frontmatter.key}}`: a top-level value from the note's own frontmatter
+//! - `{{prompt:name}}`: a value supplied by the caller
+//! - `{{include:other}}`: the content of another template
+//!
+//! Tags with other names are left as they are. Dates are formatted with
+//! chrono's English names, independent of the host locale.
+
+use crate::error::{ArkeError, Result};
+use crate::merge::{frontmatter_entries, split_frontmatter};
+use crate::naming::sanitize_file_name;
+use crate::storage::{is_contained, Storage};
+use crate::vault::Vault;
+use chrono::NaiveDateTime;
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Deepest chain of includes followed before giving up
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Values filled into a template
+#[derive(Debug, Clone)]
+pub struct TemplateValues {
+    /// Moment the note is created, for `{{date}}` and `{{time}}`
+    pub now: NaiveDateTime,
+    /// Answers to `{{prompt:name}}` tags, by name
+    pub prompts: HashMap<String, String>,
+}
+
+impl TemplateValues {
+    pub fn new(now: NaiveDateTime) -> Self {
+        Self {
+            now,
+            prompts: HashMap::new(),
+        }
+    }
+
+    /// Set the answer to a `{{prompt:name}}` tag
+    pub fn prompt(&mut self, name: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.prompts.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// Where a template is being rendered into
+pub(crate) struct Target<'a> {
+    /// Title of the note, or `None` while rendering its file name
+    pub(crate) title: Option<&'a str>,
+    pub(crate) folder: &'a Path,
+}
+
+/// Templates stored in a vault folder
+pub(crate) struct Templates<'a, S: Storage + ?Sized> {
+    storage: &'a S,
+    folder: &'a Path,
+}
+
+impl<'a, S: Storage + ?Sized> Templates<'a, S> {
+    pub(crate) fn new(storage: &'a S, folder: &'a Path) -> Self {
+        Self { storage, folder }
+    }
+
+    /// Path of a template by name, relative to the template folder; `.md` is optional
+    ///
+    /// Names that would lead out of the template folder, such as `../notes/x`,
+    /// are rejected, as is a template folder outside the vault.
+    pub(crate) fn path(&self, name: &str) -> Result<PathBuf> {
+        let name = name.trim().trim_start_matches('/');
+        if !is_contained(self.folder) || !is_contained(Path::new(name)) {
+            return Err(ArkeError::Vault(format!(
+                "Template {:?} is outside the template folder {:?}",
+                name, self.folder
+            )));
+        }
+        let path = self.folder.join(name);
+        if name.ends_with(".md") || self.storage.exists(&path) {
+            Ok(path)
+        } else {
+            Ok(self.folder.join(format!("{}.md", name)))
+        }
+    }
+
+    /// A template's content with all its includes expanded
+    pub(crate) fn expand(&self, name: &str) -> Result<String> {
+        self.expand_into(name, &mut Vec::new())
+    }
+
+    /// Render a template for a note
+    pub(crate) fn render(
+        &self,
+        name: &str,
+        target: &Target,
+        values: &TemplateValues,
+    ) -> Result<String> {
+        render_text(&self.expand(name)?, target, values)
+    }
+
+    /// Names of the prompts a template asks for, in order of first use
+    pub(crate) fn prompts(&self, name: &str) -> Result<Vec<String>> {
+        let mut prompts: Vec<String> = Vec::new();
+        for cap in tag_regex().captures_iter(&self.expand(name)?) {
+            if &cap[1] == "prompt" {
+                let name = cap.get(2).map_or("", |m| m.as_str()).trim().to_string();
+                if !prompts.contains(&name) {
+                    prompts.push(name);
+                }
+            }
+        }
+        Ok(prompts)
+    }
+
+    fn expand_into(&self, name: &str, stack: &mut Vec<PathBuf>) -> Result<String> {
+        let path = self.path(name)?;
+        if stack.contains(&path) || stack.len() >= MAX_INCLUDE_DEPTH {
+            stack.push(path);
+            let chain: Vec<String> = stack.iter().map(|p| p.display().to_string()).collect();
+            return Err(ArkeError::Parse(format!(
+                "Template includes loop: {}",
+                chain.join(" -> ")
+            )));
+        }
+
+        let content = self.storage.read_to_string(&path)?;
+        stack.push(path);
+        let mut error = None;
+        let expanded = tag_regex().replace_all(&content, |cap: &Captures| {
+            if &cap[1] != "include" || error.is_some() {
+                return cap[0].to_string();
+            }
+            let include = cap.get(2).map_or("", |m| m.as_str());
+            self.expand_into(include, stack).unwrap_or_else(|e| {
+                error = Some(e);
+                String::new()
+            })
+        });
+        stack.pop();
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(expanded.into_owned()),
+        }
+    }
+}
+
+impl<S: Storage> Vault<S> {
+    /// Names of the templates in the template folder, without `.md`, sorted
+    pub fn list_templates(&self) -> Result<Vec<String>> {
+        let folder = Path::new(&self.settings().template_folder);
+        let mut names: Vec<String> = self
+            .list_files()?
+            .iter()
+            .filter_map(|path| {
+                let name = path.strip_prefix(folder).ok()?.to_str()?;
+                Some(name.strip_suffix(".md")?.replace('\\', "/"))
+            })
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Names of the prompts a template and its includes ask for, in order of first use
+    pub fn template_prompts(&self, template: &str) -> Result<Vec<String>> {
+        self.templates().prompts(template)
+    }
+
+    /// Render a template as it would be for a note at `note`
+    pub fn render_template(
+        &self,
+        template: &str,
+        note: &Path,
+        values: &TemplateValues,
+    ) -> Result<String> {
+        let title = note
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        let target = Target {
+            title: Some(title),
+            folder: note.parent().unwrap_or(Path::new("")),
+        };
+        self.templates().render(template, &target, values)
+    }
+
+    /// Create a note in `folder` from a template, returning its path
+    ///
+    /// The file name comes from rendering `name`, which may use every tag but
+    /// `{{title}}` and `{{include}}`, e.g. `{{date}} {{prompt:topic}}`. It is
+    /// sanitized like a title passed to [`Vault::create_note`], and a number
+    /// is added if it's taken (`name 1.md`), even by a file created while the
+    /// note is being rendered. `folder` must be inside the vault.
+    pub fn create_note_from_template(
+        &mut self,
+        template: &str,
+        folder: &Path,
+        name: &str,
+        values: &TemplateValues,
+    ) -> Result<PathBuf> {
+        let target = Target {
+            title: None,
+            folder,
+        };
+        let name = render_text(name, &target, values)?;
+        // Prompt answers end up in the name, so they mustn't be able to pick
+        // another folder or a hidden file
+        let name = name
+            .split(['/', '\\'])
+            .filter(|part| !matches!(part.trim(), "" | "." | ".."))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let name = name.trim().trim_start_matches('.').trim_end_matches(".md");
+        if name.is_empty() {
+            return Err(ArkeError::Vault(
+                "Template file name pattern produced an empty name".to_string(),
+            ));
+        }
+
+        self.write_new_note(folder, &sanitize_file_name(name), |vault, path| {
+            vault.render_template(template, path, values)
+        })
+    }
+
+    fn templates(&self) -> Templates<'_, S> {
+        Templates::new(self.storage(), Path::new(&self.settings().template_folder))
+    }
+}
+
+/// Replace the tags in already expanded template text
+///
+/// Frontmatter tags are filled last, from the frontmatter as the other tags
+/// left it.
+pub(crate) fn render_text(text: &str, target: &Target, values: &TemplateValues) -> Result<String> {
+    let mut error = None;
+    let rendered = tag_regex().replace_all(text, |cap: &Captures| {
+        let arg = cap.get(2).map(|m| m.as_str());
+        match render_tag(&cap[1], arg, target, values) {
+            Ok(Some(value)) => value,
+            Ok(None) => cap[0].to_string(),
+            Err(e) => {
+                error.get_or_insert(e);
+                String::new()
+            }
+        }
+    });
+    if let Some(e) = error {
+        return Err(e);
+    }
+
+    let frontmatter: HashMap<String, String> = split_frontmatter(&rendered)
+        .0
+        .map(frontmatter_entries)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(key, text)| (key, entry_value(&text)))
+        .collect();
+    let rendered =
+        tag_regex().replace_all(&rendered, |cap: &Captures| match (&cap[1], cap.get(2)) {
+            ("frontmatter", Some(key)) => frontmatter
+                .get(key.as_str().trim())
+                .cloned()
+                .unwrap_or_default(),
+            _ => cap[0].to_string(),
+        });
+    Ok(rendered.into_owned())
+}
+
+/// The value of one tag, or `None` to leave it untouched
+fn render_tag(
+    name: &str,
+    arg: Option<&str>,
+    target: &Target,
+    values: &TemplateValues,
+) -> Result<Option<String>> {
+    let value = match name {
+        "title" => match target.title {
+            Some(title) => title.to_string(),
+            None => {
+                return Err(ArkeError::Parse(
+                    "{{title}} can't be used in a file name".to_string(),
+                ))
+            }
+        },
+        "date" | "time" => {
+            let default = if name == "date" { "%Y-%m-%d" } else { "%H:%M" };
+            let format = arg.map(str::trim).filter(|f| !f.is_empty());
+            let format = format.unwrap_or(default);
+            let mut value = String::new();
+            write!(value, "{}", values.now.format(format)).map_err(|_| {
+                ArkeError::Parse(format!("Invalid {} format in template: {:?}", name, format))
+            })?;
+            value
+        }
+        "folder" => target.folder.to_string_lossy().replace('\\', "/"),
+        "prompt" => {
+            let prompt = arg.unwrap_or_default().trim();
+            values.prompts.get(prompt).cloned().ok_or_else(|| {
+                ArkeError::Parse(format!("No value given for template prompt {:?}", prompt))
+            })?
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+/// The value of a frontmatter entry, given the entry's full text
+fn entry_value(text: &str) -> String {
+    let value = text.split_once(':').map_or("", |(_, v)| v).trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner.to_string();
+        }
+    }
+    value.to_string()
+}
+
+/// `{{name}}`, `{{name:argument}}` or `{{frontmatter.key}}`
+fn tag_regex() -> &'static Regex {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    TAG.get_or_init(|| {
+        Regex::new(r"\{\{\s*([a-z]+)(?:[:.]([^}]*?))?\s*\}\}").expect("Invalid template tag regex")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, RacingStorage};
+    use chrono::NaiveDate;
+
+    fn values() -> TemplateValues {
+        let now = NaiveDate::from_ymd_opt(2024, 3, 5)
+            .unwrap()
+            .and_hms_opt(9, 7, 0)
+            .unwrap();
+        let mut values = TemplateValues::new(now);
+        values.prompt("project", "Arke");
+        values
+    }
+
+    fn storage(templates: &[(&str, &str)]) -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        for (path, content) in templates {
+            storage.write(Path::new(path), content.as_bytes()).unwrap();
+        }
+        storage
+    }
+
+    #[test]
+    fn test_render_variables() {
+        let text = "---\nstatus: \"draft\"\n---\n# {{title}} for {{ prompt:project }}\n\
+                    {{date}} {{date:%A %d %B}} {{time}} {{time:%H:%M:%S}}\n\
+                    in {{folder}}, {{frontmatter.status}}{{frontmatter.missing}} {{other}}";
+        let target = Target {
+            title: Some("Plan"),
+            folder: Path::new("work/notes"),
+        };
+
+        assert_eq!(
+            render_text(text, &target, &values()).unwrap(),
+            "---\nstatus: \"draft\"\n---\n# Plan for Arke\n\
+             2024-03-05 Tuesday 05 March 09:07 09:07:00\n\
+             in work/notes, draft {{other}}"
+        );
+
+        let no_title = Target {
+            title: None,
+            ..target
+        };
+        assert!(render_text("{{title}}", &no_title, &values()).is_err());
+        assert!(render_text("{{prompt:missing}}", &no_title, &values()).is_err());
+        assert!(render_text("{{date:%Q}}", &no_title, &values()).is_err());
+    }
+
+    #[test]
+    fn test_includes() {
+        let storage = storage(&[
+            (
+                "templates/meeting.md",
+                "# {{title}}\n{{include:parts/attendees}}",
+            ),
+            (
+                "templates/parts/attendees.md",
+                "Attendees: {{prompt:who}}\n{{include:footer.md}}",
+            ),
+            ("templates/footer.md", "{{prompt:project}}"),
+            ("templates/loop.md", "{{include:loop}}"),
+        ]);
+        let templates = Templates::new(&storage, Path::new("templates"));
+
+        assert_eq!(
+            templates.expand("meeting").unwrap(),
+            "# {{title}}\nAttendees: {{prompt:who}}\n{{prompt:project}}"
+        );
+        assert_eq!(
+            templates.prompts("meeting").unwrap(),
+            vec!["who", "project"]
+        );
+
+        let err = templates.expand("loop").unwrap_err();
+        assert!(matches!(err, ArkeError::Parse(m) if m.contains("loop")));
+        assert!(matches!(
+            templates.expand("absent"),
+            Err(ArkeError::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_templates_stay_in_their_folder() {
+        let storage = storage(&[
+            ("secret.md", "secret"),
+            ("templates/escape.md", "{{include:../secret}}"),
+        ]);
+        let templates = Templates::new(&storage, Path::new("templates"));
+
+        for name in ["escape", "../secret", "parts/../../secret", "/../secret"] {
+            assert!(
+                matches!(templates.expand(name), Err(ArkeError::Vault(_))),
+                "{}",
+                name
+            );
+        }
+        let outside = Templates::new(&storage, Path::new("../templates"));
+        assert!(outside.expand("escape").is_err());
+    }
+
+    #[test]
+    fn test_create_note_from_template() {
+        let config = crate::VaultConfig {
+            path: PathBuf::from("/memory"),
+            name: "Memory".to_string(),
+            watch: false,
+            settings: Default::default(),
+        };
+        let mut vault = Vault::with_storage(
+            config,
+            storage(&[("templates/meeting.md", "# {{title}} ({{folder}})")]),
+        );
+        assert_eq!(vault.list_templates().unwrap(), vec!["meeting"]);
+
+        let create = |vault: &mut Vault<MemoryStorage>| {
+            vault
+                .create_note_from_template(
+                    "meeting",
+                    Path::new("work"),
+                    "{{date}} {{prompt:project}}",
+                    &values(),
+                )
+                .unwrap()
+        };
+        let first = create(&mut vault);
+        let second = create(&mut vault);
+        assert_eq!(first, PathBuf::from("work/2024-03-05 Arke.md"));
+        assert_eq!(second, PathBuf::from("work/2024-03-05 Arke 1.md"));
+        assert_eq!(
+            vault.read_note(&second).unwrap().content,
+            "# 2024-03-05 Arke 1 (work)"
+        );
+
+        let mut escaping = values();
+        escaping
+            .prompts
+            .insert("project".to_string(), "../../etc/x".to_string());
+        let path = vault
+            .create_note_from_template(
+                "meeting",
+                Path::new("work"),
+                "{{prompt:project}}",
+                &escaping,
+            )
+            .unwrap();
+        assert_eq!(path, PathBuf::from("work/etc x.md"));
+        assert!(matches!(
+            vault.create_note_from_template("meeting", Path::new("../work"), "x", &values()),
+            Err(ArkeError::Vault(_))
+        ));
+    }
+
+    #[test]
+    fn test_create_from_template_never_overwrites() {
+        let config = crate::VaultConfig {
+            path: PathBuf::from("/memory"),
+            name: "Memory".to_string(),
+            watch: false,
+            settings: Default::default(),
+        };
+        // Another client saves `work/Sync.md` right after the folder is listed
+        let storage = RacingStorage::new("work/Sync.md", b"theirs");
+        storage
+            .inner
+            .write(Path::new("templates/meeting.md"), b"# {{title}}")
+            .unwrap();
+        let mut vault = Vault::with_storage(config, storage);
+
+        let path = vault
+            .create_note_from_template("meeting", Path::new("work"), "Sync", &values())
+            .unwrap();
+        assert_eq!(path, PathBuf::from("work/Sync 1.md"));
+        assert_eq!(vault.read_note(&path).unwrap().content, "# Sync 1");
+        assert_eq!(vault.read_note("work/Sync.md").unwrap().content, "theirs");
+    }
+}
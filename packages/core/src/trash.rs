@@ -0,0 +1,258 @@
+//! Vault trash: deleted notes are kept in `.arke/trash` until the trash is emptied
+//!
+//! Each trashed file is stored as `.arke/trash/<id>`, next to a
+//! `.arke/trash/<id>.json` record of where it came from and when it was
+//! deleted. The record is written first, so an interrupted delete leaves at
+//! worst a record without a file, which is skipped.
+
+use crate::attachments;
+use crate::error::{ArkeError, Result};
+use crate::storage::{is_contained, now_secs, Storage};
+use crate::vault::content_hash;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Trash directory, relative to the vault root
+pub const TRASH_DIR: &str = ".arke/trash";
+
+/// A file in the trash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashEntry {
+    /// Identifies the entry within the trash
+    pub id: String,
+    /// Path the file was deleted from
+    pub original_path: PathBuf,
+    /// Deletion time in seconds since the Unix epoch, if the platform has a clock
+    pub deleted_at: Option<u64>,
+}
+
+/// A link or embed whose target only exists in the trash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashedLink {
+    /// Note containing the link
+    pub source: PathBuf,
+    /// The link target as written
+    pub target: String,
+    /// The trashed file the link pointed to
+    pub entry: TrashEntry,
+}
+
+/// Move a file into the trash
+pub(crate) fn move_to_trash<S: Storage + ?Sized>(storage: &S, path: &Path) -> Result<TrashEntry> {
+    move_to_trash_as(storage, path, path)
+}
+
+/// Move the file at `path` into the trash, recording it as deleted from `original`
+pub(crate) fn move_to_trash_as<S: Storage + ?Sized>(
+    storage: &S,
+    path: &Path,
+    original: &Path,
+) -> Result<TrashEntry> {
+    if storage.metadata(path)?.is_dir {
+        return Err(ArkeError::Vault(format!(
+            "Cannot trash a directory: {}",
+            path.display()
+        )));
+    }
+
+    let deleted_at = now_secs();
+    let key = format!("{}\0{}", original.display(), deleted_at.unwrap_or(0));
+    let id = (0u32..)
+        .map(|n| content_hash(format!("{}\0{}", key, n).as_bytes()))
+        .find(|id| !storage.exists(&record_path(id)) && !storage.exists(&data_path(id)))
+        .unwrap();
+
+    let entry = TrashEntry {
+        id,
+        original_path: original.to_path_buf(),
+        deleted_at,
+    };
+    let record = record_path(&entry.id);
+    storage.write(&record, serde_json::to_string_pretty(&entry)?.as_bytes())?;
+
+    if let Err(e) = storage.rename(path, &data_path(&entry.id)) {
+        let _ = storage.remove(&record);
+        return Err(e);
+    }
+    Ok(entry)
+}
+
+/// Every entry in the trash, most recently deleted first
+pub(crate) fn list<S: Storage + ?Sized>(storage: &S) -> Result<Vec<TrashEntry>> {
+    let entries = match storage.list(Path::new(TRASH_DIR)) {
+        Ok(entries) => entries,
+        Err(ArkeError::FileNotFound(_)) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut trash = Vec::new();
+    for entry in entries {
+        if entry.is_dir || entry.path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let record: TrashEntry = serde_json::from_str(&storage.read_to_string(&entry.path)?)?;
+        // A hand-edited record mustn't point purges at files outside the trash
+        if is_valid_id(&record.id) && storage.exists(&data_path(&record.id)) {
+            trash.push(record);
+        }
+    }
+
+    trash.sort_by(|a, b| {
+        b.deleted_at
+            .cmp(&a.deleted_at)
+            .then_with(|| a.original_path.cmp(&b.original_path))
+    });
+    Ok(trash)
+}
+
+/// Look up a trash entry by id
+pub(crate) fn get<S: Storage + ?Sized>(storage: &S, id: &str) -> Result<TrashEntry> {
+    if !is_valid_id(id) {
+        return Err(ArkeError::Vault(format!(
+            "Invalid trash entry id: {:?}",
+            id
+        )));
+    }
+    let json = storage
+        .read_to_string(&record_path(id))
+        .map_err(|_| ArkeError::FileNotFound(format!("{} (trash entry)", id)))?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// Move a trashed file back, returning the path it was restored to
+///
+/// If something now exists at the original path, the file is restored next
+/// to it under a numbered name such as `note 1.md`.
+pub(crate) fn restore<S: Storage + ?Sized>(storage: &S, id: &str) -> Result<PathBuf> {
+    let entry = get(storage, id)?;
+
+    let original = &entry.original_path;
+    if !is_contained(original) {
+        return Err(ArkeError::Vault(format!("Invalid trash entry: {}", id)));
+    }
+    let name = original
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| ArkeError::Vault(format!("Invalid trash entry: {}", id)))?;
+    let folder = original.parent().unwrap_or(Path::new(""));
+    let target = attachments::unique_path(folder, name, |p| storage.exists(p));
+
+    storage.rename(&data_path(id), &target)?;
+    storage.remove(&record_path(id))?;
+    Ok(target)
+}
+
+/// Permanently delete trash entries accepted by `purge`, returning them
+pub(crate) fn purge<S: Storage + ?Sized>(
+    storage: &S,
+    purge: impl Fn(&TrashEntry) -> bool,
+) -> Result<Vec<TrashEntry>> {
+    let mut purged = Vec::new();
+    for entry in list(storage)?.into_iter().filter(|e| purge(e)) {
+        storage.remove(&data_path(&entry.id))?;
+        storage.remove(&record_path(&entry.id))?;
+        purged.push(entry);
+    }
+    Ok(purged)
+}
+
+/// Whether `id` has the form of a generated id, a content hash
+fn is_valid_id(id: &str) -> bool {
+    id.len() == 16 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn data_path(id: &str) -> PathBuf {
+    Path::new(TRASH_DIR).join(id)
+}
+
+fn record_path(id: &str) -> PathBuf {
+    Path::new(TRASH_DIR).join(format!("{}.json", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_trash_and_restore() {
+        let storage = MemoryStorage::new();
+        storage.write(Path::new("notes/a.md"), b"first").unwrap();
+
+        let entry = move_to_trash(&storage, Path::new("notes/a.md")).unwrap();
+        assert!(!storage.exists(Path::new("notes/a.md")));
+        assert_eq!(entry.original_path, PathBuf::from("notes/a.md"));
+        assert!(entry.deleted_at.is_some());
+        assert_eq!(list(&storage).unwrap(), vec![entry.clone()]);
+
+        // A new note took the old path, so the restored one gets a free name
+        storage.write(Path::new("notes/a.md"), b"second").unwrap();
+        let restored = restore(&storage, &entry.id).unwrap();
+        assert_eq!(restored, PathBuf::from("notes/a 1.md"));
+        assert_eq!(storage.read(&restored).unwrap(), b"first");
+        assert!(list(&storage).unwrap().is_empty());
+        assert!(matches!(
+            restore(&storage, &entry.id),
+            Err(ArkeError::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_same_path_trashed_twice() {
+        let storage = MemoryStorage::new();
+        let path = Path::new("a.md");
+
+        storage.write(path, b"one").unwrap();
+        let first = move_to_trash(&storage, path).unwrap();
+        storage.write(path, b"two").unwrap();
+        let second = move_to_trash(&storage, path).unwrap();
+
+        assert_ne!(first.id, second.id);
+        assert_eq!(list(&storage).unwrap().len(), 2);
+        assert!(move_to_trash(&storage, path).is_err());
+    }
+
+    #[test]
+    fn test_purge_and_orphaned_records() {
+        let storage = MemoryStorage::new();
+        for (i, name) in ["old.md", "new.md"].iter().enumerate() {
+            let entry = TrashEntry {
+                id: format!("{:016x}", i),
+                original_path: PathBuf::from(name),
+                deleted_at: Some(100 * (i as u64 + 1)),
+            };
+            let json = serde_json::to_string(&entry).unwrap();
+            storage
+                .write(&record_path(&entry.id), json.as_bytes())
+                .unwrap();
+            storage.write(&data_path(&entry.id), b"text").unwrap();
+        }
+        // A record whose file never made it into the trash is ignored
+        storage
+            .write(
+                &record_path("00000000000000ff"),
+                br#"{"id": "00000000000000ff", "originalPath": "lost.md", "deletedAt": 1}"#,
+            )
+            .unwrap();
+        // As is one whose id would lead out of the trash
+        storage.write(Path::new("keep.md"), b"mine").unwrap();
+        storage
+            .write(
+                &record_path("escape"),
+                br#"{"id": "../../keep.md", "originalPath": "keep.md", "deletedAt": 1}"#,
+            )
+            .unwrap();
+
+        let purged = purge(&storage, |e| e.deleted_at < Some(150)).unwrap();
+        assert_eq!(purged.len(), 1);
+        assert_eq!(purged[0].original_path, PathBuf::from("old.md"));
+        assert!(!storage.exists(&data_path("0000000000000000")));
+        assert!(storage.exists(Path::new("keep.md")));
+
+        let remaining = list(&storage).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].original_path, PathBuf::from("new.md"));
+    }
+}
@@ -1,10 +1,29 @@
+use crate::attachments::{
+    self, AttachmentExtractor, AttachmentIndex, AttachmentRef, AttachmentReport, MissingAttachment,
+};
+use crate::batch::{self, Batch, BatchRecovery};
+use crate::cache::{NoteCache, Stamp, DEFAULT_CACHE_BUDGET};
+use crate::config::{VaultSettings, CONFIG_DIR};
 use crate::error::{ArkeError, Result};
+use crate::events::{self, EventBus, VaultEvent};
+use crate::folders::{FileLookup, FolderReport, LinkRewriter, MovedFile};
+use crate::history::{self, DiffLine, Revision};
+use crate::ignore::IgnoreRules;
+use crate::links::{LinkExtractor, LinkIndex};
+use crate::merge::{self, ConflictCopy, MergeResult};
+use crate::parser::MarkdownParser;
+use crate::storage::{is_contained, NativeStorage, Storage};
+use crate::tasks::VaultTask;
+use crate::trash::{self, TrashEntry, TrashedLink};
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 /// Configuration for a vault
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct VaultConfig {
     /// Root path of the vault
     pub path: PathBuf,
@@ -12,10 +31,14 @@ pub struct VaultConfig {
     pub name: String,
     /// Whether to watch for file changes
     pub watch: bool,
+    /// Settings persisted in the vault's `.arke/config.json`
+    #[serde(default)]
+    pub settings: VaultSettings,
 }
 
 /// Represents a markdown note file
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Note {
     /// File path relative to vault root
     pub path: PathBuf,
@@ -25,12 +48,44 @@ pub struct Note {
     pub metadata: HashMap<String, String>,
     /// Last modified timestamp
     pub modified: Option<u64>,
+    /// Hash of the content, see [`content_hash`]
+    #[serde(default)]
+    pub hash: String,
+}
+
+impl Note {
+    /// The version to pass to [`Vault::write_note_if`] when saving edits to this note
+    pub fn version(&self) -> NoteVersion {
+        NoteVersion::Hash(self.hash.clone())
+    }
+}
+
+/// A recorded version of a note, used to detect changes made by someone else
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "lowercase")]
+pub enum NoteVersion {
+    /// Last modified time in seconds, as in [`Note::modified`]
+    ///
+    /// Coarser than a hash: two writes within the same second look alike.
+    Modified(u64),
+    /// Content hash, as in [`Note::hash`]
+    Hash(String),
+    /// The note doesn't exist, for creating it without replacing one made meanwhile
+    Absent,
 }
 
 /// The Vault manages a collection of markdown files
-pub struct Vault {
+///
+/// File access goes through a [`Storage`] backend: the local filesystem by
+/// default, or any other backend via [`Vault::with_storage`].
+pub struct Vault<S: Storage = NativeStorage> {
     config: VaultConfig,
-    notes: HashMap<PathBuf, Note>,
+    storage: S,
+    ignore: IgnoreRules,
+    cache: NoteCache,
+    events: EventBus,
+    /// Paths the vault removed or renamed away, whose watcher events are expected
+    removed: Mutex<HashSet<PathBuf>>,
 }
 
 impl Vault {
@@ -43,13 +98,15 @@ impl Vault {
             )));
         }
 
-        Ok(Self {
-            config,
-            notes: HashMap::new(),
-        })
+        let storage = NativeStorage::new(&config.path);
+        let mut vault = Self::with_storage(config, storage);
+        vault.recover_batch()?;
+        Ok(vault)
     }
 
-    /// Open an existing vault at the given path
+    /// Open an existing vault at the given path, loading its saved settings
+    ///
+    /// A batch interrupted by a crash is recovered first, see [`Vault::recover_batch`].
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
         let name = path
@@ -57,139 +114,1122 @@ impl Vault {
             .and_then(|n| n.to_str())
             .unwrap_or("Untitled")
             .to_string();
+        let settings = VaultSettings::load(&NativeStorage::new(&path))?;
 
         let config = VaultConfig {
             path,
             name,
             watch: false,
+            settings,
         };
 
         Self::new(config)
     }
+}
+
+impl<S: Storage> Vault<S> {
+    /// Create a vault over a custom storage backend
+    ///
+    /// Unlike [`Vault::open`], this doesn't recover interrupted batches; call
+    /// [`Vault::recover_batch`] before making changes.
+    pub fn with_storage(config: VaultConfig, storage: S) -> Self {
+        Self {
+            ignore: ignore_rules(&config),
+            config,
+            storage,
+            cache: NoteCache::new(DEFAULT_CACHE_BUDGET),
+            events: EventBus::new(),
+            removed: Mutex::default(),
+        }
+    }
 
     /// Get the vault configuration
     pub fn config(&self) -> &VaultConfig {
         &self.config
     }
 
-    /// List all markdown files in the vault
+    /// Get the storage backend
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// The bus the vault emits change events on
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Get the vault settings
+    pub fn settings(&self) -> &VaultSettings {
+        &self.config.settings
+    }
+
+    /// Replace the vault settings and save them to `.arke/config.json`
+    pub fn update_settings(&mut self, settings: VaultSettings) -> Result<()> {
+        settings.save(&self.storage)?;
+        self.config.settings = settings;
+        self.ignore = ignore_rules(&self.config);
+        Ok(())
+    }
+
+    /// A parser configured from the vault settings
+    pub(crate) fn parser(&self) -> MarkdownParser {
+        MarkdownParser::with_options(&self.config.settings.parser)
+    }
+
+    /// List all markdown files in the vault, skipping ignored paths
     pub fn list_files(&self) -> Result<Vec<PathBuf>> {
         let mut files = Vec::new();
-        self.walk_dir(&self.config.path, &mut files)?;
+        walk_dir(
+            &self.storage,
+            Path::new(""),
+            &self.ignore,
+            is_markdown,
+            &mut files,
+        )?;
         Ok(files)
     }
 
-    /// Recursively walk directory and collect .md files
-    fn walk_dir(&self, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
-        for entry in std::fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
+    /// List all attachments (non-markdown files) in the vault, skipping ignored paths
+    pub fn list_attachments(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        walk_dir(
+            &self.storage,
+            Path::new(""),
+            &self.ignore,
+            attachments::is_attachment,
+            &mut files,
+        )?;
+        Ok(files)
+    }
 
-            if path.is_dir() {
-                // Skip hidden directories and node_modules
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    if name.starts_with('.') || name == "node_modules" {
-                        continue;
+    /// Save `contents` as a new file in the configured attachment folder
+    ///
+    /// Only the file name of `name` is used. If the name is taken, a numeric
+    /// suffix is added (`image 1.png`). Returns the new attachment's path.
+    pub fn import_attachment(&mut self, name: &str, contents: &[u8]) -> Result<PathBuf> {
+        let file_name = Path::new(name)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| ArkeError::Vault(format!("Invalid attachment name: {}", name)))?;
+
+        let folder = Path::new(&self.config.settings.attachment_folder);
+        if !is_user_folder(folder) {
+            return Err(ArkeError::Config {
+                key: "attachmentFolder".to_string(),
+                message: format!("{:?} must be a folder inside the vault", folder),
+            });
+        }
+        let path = attachments::unique_path(folder, file_name, |p| self.storage.exists(p));
+        self.storage.write(&path, contents)?;
+        Ok(path)
+    }
+
+    /// Resolve an attachment reference made from `note`
+    pub fn resolve_attachment(
+        &self,
+        note: &Path,
+        reference: &AttachmentRef,
+    ) -> Result<Option<PathBuf>> {
+        let index = AttachmentIndex::new(&self.list_attachments()?);
+        Ok(index.resolve(reference, note).cloned())
+    }
+
+    /// Find attachments no note references, and references to missing attachments
+    pub fn attachment_report(&self) -> Result<AttachmentReport> {
+        let attachments = self.list_attachments()?;
+        let index = AttachmentIndex::new(&attachments);
+        let extractor = AttachmentExtractor::new();
+
+        let mut referenced = HashSet::new();
+        let mut missing = Vec::new();
+        let mut notes = self.list_files()?;
+        notes.sort();
+
+        for path in notes {
+            let note = self.read_note(&path)?;
+            for reference in extractor.extract(&note.content) {
+                match index.resolve(&reference, &path) {
+                    Some(attachment) => {
+                        referenced.insert(attachment.clone());
                     }
-                }
-                self.walk_dir(&path, files)?;
-            } else if path.extension().and_then(|e| e.to_str()) == Some("md") {
-                if let Ok(relative) = path.strip_prefix(&self.config.path) {
-                    files.push(relative.to_path_buf());
+                    None => missing.push(MissingAttachment {
+                        note: path.clone(),
+                        target: reference.target,
+                    }),
                 }
             }
         }
-        Ok(())
+
+        let mut orphaned: Vec<PathBuf> = attachments
+            .into_iter()
+            .filter(|a| !referenced.contains(a))
+            .collect();
+        orphaned.sort();
+
+        Ok(AttachmentReport { orphaned, missing })
     }
 
-    /// Read a note file
-    pub fn read_note<P: AsRef<Path>>(&mut self, path: P) -> Result<&Note> {
-        let path = path.as_ref().to_path_buf();
-        let full_path = self.config.path.join(&path);
+    /// Whether `path` is excluded by the vault's ignore rules
+    ///
+    /// Use this to filter paths that don't come from [`Vault::list_files`],
+    /// such as file watcher events.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        self.ignore.is_ignored(&self.storage, path)
+    }
+
+    /// The ignore rules from the vault configuration
+    #[cfg(feature = "native")]
+    pub(crate) fn ignore_rules(&self) -> &IgnoreRules {
+        &self.ignore
+    }
+
+    /// Read a note, from the cache if the file hasn't changed since it was cached
+    pub fn read_note<P: AsRef<Path>>(&self, path: P) -> Result<Arc<Note>> {
+        let path = path.as_ref();
+        let stamp = Stamp::of(&self.storage.metadata(path)?);
+        if let Some(note) = stamp.and_then(|stamp| self.cache.get(path, stamp)) {
+            return Ok(note);
+        }
 
-        if !full_path.exists() {
-            return Err(ArkeError::FileNotFound(path.display().to_string()));
+        let note = Arc::new(load_note(&self.storage, path)?);
+        if let Some(stamp) = stamp {
+            self.cache.insert(Arc::clone(&note), stamp);
         }
+        Ok(note)
+    }
+
+    /// Set the note cache's memory budget in bytes, evicting notes if it shrank
+    ///
+    /// Defaults to [`DEFAULT_CACHE_BUDGET`].
+    pub fn set_cache_budget(&self, bytes: usize) {
+        self.cache.set_budget(bytes);
+    }
 
-        let content = std::fs::read_to_string(&full_path)?;
-        let metadata = HashMap::new(); // TODO: Parse frontmatter
+    /// Drop cached copies of `path`, or of everything beneath it if it's a folder
+    ///
+    /// Call this for changes reported by a file watcher.
+    pub fn invalidate(&self, path: &Path) {
+        self.cache.invalidate(path);
+    }
 
-        let modified = full_path
-            .metadata()
-            .ok()
-            .and_then(|m| m.modified().ok())
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs());
+    /// Invalidate the notes touched by a file watcher event and emit the changes it reports
+    ///
+    /// With [`VaultConfig::watch`] set, changes the vault made itself aren't
+    /// reported a second time when the watcher sees them.
+    #[cfg(feature = "native")]
+    pub fn handle_watch_event(&self, event: &notify::Event) {
+        use notify::event::{EventKind, ModifyKind, RenameMode};
 
-        let note = Note {
-            path: path.clone(),
-            content,
-            metadata,
-            modified,
+        if event.need_rescan() {
+            self.cache.clear();
+            return;
+        }
+        let mut paths = Vec::new();
+        for path in &event.paths {
+            match self.relative_path(path) {
+                Some(path) => paths.push(path),
+                None => {
+                    self.cache.clear();
+                    return;
+                }
+            }
+        }
+
+        if !self.events.has_subscribers() {
+            for path in &paths {
+                self.invalidate(path);
+            }
+            return;
+        }
+        let changes: Vec<VaultEvent> = match (event.kind, paths.as_slice()) {
+            (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) => {
+                self.disk_rename(from, to)
+            }
+            (EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_), _) => {
+                paths.iter().flat_map(|p| self.disk_remove(p)).collect()
+            }
+            (EventKind::Create(_), _) => paths
+                .iter()
+                .flat_map(|p| self.disk_change(p, true))
+                .collect(),
+            (EventKind::Modify(_), _) => paths
+                .iter()
+                .flat_map(|p| self.disk_change(p, false))
+                .collect(),
+            _ => {
+                for path in &paths {
+                    self.invalidate(path);
+                }
+                Vec::new()
+            }
         };
+        for change in &changes {
+            self.events.emit(change);
+        }
+    }
 
-        self.notes.insert(path.clone(), note);
-        Ok(self.notes.get(&path).unwrap())
+    /// A watcher path relative to the vault root
+    #[cfg(feature = "native")]
+    fn relative_path(&self, path: &Path) -> Option<PathBuf> {
+        if let Ok(relative) = path.strip_prefix(&self.config.path) {
+            return Some(relative.to_path_buf());
+        }
+        // Watchers report canonical paths, which the vault path may not be
+        let root = self.config.path.canonicalize().ok()?;
+        path.strip_prefix(root).ok().map(Path::to_path_buf)
     }
 
-    /// Write a note to disk
-    pub fn write_note(&mut self, path: &Path, content: &str) -> Result<()> {
-        let full_path = self.config.path.join(path);
+    /// Events for a file created or modified on disk
+    ///
+    /// The cached copy, if any, is the last version the vault saw. A cached
+    /// copy that is still current means the vault made the change itself.
+    #[cfg(feature = "native")]
+    fn disk_change(&self, path: &Path, created: bool) -> Vec<VaultEvent> {
+        // Gone again already; its removal is reported by an event of its own
+        let Ok(metadata) = self.storage.metadata(path) else {
+            self.invalidate(path);
+            return Vec::new();
+        };
+        if metadata.is_dir || !is_markdown(path) || self.is_ignored(path) {
+            self.invalidate(path);
+            return Vec::new();
+        }
 
-        // Create parent directories if needed
-        if let Some(parent) = full_path.parent() {
-            std::fs::create_dir_all(parent)?;
+        let previous = self.cache.peek(path);
+        if previous
+            .as_ref()
+            .is_some_and(|(_, stamp)| Some(*stamp) == Stamp::of(&metadata))
+        {
+            return Vec::new();
         }
+        let Ok(note) = self.read_note(path) else {
+            self.invalidate(path);
+            return Vec::new();
+        };
 
-        std::fs::write(&full_path, content)?;
+        match previous {
+            Some((old, _)) => events::write_events(path, Some(&old.content), &note.content),
+            None if created => events::write_events(path, None, &note.content),
+            // Modified, but we never saw what it was before
+            None => vec![VaultEvent::NoteUpdated {
+                path: path.to_path_buf(),
+            }],
+        }
+    }
 
-        // Update cache
-        let note = Note {
+    /// Events for a file removed on disk
+    #[cfg(feature = "native")]
+    fn disk_remove(&self, path: &Path) -> Vec<VaultEvent> {
+        self.invalidate(path);
+        let ours = self.removed.lock().unwrap().remove(path);
+        if ours || self.storage.exists(path) || !is_markdown(path) || self.is_ignored(path) {
+            return Vec::new();
+        }
+        vec![VaultEvent::NoteDeleted {
             path: path.to_path_buf(),
-            content: content.to_string(),
-            metadata: HashMap::new(),
-            modified: None,
+        }]
+    }
+
+    /// Events for a file or folder renamed on disk
+    #[cfg(feature = "native")]
+    fn disk_rename(&self, from: &Path, to: &Path) -> Vec<VaultEvent> {
+        {
+            // The vault renamed it, and reported each note it moved
+            let mut removed = self.removed.lock().unwrap();
+            let before = removed.len();
+            removed.retain(|p| !p.starts_with(from));
+            if removed.len() != before {
+                self.invalidate(from);
+                return Vec::new();
+            }
+        }
+        self.invalidate(from);
+        self.invalidate(to);
+
+        let is_note = |p: &Path| is_markdown(p) && !self.is_ignored(p);
+        let mut moves = Vec::new();
+        if self.storage.metadata(to).is_ok_and(|m| m.is_dir) {
+            let mut notes = Vec::new();
+            if walk_dir(&self.storage, to, &self.ignore, is_markdown, &mut notes).is_ok() {
+                notes.sort();
+                for note in notes {
+                    moves.push((from.join(note.strip_prefix(to).unwrap()), note));
+                }
+            }
+        } else {
+            moves.push((from.to_path_buf(), to.to_path_buf()));
+        }
+
+        let mut changes = Vec::new();
+        for (from, to) in moves {
+            match (is_note(&from), is_note(&to)) {
+                (true, true) => changes.push(VaultEvent::NoteRenamed { from, to }),
+                (true, false) => changes.push(VaultEvent::NoteDeleted { path: from }),
+                (false, true) => changes.extend(self.disk_change(&to, true)),
+                (false, false) => {}
+            }
+        }
+        changes
+    }
+
+    /// Emit events for a change made through the vault
+    ///
+    /// When watching, the changed notes are cached and removed paths
+    /// remembered, so the watcher's reports of the same change are recognized.
+    fn publish(&self, changes: Vec<VaultEvent>) {
+        if changes.is_empty() {
+            return;
+        }
+        if self.config.watch {
+            for change in &changes {
+                match change {
+                    VaultEvent::NoteCreated { path } | VaultEvent::NoteUpdated { path } => {
+                        let _ = self.read_note(path);
+                    }
+                    VaultEvent::NoteRenamed { from, to } => {
+                        self.removed.lock().unwrap().insert(from.clone());
+                        let _ = self.read_note(to);
+                    }
+                    VaultEvent::NoteDeleted { path } => {
+                        self.removed.lock().unwrap().insert(path.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for change in &changes {
+            self.events.emit(change);
+        }
+    }
+
+    /// Write a note to storage, snapshotting its previous content if history is enabled
+    pub fn write_note(&mut self, path: &Path, content: &str) -> Result<()> {
+        let previous = if self.events.has_subscribers() {
+            match self.storage.read_to_string(path) {
+                Ok(previous) => Some(previous),
+                Err(ArkeError::FileNotFound(_)) => None,
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
         };
-        self.notes.insert(path.to_path_buf(), note);
 
+        history::record(
+            &self.storage,
+            &self.config.settings.history,
+            path,
+            content.as_bytes(),
+        )?;
+        self.storage.write(path, content.as_bytes())?;
+
+        // Cache what was just written, stamped as it is now on disk
+        self.cache.invalidate(path);
+        if let Some(stamp) = self.storage.metadata(path).ok().and_then(|m| Stamp::of(&m)) {
+            let note = Note {
+                path: path.to_path_buf(),
+                content: content.to_string(),
+                metadata: HashMap::new(),
+                modified: modified_secs(&self.storage, path),
+                hash: content_hash(content.as_bytes()),
+            };
+            self.cache.insert(Arc::new(note), stamp);
+        }
+
+        if self.events.has_subscribers() {
+            self.publish(events::write_events(path, previous.as_deref(), content));
+        }
         Ok(())
     }
 
-    /// Delete a note
-    pub fn delete_note(&mut self, path: &Path) -> Result<()> {
-        let full_path = self.config.path.join(path);
-        std::fs::remove_file(&full_path)?;
-        self.notes.remove(path);
-        Ok(())
+    /// Write a note only if storage still holds the `expected` version
+    ///
+    /// Fails with [`ArkeError::Conflict`], carrying our content and the stored
+    /// content, if the note was changed or deleted since `expected` was
+    /// recorded, or with [`NoteVersion::Absent`], if it now exists. Returns
+    /// the version just written.
+    pub fn write_note_if(
+        &mut self,
+        path: &Path,
+        content: &str,
+        expected: &NoteVersion,
+    ) -> Result<NoteVersion> {
+        check_version(&self.storage, path, content, expected)?;
+        self.write_note(path, content)?;
+        Ok(NoteVersion::Hash(content_hash(content.as_bytes())))
+    }
+
+    /// Delete a note by moving it to the vault trash
+    pub fn delete_note(&mut self, path: &Path) -> Result<TrashEntry> {
+        let entry = trash::move_to_trash(&self.storage, path)?;
+        self.cache.invalidate(path);
+        if is_markdown(path) {
+            self.publish(vec![VaultEvent::NoteDeleted {
+                path: path.to_path_buf(),
+            }]);
+        }
+        Ok(entry)
+    }
+
+    /// Apply writes, renames and deletes together, rolling all of them back if any fails
+    ///
+    /// Writes record history like [`Vault::write_note`] and deletes move
+    /// notes to the trash, whose entries are returned. An undo journal in
+    /// `.arke/journal` lets [`Vault::recover_batch`] clean up if the process
+    /// dies midway.
+    pub fn apply_batch(&mut self, batch: &Batch) -> Result<Vec<TrashEntry>> {
+        let changes = if self.events.has_subscribers() {
+            events::batch_events(&self.storage, batch)?
+        } else {
+            Vec::new()
+        };
+
+        let result = batch::apply(&self.storage, &self.config.settings.history, batch);
+        for path in batch.paths() {
+            self.cache.invalidate(path);
+        }
+        let trashed = result?;
+        self.publish(changes);
+        Ok(trashed)
+    }
+
+    /// Roll back, or finish if it had committed, a batch interrupted by a crash
+    ///
+    /// Returns `None` if there was nothing to recover.
+    pub fn recover_batch(&mut self) -> Result<Option<BatchRecovery>> {
+        let recovery = batch::recover(&self.storage, &self.config.settings.history)?;
+        if recovery.is_some() {
+            self.cache.clear();
+        }
+        Ok(recovery)
+    }
+
+    /// Notes in the trash, most recently deleted first
+    pub fn list_trash(&self) -> Result<Vec<TrashEntry>> {
+        trash::list(&self.storage)
+    }
+
+    /// Restore a note from the trash, returning the path it was restored to
+    ///
+    /// If its original path has been taken since, the note gets a numbered
+    /// name such as `note 1.md` next to it.
+    pub fn restore_from_trash(&mut self, id: &str) -> Result<PathBuf> {
+        let path = trash::restore(&self.storage, id)?;
+        self.cache.invalidate(&path);
+        if self.events.has_subscribers() && is_markdown(&path) {
+            let content = self.storage.read_to_string(&path)?;
+            self.publish(events::write_events(&path, None, &content));
+        }
+        Ok(path)
+    }
+
+    /// Permanently delete everything in the trash, returning the removed entries
+    pub fn empty_trash(&mut self) -> Result<Vec<TrashEntry>> {
+        trash::purge(&self.storage, |_| true)
+    }
+
+    /// Permanently delete trash entries deleted before `cutoff` (seconds since the Unix epoch)
+    ///
+    /// Entries without a recorded deletion time are kept.
+    pub fn purge_trash(&mut self, cutoff: u64) -> Result<Vec<TrashEntry>> {
+        trash::purge(&self.storage, |e| e.deleted_at.is_some_and(|t| t < cutoff))
+    }
+
+    /// Links and embeds that no longer resolve because their target is in the trash
+    ///
+    /// Both wikilinks and markdown links count. Links are ordered by note, then
+    /// by position.
+    pub fn links_to_trash(&self) -> Result<Vec<TrashedLink>> {
+        let trashed = self.list_trash()?;
+        if trashed.is_empty() {
+            return Ok(Vec::new());
+        }
+        let trashed_paths: Vec<PathBuf> = trashed.iter().map(|e| e.original_path.clone()).collect();
+        let trash_index = LinkIndex::new(&trashed_paths);
+        let trash_lookup = FileLookup::new(&trashed_paths);
+
+        let mut notes = self.list_files()?;
+        let mut files = notes.clone();
+        files.extend(self.list_attachments()?);
+        let index = LinkIndex::new(&files);
+        let lookup = FileLookup::new(&files);
+        let extractor = LinkExtractor::new();
+        let markdown = AttachmentExtractor::new();
+        notes.sort();
+
+        // Most recently deleted first, so that entry wins
+        let entry_for = |original: &Path| {
+            trashed
+                .iter()
+                .find(|e| e.original_path == original)
+                .unwrap()
+                .clone()
+        };
+
+        let mut links = Vec::new();
+        for path in notes {
+            let note = self.read_note(&path)?;
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let mut found = Vec::new();
+
+            for link in extractor.extract(&note.content) {
+                if index.resolve(&link.target).is_some() {
+                    continue;
+                }
+                if let Some(original) = trash_index.resolve(&link.target) {
+                    found.push((link.position, link.target, entry_for(original)));
+                }
+            }
+            for link in markdown.markdown_links(&note.content) {
+                if lookup.resolve_markdown(&link.target, dir).is_some() {
+                    continue;
+                }
+                if let Some((original, _)) = trash_lookup.resolve_markdown(&link.target, dir) {
+                    found.push((link.position, link.target, entry_for(&original)));
+                }
+            }
+
+            found.sort_by_key(|(position, _, _)| *position);
+            links.extend(found.into_iter().map(|(_, target, entry)| TrashedLink {
+                source: path.clone(),
+                target,
+                entry,
+            }));
+        }
+        Ok(links)
     }
 
-    /// Rename/move a note
+    /// Rename/move a note, along with its history
     pub fn rename_note(&mut self, old_path: &Path, new_path: &Path) -> Result<()> {
-        let old_full = self.config.path.join(old_path);
-        let new_full = self.config.path.join(new_path);
+        self.storage.rename(old_path, new_path)?;
+        // The rename already happened, so a failed history move mustn't report otherwise
+        let _ = history::rename(&self.storage, old_path, new_path);
+
+        self.cache.invalidate(old_path);
+        self.cache.invalidate(new_path);
+        // Renaming to or from another file type creates or deletes a note
+        let changes = match (is_markdown(old_path), is_markdown(new_path)) {
+            (true, true) => vec![VaultEvent::NoteRenamed {
+                from: old_path.to_path_buf(),
+                to: new_path.to_path_buf(),
+            }],
+            (true, false) => vec![VaultEvent::NoteDeleted {
+                path: old_path.to_path_buf(),
+            }],
+            (false, true) => match self.storage.read_to_string(new_path) {
+                Ok(content) => events::write_events(new_path, None, &content),
+                Err(_) => vec![VaultEvent::NoteCreated {
+                    path: new_path.to_path_buf(),
+                }],
+            },
+            (false, false) => Vec::new(),
+        };
+        self.publish(changes);
+        Ok(())
+    }
+
+    /// Recorded versions of a note, newest first
+    pub fn note_history(&self, path: &Path) -> Result<Vec<Revision>> {
+        history::revisions(&self.storage, path)
+    }
+
+    /// Content of a recorded version of a note
+    pub fn read_revision(&self, path: &Path, id: u64) -> Result<String> {
+        history::read_revision(&self.storage, path, id)
+    }
+
+    /// Line diff between two versions of a note; `None` stands for the current content
+    pub fn diff_revisions(
+        &self,
+        path: &Path,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> Result<Vec<DiffLine>> {
+        let content = |id: Option<u64>| match id {
+            Some(id) => self.read_revision(path, id),
+            None => self.storage.read_to_string(path),
+        };
+        Ok(history::diff(&content(from)?, &content(to)?))
+    }
+
+    /// Replace a note's content with a recorded version
+    ///
+    /// The content being replaced is snapshotted first, so a restore can be undone.
+    pub fn restore_revision(&mut self, path: &Path, id: u64) -> Result<()> {
+        let content = self.read_revision(path, id)?;
+        self.write_note(path, &content)
+    }
 
-        // Create parent directories for new path
-        if let Some(parent) = new_full.parent() {
-            std::fs::create_dir_all(parent)?;
+    /// Create an empty folder, along with any missing parents
+    pub fn create_folder(&mut self, path: &Path) -> Result<()> {
+        check_folder_path(path)?;
+        if self.storage.metadata(path).is_ok_and(|m| !m.is_dir) {
+            return Err(ArkeError::Vault(format!(
+                "A file already exists at {}",
+                path.display()
+            )));
+        }
+        self.storage.create_dir(path)
+    }
+
+    /// Rename or move a folder and everything in it, updating links that spell out moved paths
+    ///
+    /// Wikilinks such as `[[folder/note]]` and markdown links and embeds
+    /// anywhere in the vault are rewritten, as are relative links inside the
+    /// moved notes. All changes are applied as one batch.
+    pub fn rename_folder(&mut self, from: &Path, to: &Path) -> Result<FolderReport> {
+        check_folder_path(from)?;
+        check_folder_path(to)?;
+        if !self.storage.metadata(from)?.is_dir {
+            return Err(ArkeError::Vault(format!(
+                "Not a folder: {}",
+                from.display()
+            )));
+        }
+        if to.starts_with(from) {
+            return Err(ArkeError::Vault(format!(
+                "Cannot move {} into itself",
+                from.display()
+            )));
+        }
+        if self.storage.exists(to) {
+            return Err(ArkeError::Vault(format!("{} already exists", to.display())));
+        }
+
+        let mut contents = Vec::new();
+        files_under(&self.storage, from, &mut contents, &mut Vec::new())?;
+        contents.sort();
+        let moved = |p: &Path| p.strip_prefix(from).ok().map(|rest| to.join(rest));
+
+        let mut files = self.list_files()?;
+        files.extend(self.list_attachments()?);
+        let lookup = FileLookup::new(&files);
+        let rewriter = LinkRewriter::new();
+
+        let mut batch = Batch::new();
+        batch.rename(from, to);
+        let mut updated = Vec::new();
+        for note in self.list_files()? {
+            let new_note = moved(&note).unwrap_or_else(|| note.clone());
+            let content = self.storage.read_to_string(&note)?;
+            if let Some(content) = rewriter.rewrite(&content, &note, &new_note, &lookup, moved) {
+                batch.write(new_note.clone(), content);
+                updated.push(new_note);
+            }
+        }
+        updated.sort();
+
+        self.apply_batch(&batch)?;
+        // The folder has moved either way; history left at `from` is only harder to find
+        let _ = history::rename(&self.storage, from, to);
+        self.cache.invalidate(from);
+
+        Ok(FolderReport {
+            moved: contents
+                .into_iter()
+                .map(|from| MovedFile {
+                    to: moved(&from).unwrap(),
+                    from,
+                })
+                .collect(),
+            updated,
+            trashed: Vec::new(),
+        })
+    }
+
+    /// Move a folder into `parent` (`""` for the vault root), keeping its name
+    ///
+    /// See [`Vault::rename_folder`].
+    pub fn move_folder(&mut self, from: &Path, parent: &Path) -> Result<FolderReport> {
+        let name = from
+            .file_name()
+            .ok_or_else(|| ArkeError::Vault(format!("Not a folder: {}", from.display())))?;
+        self.rename_folder(from, &parent.join(name))
+    }
+
+    /// Move every file in a folder to the trash, then remove the folder
+    ///
+    /// Each file gets its own trash entry, so links to them show up in
+    /// [`Vault::links_to_trash`] and they can be restored one at a time.
+    pub fn delete_folder(&mut self, path: &Path) -> Result<FolderReport> {
+        check_folder_path(path)?;
+        if !self.storage.metadata(path)?.is_dir {
+            return Err(ArkeError::Vault(format!(
+                "Not a folder: {}",
+                path.display()
+            )));
         }
 
-        std::fs::rename(&old_full, &new_full)?;
+        let (mut files, mut dirs) = (Vec::new(), vec![path.to_path_buf()]);
+        files_under(&self.storage, path, &mut files, &mut dirs)?;
+        files.sort();
+
+        let mut batch = Batch::new();
+        for file in &files {
+            batch.delete(file.clone());
+        }
+        let trashed = self.apply_batch(&batch)?;
 
-        // Update cache
-        if let Some(note) = self.notes.remove(old_path) {
-            let mut note = note;
-            note.path = new_path.to_path_buf();
-            self.notes.insert(new_path.to_path_buf(), note);
+        // Deepest first, so each folder is empty by the time it's removed
+        dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+        for dir in dirs {
+            self.storage.remove_dir(&dir)?;
         }
 
+        Ok(FolderReport {
+            trashed,
+            ..Default::default()
+        })
+    }
+
+    /// Find sync-service conflict copies (e.g. `note (conflicted copy).md`) whose note still exists
+    pub fn conflict_copies(&self) -> Result<Vec<ConflictCopy>> {
+        let files = self.list_files()?;
+        let mut copies: Vec<ConflictCopy> = files
+            .iter()
+            .filter_map(|copy| {
+                let original = merge::conflict_copy_original(copy)?;
+                files.contains(&original).then(|| ConflictCopy {
+                    original,
+                    copy: copy.clone(),
+                })
+            })
+            .collect();
+        copies.sort_by(|a, b| a.copy.cmp(&b.copy));
+        Ok(copies)
+    }
+
+    /// Merge a conflict copy with its note
+    ///
+    /// Sync services keep no common ancestor, so this is a two-way merge with
+    /// the note as "ours" and the copy as "theirs". Nothing is written.
+    pub fn merge_conflict_copy(&self, copy: &ConflictCopy) -> Result<MergeResult> {
+        let ours = self.read_note(&copy.original)?.content.clone();
+        let theirs = self.read_note(&copy.copy)?.content.clone();
+        Ok(merge::merge_two_way(&ours, &theirs))
+    }
+
+    /// Collect every task list item in the vault
+    pub fn tasks(&self) -> Result<Vec<VaultTask>> {
+        let parser = self.parser();
+        let mut tasks = Vec::new();
+
+        for path in self.list_files()? {
+            let note = self.read_note(&path)?;
+            for task in parser.parse_tasks(&note.content) {
+                tasks.push(VaultTask {
+                    path: path.clone(),
+                    task,
+                });
+            }
+        }
+
+        Ok(tasks)
+    }
+
+    /// Open tasks ordered by due date, soonest first; undated tasks come last
+    pub fn open_tasks_by_due(&self) -> Result<Vec<VaultTask>> {
+        let mut tasks: Vec<VaultTask> = self
+            .tasks()?
+            .into_iter()
+            .filter(|t| !t.task.checked)
+            .collect();
+        tasks.sort_by(|a, b| {
+            let key = |t: &VaultTask| (t.task.due.is_none(), t.task.due);
+            key(a)
+                .cmp(&key(b))
+                .then_with(|| a.path.cmp(&b.path))
+                .then_with(|| a.task.line.cmp(&b.task.line))
+        });
+        Ok(tasks)
+    }
+
+    /// Open tasks whose due date is before `today`, soonest first
+    pub fn overdue_tasks(&self, today: NaiveDate) -> Result<Vec<VaultTask>> {
+        Ok(self
+            .open_tasks_by_due()?
+            .into_iter()
+            .filter(|t| t.task.due.is_some_and(|due| due < today))
+            .collect())
+    }
+
+    /// Tasks tagged with `tag` (or a nested tag under it)
+    pub fn tasks_with_tag(&self, tag: &str) -> Result<Vec<VaultTask>> {
+        Ok(self
+            .tasks()?
+            .into_iter()
+            .filter(|t| t.task.has_tag(tag))
+            .collect())
+    }
+
+    /// Ensure the block on `line` (1-based) has a `^block-id` and return a reference to it
+    ///
+    /// An existing id is reused. Otherwise a new id, derived from the block text and
+    /// unique within the note, is appended to the block and the note is written back.
+    /// Returns a `[[note#^id]]` wikilink, naming the note by path if its name is ambiguous.
+    pub fn create_block_reference(&mut self, path: &Path, line: usize) -> Result<String> {
+        let note = self.read_note(path)?;
+        let (content, version) = (note.content.clone(), note.version());
+        let blocks = self.parser().parse_blocks(&content);
+
+        let block = blocks
+            .iter()
+            .filter(|b| b.start_line <= line && line <= b.end_line)
+            .min_by_key(|b| b.end_line - b.start_line)
+            .ok_or_else(|| {
+                ArkeError::Vault(format!(
+                    "No referenceable block at line {} in {}",
+                    line,
+                    path.display()
+                ))
+            })?;
+
+        let id = match &block.id {
+            Some(id) => id.clone(),
+            None => {
+                let id = generate_block_id(&block.text, |candidate| {
+                    blocks.iter().any(|b| b.id.as_deref() == Some(candidate))
+                });
+
+                let mut updated = String::with_capacity(content.len() + id.len() + 2);
+                for (i, source_line) in content.split_inclusive('\n').enumerate() {
+                    if i + 1 == block.marker_line {
+                        let text = source_line.trim_end_matches(['\r', '\n']);
+                        updated.push_str(text.trim_end());
+                        updated.push_str(" ^");
+                        updated.push_str(&id);
+                        updated.push_str(&source_line[text.len()..]);
+                    } else {
+                        updated.push_str(source_line);
+                    }
+                }
+
+                self.write_note_if(path, &updated, &version)?;
+                id
+            }
+        };
+
+        Ok(format!("[[{}#^{}]]", self.wikilink_target(path)?, id))
+    }
+
+    /// Toggle the checkbox of the task on `line` (1-based) and write the note back
+    ///
+    /// Returns the new checked state.
+    pub fn toggle_task(&mut self, path: &Path, line: usize) -> Result<bool> {
+        let note = self.read_note(path)?;
+        let (content, version) = (note.content.clone(), note.version());
+
+        let is_task = self
+            .parser()
+            .parse_tasks(&content)
+            .iter()
+            .any(|t| t.line == line);
+        if !is_task {
+            return Err(ArkeError::Vault(format!(
+                "No task at line {} in {}",
+                line,
+                path.display()
+            )));
+        }
+
+        let start: usize = content
+            .split_inclusive('\n')
+            .take(line - 1)
+            .map(str::len)
+            .sum();
+        let end = content[start..]
+            .find('\n')
+            .map_or(content.len(), |i| start + i);
+
+        let source_line = &content[start..end];
+        let checkbox = source_line
+            .find('[')
+            .filter(|i| source_line[i + 1..].chars().nth(1) == Some(']'))
+            .ok_or_else(|| ArkeError::Parse(format!("Malformed task at line {}", line)))?;
+
+        let checked = source_line[checkbox + 1..].starts_with(' ');
+        let mark = if checked { "x" } else { " " };
+
+        let mut updated = String::with_capacity(content.len());
+        updated.push_str(&content[..start + checkbox + 1]);
+        updated.push_str(mark);
+        updated.push_str(&content[start + checkbox + 2..]);
+
+        self.write_note_if(path, &updated, &version)?;
+        Ok(checked)
+    }
+}
+
+/// Build the root ignore rules for a vault: built-in defaults, then configured patterns
+pub(crate) fn ignore_rules(config: &VaultConfig) -> IgnoreRules {
+    let mut rules = IgnoreRules::with_defaults();
+    for pattern in &config.settings.ignore {
+        rules.add_pattern(Path::new(""), pattern);
+    }
+    rules
+}
+
+/// Recursively walk directory and collect files accepted by `keep` that aren't ignored
+pub(crate) fn walk_dir<S: Storage + ?Sized>(
+    storage: &S,
+    dir: &Path,
+    rules: &IgnoreRules,
+    keep: fn(&Path) -> bool,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let entries = storage.list(dir)?;
+    let scoped = rules.scoped(storage, dir, &entries)?;
+    let rules = scoped.as_ref().unwrap_or(rules);
+
+    for entry in entries {
+        if rules.matches(&entry.path, entry.is_dir) {
+            continue;
+        }
+
+        if entry.is_dir {
+            walk_dir(storage, &entry.path, rules, keep, files)?;
+        } else if keep(&entry.path) {
+            files.push(entry.path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether `path` is a folder inside the vault, outside Arke's own data folder; `""` is the root
+pub(crate) fn is_user_folder(path: &Path) -> bool {
+    is_contained(path) && !path.starts_with(CONFIG_DIR)
+}
+
+/// Reject the vault root, Arke's own data folder and paths leading outside the vault
+fn check_folder_path(path: &Path) -> Result<()> {
+    if path.as_os_str().is_empty() || !is_user_folder(path) {
+        return Err(ArkeError::Vault(format!(
+            "Not a folder that can be changed: {:?}",
+            path
+        )));
+    }
+    Ok(())
+}
+
+/// Collect every file and subfolder under `dir`, ignored or not
+pub(crate) fn files_under<S: Storage + ?Sized>(
+    storage: &S,
+    dir: &Path,
+    files: &mut Vec<PathBuf>,
+    dirs: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in storage.list(dir)? {
+        if entry.is_dir {
+            files_under(storage, &entry.path, files, dirs)?;
+            dirs.push(entry.path);
+        } else {
+            files.push(entry.path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether a path names a markdown note
+pub(crate) fn is_markdown(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some("md")
+}
+
+/// Read a note and its metadata from storage
+pub(crate) fn load_note<S: Storage + ?Sized>(storage: &S, path: &Path) -> Result<Note> {
+    let content = storage.read_to_string(path)?;
+    let metadata = HashMap::new(); // TODO: Parse frontmatter
+
+    Ok(Note {
+        path: path.to_path_buf(),
+        hash: content_hash(content.as_bytes()),
+        content,
+        metadata,
+        modified: modified_secs(storage, path),
+    })
+}
+
+/// Last modified time of a file in seconds since the Unix epoch
+fn modified_secs<S: Storage + ?Sized>(storage: &S, path: &Path) -> Option<u64> {
+    storage
+        .metadata(path)
+        .ok()
+        .and_then(|m| m.modified)
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+}
+
+/// Hash of a note's content, as 16 hex digits
+///
+/// Stable across runs and platforms, so hosts may keep it between sessions.
+pub fn content_hash(content: &[u8]) -> String {
+    format!("{:016x}", fnv1a(content.iter().copied()))
+}
+
+/// 64-bit FNV-1a
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Fail with [`ArkeError::Conflict`] unless `path` is still at the `expected` version
+///
+/// Backends without modification times never match a [`NoteVersion::Modified`].
+pub(crate) fn check_version<S: Storage + ?Sized>(
+    storage: &S,
+    path: &Path,
+    ours: &str,
+    expected: &NoteVersion,
+) -> Result<()> {
+    let theirs = match storage.read_to_string(path) {
+        Ok(content) => Some(content),
+        Err(ArkeError::FileNotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    let unchanged = match (expected, &theirs) {
+        (NoteVersion::Absent, theirs) => theirs.is_none(),
+        (_, None) => false,
+        (NoteVersion::Hash(hash), Some(content)) => content_hash(content.as_bytes()) == *hash,
+        (NoteVersion::Modified(secs), Some(_)) => modified_secs(storage, path) == Some(*secs),
+    };
+
+    if unchanged {
         Ok(())
+    } else {
+        Err(ArkeError::Conflict {
+            path: path.display().to_string(),
+            ours: ours.to_string(),
+            theirs,
+        })
+    }
+}
+
+/// Derive a short block id from block text, retrying until `taken` rejects it
+fn generate_block_id(text: &str, taken: impl Fn(&str) -> bool) -> String {
+    const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+    let mut salt: u64 = 0;
+    loop {
+        // FNV-1a, so the same text yields the same id across runs
+        let hash = fnv1a(text.bytes().chain(salt.to_le_bytes()));
+
+        let id: String = (0..6)
+            .map(|i| ALPHABET[((hash >> (i * 6)) % 36) as usize] as char)
+            .collect();
+        if !taken(&id) {
+            return id;
+        }
+        salt += 1;
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
+    use crate::storage::MemoryStorage;
     use tempfile::TempDir;
 
     fn create_test_vault() -> (TempDir, Vault) {
@@ -217,6 +1257,29 @@ mod tests {
         assert_eq!(note.content, content);
     }
 
+    #[test]
+    fn test_note_cache() {
+        let (temp, mut vault) = create_test_vault();
+        let path = Path::new("cached.md");
+        vault.write_note(path, "one").unwrap();
+
+        let first = vault.read_note(path).unwrap();
+        assert!(Arc::ptr_eq(&first, &vault.read_note(path).unwrap()));
+
+        // An edit made outside the vault changes the file's size, so it misses
+        std::fs::write(temp.path().join(path), "edited outside").unwrap();
+        assert_eq!(vault.read_note(path).unwrap().content, "edited outside");
+
+        vault.invalidate(path);
+        let reread = vault.read_note(path).unwrap();
+        assert!(Arc::ptr_eq(&reread, &vault.read_note(path).unwrap()));
+
+        // Notes over budget are read fresh each time
+        vault.set_cache_budget(0);
+        let uncached = vault.read_note(path).unwrap();
+        assert!(!Arc::ptr_eq(&uncached, &vault.read_note(path).unwrap()));
+    }
+
     #[test]
     fn test_list_files() {
         let (_temp, mut vault) = create_test_vault();
@@ -227,4 +1290,657 @@ mod tests {
         let files = vault.list_files().unwrap();
         assert_eq!(files.len(), 2);
     }
+
+    #[test]
+    fn test_memory_backed_vault() {
+        let config = VaultConfig {
+            path: PathBuf::from("memory"),
+            name: "memory".to_string(),
+            watch: false,
+            settings: VaultSettings::default(),
+        };
+        let mut vault = Vault::with_storage(config, MemoryStorage::new());
+
+        vault.write_note(Path::new("a.md"), "alpha").unwrap();
+        vault.write_note(Path::new("sub/b.md"), "beta").unwrap();
+        vault
+            .write_note(Path::new(".hidden/c.md"), "hidden")
+            .unwrap();
+        vault.storage().write(Path::new("img.png"), b"png").unwrap();
+
+        let mut files = vault.list_files().unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![PathBuf::from("a.md"), PathBuf::from("sub/b.md")]
+        );
+
+        vault
+            .rename_note(Path::new("sub/b.md"), Path::new("c.md"))
+            .unwrap();
+        assert_eq!(vault.read_note("c.md").unwrap().content, "beta");
+
+        vault.delete_note(Path::new("a.md")).unwrap();
+        assert!(matches!(
+            vault.read_note("a.md"),
+            Err(ArkeError::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_ignore_rules() {
+        let config = VaultConfig {
+            path: PathBuf::from("memory"),
+            name: "memory".to_string(),
+            watch: false,
+            settings: VaultSettings {
+                ignore: vec!["templates/".to_string()],
+                ..Default::default()
+            },
+        };
+        let mut vault = Vault::with_storage(config, MemoryStorage::new());
+        for path in [
+            "a.md",
+            "build/out.md",
+            "templates/daily.md",
+            "archive/old.md",
+            "archive/keep.md",
+            "node_modules/pkg/readme.md",
+        ] {
+            vault.write_note(Path::new(path), "content").unwrap();
+        }
+        vault
+            .storage()
+            .write(Path::new(".arkeignore"), b"# build output\n/build\n")
+            .unwrap();
+        vault
+            .storage()
+            .write(Path::new("archive/.arkeignore"), b"*.md\n!keep.md\n")
+            .unwrap();
+
+        let mut files = vault.list_files().unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![PathBuf::from("a.md"), PathBuf::from("archive/keep.md")]
+        );
+
+        assert!(vault.is_ignored(Path::new("build/out.md")));
+        assert!(vault.is_ignored(Path::new("templates/new.md")));
+        assert!(vault.is_ignored(Path::new("archive/old.md")));
+        assert!(!vault.is_ignored(Path::new("archive/keep.md")));
+        assert!(!vault.is_ignored(Path::new("a.md")));
+    }
+
+    #[test]
+    fn test_settings_persist() {
+        let (temp, mut vault) = create_test_vault();
+        vault.write_note(Path::new("a.md"), "a").unwrap();
+        vault.write_note(Path::new("drafts/b.md"), "b").unwrap();
+
+        let settings = VaultSettings {
+            ignore: vec!["drafts/".to_string()],
+            ..Default::default()
+        };
+        vault.update_settings(settings.clone()).unwrap();
+        assert_eq!(vault.list_files().unwrap(), vec![PathBuf::from("a.md")]);
+
+        let reopened = Vault::open(temp.path()).unwrap();
+        assert_eq!(reopened.settings(), &settings);
+        assert_eq!(reopened.list_files().unwrap(), vec![PathBuf::from("a.md")]);
+    }
+
+    #[test]
+    fn test_attachments() {
+        let config = VaultConfig {
+            path: PathBuf::from("memory"),
+            name: "memory".to_string(),
+            watch: false,
+            settings: VaultSettings::default(),
+        };
+        let mut vault = Vault::with_storage(config, MemoryStorage::new());
+
+        let first = vault.import_attachment("../photo.png", b"one").unwrap();
+        let second = vault.import_attachment("photo.png", b"two").unwrap();
+        let unused = vault.import_attachment("unused.pdf", b"pdf").unwrap();
+        assert_eq!(first, PathBuf::from("assets/photo.png"));
+        assert_eq!(second, PathBuf::from("assets/photo 1.png"));
+
+        vault
+            .write_note(
+                Path::new("notes/a.md"),
+                "![[photo.png]] ![chart](../assets/photo%201.png) ![[gone.jpg]]",
+            )
+            .unwrap();
+
+        let mut attachments = vault.list_attachments().unwrap();
+        attachments.sort();
+        assert_eq!(attachments, vec![second, first, unused.clone()]);
+
+        let report = vault.attachment_report().unwrap();
+        assert_eq!(report.orphaned, vec![unused]);
+        assert_eq!(
+            report.missing,
+            vec![MissingAttachment {
+                note: PathBuf::from("notes/a.md"),
+                target: "gone.jpg".to_string(),
+            }]
+        );
+
+        // A misconfigured folder can't send imports outside the vault or into `.arke`
+        for folder in ["../x", "/tmp", ".arke/files"] {
+            vault.config.settings.attachment_folder = folder.to_string();
+            let err = vault.import_attachment("photo.png", b"three").unwrap_err();
+            assert!(
+                matches!(err, ArkeError::Config { ref key, .. } if key == "attachmentFolder"),
+                "{}",
+                folder
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_conflicts() {
+        let (_temp, mut vault) = create_test_vault();
+        let path = Path::new("note.md");
+        vault.write_note(path, "v1").unwrap();
+
+        let version = vault.read_note(path).unwrap().version();
+        let next = vault.write_note_if(path, "v2 (ours)", &version).unwrap();
+
+        // Someone else edits the note on disk
+        vault.storage().write(path, b"v2 (theirs)").unwrap();
+        let err = vault.write_note_if(path, "v3 (ours)", &next).unwrap_err();
+        match err {
+            ArkeError::Conflict { ours, theirs, .. } => {
+                assert_eq!(ours, "v3 (ours)");
+                assert_eq!(theirs.as_deref(), Some("v2 (theirs)"));
+            }
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert_eq!(vault.read_note(path).unwrap().content, "v2 (theirs)");
+
+        // Deleted underneath us
+        let version = vault.read_note(path).unwrap().version();
+        vault.storage().remove(path).unwrap();
+        assert!(matches!(
+            vault.write_note_if(path, "v4", &version),
+            Err(ArkeError::Conflict { theirs: None, .. })
+        ));
+
+        // Matching modification time
+        vault.write_note(path, "v5").unwrap();
+        let modified = vault.read_note(path).unwrap().modified.unwrap();
+        vault
+            .write_note_if(path, "v6", &NoteVersion::Modified(modified))
+            .unwrap();
+        assert!(vault
+            .write_note_if(path, "v7", &NoteVersion::Modified(modified - 10))
+            .is_err());
+
+        // Creating a note that someone else created first
+        let new = Path::new("new.md");
+        vault
+            .write_note_if(new, "first", &NoteVersion::Absent)
+            .unwrap();
+        match vault.write_note_if(new, "second", &NoteVersion::Absent) {
+            Err(ArkeError::Conflict { theirs, .. }) => assert_eq!(theirs.as_deref(), Some("first")),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert_eq!(vault.read_note(new).unwrap().content, "first");
+    }
+
+    #[test]
+    fn test_conflict_copies() {
+        let (_temp, mut vault) = create_test_vault();
+        vault
+            .write_note(Path::new("plan.md"), "# Plan\n\n- ship\n")
+            .unwrap();
+        vault
+            .write_note(
+                Path::new("plan (conflicted copy).md"),
+                "# Plan\n\n- ship\n- celebrate\n",
+            )
+            .unwrap();
+        vault
+            .write_note(Path::new("orphan (conflicted copy).md"), "x")
+            .unwrap();
+
+        let copies = vault.conflict_copies().unwrap();
+        assert_eq!(
+            copies,
+            vec![ConflictCopy {
+                original: PathBuf::from("plan.md"),
+                copy: PathBuf::from("plan (conflicted copy).md"),
+            }]
+        );
+
+        let merged = vault.merge_conflict_copy(&copies[0]).unwrap();
+        assert_eq!(merged.merged().unwrap(), "# Plan\n\n- ship\n- celebrate\n");
+    }
+
+    #[test]
+    fn test_task_queries() {
+        let (_temp, mut vault) = create_test_vault();
+        vault
+            .write_note(
+                Path::new("a.md"),
+                "- [ ] Later 📅 2025-12-01\n- [ ] Soon due: 2025-10-01 #work\n- [x] Done 📅 2025-01-01",
+            )
+            .unwrap();
+        vault
+            .write_note(Path::new("b.md"), "- [ ] Whenever #work/admin")
+            .unwrap();
+
+        let open = vault.open_tasks_by_due().unwrap();
+        let texts: Vec<&str> = open.iter().map(|t| t.task.text.as_str()).collect();
+        assert_eq!(texts[0], "Soon due: 2025-10-01 #work");
+        assert_eq!(texts[1], "Later 📅 2025-12-01");
+        assert_eq!(texts[2], "Whenever #work/admin");
+
+        let today = NaiveDate::from_ymd_opt(2025, 11, 1).unwrap();
+        let overdue = vault.overdue_tasks(today).unwrap();
+        assert_eq!(overdue.len(), 1);
+        assert_eq!(overdue[0].task.line, 2);
+
+        let work = vault.tasks_with_tag("#work").unwrap();
+        assert_eq!(work.len(), 2);
+    }
+
+    #[test]
+    fn test_toggle_task() {
+        let (_temp, mut vault) = create_test_vault();
+        let path = Path::new("todo.md");
+        vault
+            .write_note(path, "# Todo\r\n\r\n- [ ] [[Link]] one\r\n- [x] two\r\n")
+            .unwrap();
+
+        assert!(vault.toggle_task(path, 3).unwrap());
+        assert!(!vault.toggle_task(path, 4).unwrap());
+        assert!(vault.toggle_task(path, 1).is_err());
+
+        let note = vault.read_note(path).unwrap();
+        assert_eq!(
+            note.content,
+            "# Todo\r\n\r\n- [x] [[Link]] one\r\n- [ ] two\r\n"
+        );
+    }
+
+    #[test]
+    fn test_create_block_reference() {
+        let (_temp, mut vault) = create_test_vault();
+        let path = Path::new("ideas.md");
+        vault
+            .write_note(
+                path,
+                "# Ideas\n\nFirst idea\nstill first\n\n- item ^existing\n",
+            )
+            .unwrap();
+
+        let reference = vault.create_block_reference(path, 4).unwrap();
+        let content = vault.read_note(path).unwrap().content.clone();
+        let id = reference
+            .strip_prefix("[[ideas#^")
+            .and_then(|r| r.strip_suffix("]]"))
+            .unwrap();
+        assert_eq!(id.len(), 6);
+        assert!(content.contains(&format!("still first ^{}\n", id)));
+
+        // Same block again reuses the id without rewriting
+        assert_eq!(vault.create_block_reference(path, 3).unwrap(), reference);
+        assert_eq!(vault.read_note(path).unwrap().content, content);
+
+        assert_eq!(
+            vault.create_block_reference(path, 6).unwrap(),
+            "[[ideas#^existing]]"
+        );
+        assert!(vault.create_block_reference(path, 1).is_err());
+
+        // Once another note shares the name, the reference uses the path
+        vault
+            .write_note(Path::new("old/ideas.md"), "- other ^existing\n")
+            .unwrap();
+        assert_eq!(
+            vault
+                .create_block_reference(Path::new("old/ideas.md"), 1)
+                .unwrap(),
+            "[[old/ideas#^existing]]"
+        );
+    }
+
+    #[test]
+    fn test_trash() {
+        let (_temp, mut vault) = create_test_vault();
+        vault.write_note(Path::new("Target.md"), "gone").unwrap();
+        vault
+            .write_note(
+                Path::new("source.md"),
+                "[[Target]] and [[Missing]]\n\n[see](Target.md) ![chart](assets/chart.png)",
+            )
+            .unwrap();
+        vault
+            .storage()
+            .write(Path::new("assets/chart.png"), b"png")
+            .unwrap();
+
+        let chart = vault.delete_note(Path::new("assets/chart.png")).unwrap();
+        let entry = vault.delete_note(Path::new("Target.md")).unwrap();
+        assert_eq!(
+            vault.list_files().unwrap(),
+            vec![PathBuf::from("source.md")]
+        );
+        assert_eq!(vault.list_trash().unwrap().len(), 2);
+
+        let links = vault.links_to_trash().unwrap();
+        let targets: Vec<_> = links.iter().map(|l| l.target.as_str()).collect();
+        assert_eq!(targets, ["Target", "Target.md", "assets/chart.png"]);
+        assert!(links.iter().all(|l| l.source == Path::new("source.md")));
+        assert_eq!(links[1].entry, entry);
+        assert_eq!(links[2].entry, chart);
+
+        let restored = vault.restore_from_trash(&entry.id).unwrap();
+        assert_eq!(restored, PathBuf::from("Target.md"));
+        assert_eq!(vault.read_note(&restored).unwrap().content, "gone");
+        assert_eq!(vault.links_to_trash().unwrap().len(), 1);
+
+        // Ids that aren't trash ids are refused before touching storage
+        assert!(vault.restore_from_trash("../../Target.md").is_err());
+        assert!(vault.read_note("Target.md").is_ok());
+
+        vault.delete_note(&restored).unwrap();
+        assert!(vault.purge_trash(0).unwrap().is_empty());
+        assert_eq!(vault.empty_trash().unwrap().len(), 2);
+        assert!(vault.list_trash().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_note_history() {
+        let (_temp, mut vault) = create_test_vault();
+        let mut settings = vault.settings().clone();
+        settings.history.enabled = true;
+        vault.update_settings(settings).unwrap();
+
+        let path = Path::new("a.md");
+        vault.write_note(path, "one\ntwo\n").unwrap();
+        vault.write_note(path, "one\nthree\n").unwrap();
+
+        let versions = vault.note_history(path).unwrap();
+        assert_eq!(versions.len(), 1);
+        let diff = vault
+            .diff_revisions(path, Some(versions[0].id), None)
+            .unwrap();
+        let changed: Vec<&str> = diff
+            .iter()
+            .filter(|l| l.kind != history::DiffKind::Equal)
+            .map(|l| l.text.as_str())
+            .collect();
+        assert_eq!(changed, vec!["two", "three"]);
+
+        vault.restore_revision(path, versions[0].id).unwrap();
+        assert_eq!(vault.read_note(path).unwrap().content, "one\ntwo\n");
+        assert_eq!(vault.note_history(path).unwrap().len(), 2);
+
+        // History follows the note when it moves
+        vault.rename_note(path, Path::new("b.md")).unwrap();
+        assert_eq!(vault.note_history(Path::new("b.md")).unwrap().len(), 2);
+        assert!(vault.note_history(path).unwrap().is_empty());
+
+        // A rename that happened is reported as one, even if its history can't follow
+        vault.write_note(Path::new("c.md"), "c1").unwrap();
+        vault.write_note(Path::new("c.md"), "c2").unwrap();
+        vault.delete_note(Path::new("c.md")).unwrap();
+        vault
+            .rename_note(Path::new("b.md"), Path::new("c.md"))
+            .unwrap();
+        assert_eq!(vault.read_note("c.md").unwrap().content, "one\ntwo\n");
+        assert_eq!(vault.note_history(Path::new("b.md")).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let (temp, mut vault) = create_test_vault();
+        vault.write_note(Path::new("a.md"), "A").unwrap();
+        vault.write_note(Path::new("b.md"), "B").unwrap();
+
+        let mut batch = Batch::new();
+        batch
+            .write("a.md", "A2")
+            .rename("b.md", "c.md")
+            .delete("missing.md");
+        assert!(vault.apply_batch(&batch).is_err());
+        assert_eq!(vault.read_note("a.md").unwrap().content, "A");
+        assert!(vault.read_note("b.md").is_ok());
+
+        batch.ops.pop();
+        batch.delete("a.md");
+        vault.apply_batch(&batch).unwrap();
+        assert_eq!(vault.list_files().unwrap(), vec![PathBuf::from("c.md")]);
+        assert_eq!(vault.list_trash().unwrap().len(), 1);
+
+        // A journal left by a crash is rolled back when the vault is reopened
+        let storage = NativeStorage::new(temp.path());
+        storage
+            .write(
+                Path::new(".arke/journal/journal.json"),
+                br#"{"steps": [{"op": {"kind": "write", "path": "new.md", "content": "x"}, "backup": false}],
+                    "current": 0, "committed": false}"#,
+            )
+            .unwrap();
+        storage.write(Path::new("new.md"), b"x").unwrap();
+        let vault = Vault::open(temp.path()).unwrap();
+        assert_eq!(vault.list_files().unwrap(), vec![PathBuf::from("c.md")]);
+    }
+
+    fn record_events(vault: &Vault) -> Arc<Mutex<Vec<VaultEvent>>> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        vault
+            .events()
+            .subscribe(move |e| sink.lock().unwrap().push(e.clone()));
+        seen
+    }
+
+    #[test]
+    fn test_api_events() {
+        let (_temp, mut vault) = create_test_vault();
+        let seen = record_events(&vault);
+
+        vault.write_note(Path::new("a.md"), "[[b]]").unwrap();
+        vault
+            .write_note(Path::new("a.md"), "---\ntags: [x]\n---\n[[b]]")
+            .unwrap();
+        vault
+            .rename_note(Path::new("a.md"), Path::new("c.md"))
+            .unwrap();
+        vault.delete_note(Path::new("c.md")).unwrap();
+
+        let path = PathBuf::from("a.md");
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                VaultEvent::NoteCreated { path: path.clone() },
+                VaultEvent::LinksChanged {
+                    path: path.clone(),
+                    added: vec!["b".into()],
+                    removed: vec![],
+                },
+                VaultEvent::NoteUpdated { path: path.clone() },
+                VaultEvent::FrontmatterChanged {
+                    path: path.clone(),
+                    keys: vec!["tags".into()],
+                },
+                VaultEvent::NoteRenamed {
+                    from: path,
+                    to: "c.md".into(),
+                },
+                VaultEvent::NoteDeleted {
+                    path: "c.md".into(),
+                },
+            ]
+        );
+        seen.lock().unwrap().clear();
+
+        // Only changes to notes are reported, as for batches and disk changes
+        let chart = Path::new("assets/chart.png");
+        vault.storage().write(chart, b"png").unwrap();
+        vault.rename_note(chart, Path::new("chart.png")).unwrap();
+        vault.delete_note(Path::new("chart.png")).unwrap();
+        assert!(seen.lock().unwrap().is_empty());
+
+        vault.storage().write(Path::new("d.txt"), b"[[b]]").unwrap();
+        vault
+            .rename_note(Path::new("d.txt"), Path::new("d.md"))
+            .unwrap();
+        vault
+            .rename_note(Path::new("d.md"), Path::new("d.txt"))
+            .unwrap();
+        let path = PathBuf::from("d.md");
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                VaultEvent::NoteCreated { path: path.clone() },
+                VaultEvent::LinksChanged {
+                    path: path.clone(),
+                    added: vec!["b".into()],
+                    removed: vec![],
+                },
+                VaultEvent::NoteDeleted { path },
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "native")]
+    fn test_watch_events() {
+        use notify::event::{CreateKind, EventKind, ModifyKind, RemoveKind, RenameMode};
+
+        let (temp, vault) = create_test_vault();
+        let mut vault = Vault::new(VaultConfig {
+            watch: true,
+            ..vault.config().clone()
+        })
+        .unwrap();
+        let seen = record_events(&vault);
+        let event = |kind, paths: &[&str]| {
+            paths.iter().fold(notify::Event::new(kind), |e, p| {
+                e.add_path(temp.path().join(p))
+            })
+        };
+
+        // The vault's own changes aren't reported again
+        vault.write_note(Path::new("a.md"), "one").unwrap();
+        vault
+            .rename_note(Path::new("a.md"), Path::new("b.md"))
+            .unwrap();
+        vault.handle_watch_event(&event(EventKind::Create(CreateKind::File), &["a.md"]));
+        vault.handle_watch_event(&event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &["a.md", "b.md"],
+        ));
+        assert_eq!(seen.lock().unwrap().len(), 2);
+        seen.lock().unwrap().clear();
+
+        // Changes made by others are
+        std::fs::write(temp.path().join("b.md"), "two [[x]]").unwrap();
+        vault.handle_watch_event(&event(EventKind::Modify(ModifyKind::Any), &["b.md"]));
+        std::fs::rename(temp.path().join("b.md"), temp.path().join("c.md")).unwrap();
+        vault.handle_watch_event(&event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &["b.md", "c.md"],
+        ));
+        std::fs::remove_file(temp.path().join("c.md")).unwrap();
+        vault.handle_watch_event(&event(EventKind::Remove(RemoveKind::File), &["c.md"]));
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                VaultEvent::NoteUpdated {
+                    path: "b.md".into()
+                },
+                VaultEvent::LinksChanged {
+                    path: "b.md".into(),
+                    added: vec!["x".into()],
+                    removed: vec![],
+                },
+                VaultEvent::NoteRenamed {
+                    from: "b.md".into(),
+                    to: "c.md".into(),
+                },
+                VaultEvent::NoteDeleted {
+                    path: "c.md".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_folder_operations() {
+        let (_temp, mut vault) = create_test_vault();
+        vault
+            .write_note(Path::new("projects/plan.md"), "See [[projects/ideas]]")
+            .unwrap();
+        vault
+            .write_note(Path::new("projects/ideas.md"), "[home](../index.md)")
+            .unwrap();
+        vault
+            .write_note(Path::new("index.md"), "[[projects/plan|Plan]] [[ideas]]")
+            .unwrap();
+        vault.create_folder(Path::new("archive")).unwrap();
+        assert!(vault.create_folder(Path::new("index.md")).is_err());
+
+        let report = vault
+            .move_folder(Path::new("projects"), Path::new("archive"))
+            .unwrap();
+        assert_eq!(report.moved.len(), 2);
+        assert_eq!(
+            report.updated,
+            vec![
+                PathBuf::from("archive/projects/ideas.md"),
+                PathBuf::from("archive/projects/plan.md"),
+                PathBuf::from("index.md"),
+            ]
+        );
+        assert_eq!(
+            vault.read_note("index.md").unwrap().content,
+            "[[archive/projects/plan|Plan]] [[ideas]]"
+        );
+        assert_eq!(
+            vault
+                .read_note("archive/projects/ideas.md")
+                .unwrap()
+                .content,
+            "[home](../../index.md)"
+        );
+        assert!(!vault.storage().exists(Path::new("projects")));
+        assert!(vault
+            .rename_folder(Path::new("archive"), Path::new("archive/inner"))
+            .is_err());
+
+        // Paths leading outside the vault are rejected before anything is touched
+        assert!(vault
+            .rename_folder(Path::new("archive"), Path::new("../elsewhere"))
+            .is_err());
+        assert!(vault
+            .rename_folder(Path::new("archive/.."), Path::new("root"))
+            .is_err());
+        assert!(vault.create_folder(Path::new("/tmp/outside")).is_err());
+        assert!(vault.delete_folder(Path::new("archive/../..")).is_err());
+        assert!(vault.storage().exists(Path::new("archive/projects")));
+
+        let report = vault.delete_folder(Path::new("archive")).unwrap();
+        assert_eq!(report.trashed.len(), 2);
+        assert!(!vault.storage().exists(Path::new("archive")));
+        assert_eq!(vault.list_files().unwrap(), vec![PathBuf::from("index.md")]);
+        assert_eq!(vault.links_to_trash().unwrap().len(), 1);
+        assert!(vault.delete_folder(Path::new(".arke")).is_err());
+    }
+
+    #[test]
+    fn test_generate_block_id_avoids_collisions() {
+        let first = generate_block_id("text", |_| false);
+        let second = generate_block_id("text", |id| id == first);
+
+        assert_eq!(first, generate_block_id("text", |_| false));
+        assert_ne!(first, second);
+    }
 }
@@ -0,0 +1,1046 @@
+//! JavaScript bindings for the web build
+//!
+//! Results cross the boundary as plain JS objects (via serde) and errors are
+//! thrown as `Error` instances carrying a `code` property from [`ArkeError::code`].
+
+use crate::batch::{Batch, BatchOp};
+use crate::config::VaultSettings;
+use crate::error::ArkeError;
+use crate::events::VaultEvent;
+use crate::links::{LinkExtractor, LinksMap};
+use crate::merge;
+use crate::parser::MarkdownParser;
+use crate::periodic::Period;
+use crate::storage::{DirEntry, FileMetadata, MemoryStorage, Storage};
+use crate::templates::TemplateValues;
+use crate::vault::{content_hash, NoteVersion, Vault, VaultConfig};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_TYPES: &str = r#"
+export interface Heading { level: number; text: string; id: string; }
+export interface CodeBlock {
+  language: string | null;
+  attributes: string | null;
+  code: string;
+  fenced: boolean;
+  startLine: number;
+  endLine: number;
+}
+export interface MathExpression { literal: string; display: boolean; line: number; }
+export type CalloutFold = "expanded" | "collapsed";
+export interface Callout {
+  kind: string;
+  title: string;
+  fold: CalloutFold | null;
+  content: string;
+  startLine: number;
+  endLine: number;
+}
+export type TaskPriority = "lowest" | "low" | "medium" | "high" | "highest";
+export interface Task {
+  line: number;
+  text: string;
+  checked: boolean;
+  depth: number;
+  heading: string | null;
+  /** ISO date, `YYYY-MM-DD` */
+  due: string | null;
+  priority: TaskPriority | null;
+  tags: string[];
+}
+export type BlockKind = "paragraph" | "listItem";
+export interface BlockId {
+  id: string;
+  kind: BlockKind;
+  text: string;
+  startLine: number;
+  endLine: number;
+}
+export interface ParsedMarkdown {
+  raw: string;
+  html: string;
+  headings: Heading[];
+  codeBlocks: CodeBlock[];
+  math: MathExpression[];
+  callouts: Callout[];
+  tasks: Task[];
+  blockIds: BlockId[];
+}
+export interface WikiLink { target: string; display: string | null; position: number; }
+export interface SearchResult { path: string; score: number; snippet: string; }
+export interface MissingAttachment { note: string; target: string; }
+export interface AttachmentReport { orphaned: string[]; missing: MissingAttachment[]; }
+export type MergeRegion =
+  | { kind: "resolved"; text: string }
+  | { kind: "conflict"; key: string | null; base: string; ours: string; theirs: string };
+export interface MergeResult { regions: MergeRegion[]; }
+export interface NewNote {
+  path: string;
+  /** Link to the note in the vault's link style */
+  link: string;
+}
+export interface TrashEntry {
+  id: string;
+  originalPath: string;
+  /** Seconds since the Unix epoch */
+  deletedAt: number | null;
+}
+export interface Revision {
+  id: number;
+  hash: string;
+  /** Seconds since the Unix epoch */
+  savedAt: number | null;
+  size: number;
+}
+export interface DiffLine { kind: "equal" | "insert" | "delete"; text: string; }
+export type BatchOp =
+  | { kind: "write"; path: string; content: string }
+  | { kind: "rename"; from: string; to: string }
+  | { kind: "delete"; path: string };
+export interface MovedFile { from: string; to: string; }
+export interface FolderReport { moved: MovedFile[]; updated: string[]; trashed: TrashEntry[]; }
+export interface TrashedLink { source: string; target: string; entry: TrashEntry; }
+export type Period = "daily" | "weekly" | "monthly" | "yearly";
+/** Dates are ISO `YYYY-MM-DD` strings */
+export interface PeriodicNote { period: Period; date: string; path: string; }
+export type VaultEvent =
+  | { kind: "noteCreated"; path: string }
+  | { kind: "noteUpdated"; path: string }
+  | { kind: "noteRenamed"; from: string; to: string }
+  | { kind: "noteDeleted"; path: string }
+  | { kind: "frontmatterChanged"; path: string; keys: string[] }
+  | { kind: "linksChanged"; path: string; added: string[]; removed: string[] }
+  | { kind: "indexUpdated"; notes: number };
+/**
+ * Synchronous file storage supplied by the host (e.g. OPFS sync access handles).
+ * Paths are relative to the vault root and use `/` separators. Throw an error
+ * named `NotFoundError` for missing files.
+ */
+export interface StorageBackend {
+  list(dir: string): { path: string; isDir: boolean }[];
+  read(path: string): Uint8Array;
+  /** Must create parent directories as needed */
+  write(path: string, contents: Uint8Array): void;
+  /** Must create parent directories as needed; works for files and directories */
+  rename(from: string, to: string): void;
+  remove(path: string): void;
+  /** Must create parent directories as needed, and succeed if the directory exists */
+  createDir(path: string): void;
+  /** Remove an empty directory */
+  removeDir(path: string): void;
+  /** `modified` is milliseconds since the Unix epoch; return null if missing */
+  metadata(path: string): { isDir: boolean; size: number; modified?: number | null } | null;
+}
+/** Parser options in vault settings */
+export interface ParserOptions {
+  hardBreaks: boolean;
+  math: boolean;
+  footnotes: boolean;
+  smartPunctuation: boolean;
+}
+export interface HistorySettings {
+  enabled: boolean;
+  maxVersions: number;
+  maxAgeDays: number | null;
+}
+export interface PeriodSettings {
+  folder: string;
+  format: string | null;
+  template: string | null;
+}
+export interface PeriodicSettings {
+  daily: PeriodSettings;
+  weekly: PeriodSettings;
+  monthly: PeriodSettings;
+  yearly: PeriodSettings;
+}
+/** Settings persisted in `.arke/config.json` */
+export interface VaultSettings {
+  version: number;
+  ignore: string[];
+  attachmentFolder: string;
+  templateFolder: string;
+  newNoteFolder: string;
+  periodic: PeriodicSettings;
+  linkStyle: "wikilink" | "markdown";
+  parser: ParserOptions;
+  history: HistorySettings;
+}
+/** Error thrown by the core engine */
+export interface ArkeError extends Error {
+  code:
+    | "VAULT"
+    | "FILE_NOT_FOUND"
+    | "IO"
+    | "PARSE"
+    | "INVALID_WIKILINK"
+    | "SERIALIZATION"
+    | "CONFIG"
+    | "INDEX"
+    | "CANCELLED"
+    | "CONFLICT"
+    | "UNKNOWN";
+  /** For `CONFLICT`: the content being written */
+  ours?: string;
+  /** For `CONFLICT`: the content now stored, or null if the note was deleted */
+  theirs?: string | null;
+}
+"#;
+
+/// Convert an engine error into a JS `Error` with a `code` property
+impl From<ArkeError> for JsValue {
+    fn from(err: ArkeError) -> Self {
+        let js_err = js_sys::Error::new(&err.to_string());
+        js_err.set_name("ArkeError");
+        // Setting a property on a fresh Error object cannot fail
+        let _ = js_sys::Reflect::set(&js_err, &"code".into(), &err.code().into());
+        if let ArkeError::Conflict { ours, theirs, .. } = &err {
+            let theirs = theirs.as_deref().map_or(JsValue::NULL, JsValue::from);
+            let _ = js_sys::Reflect::set(&js_err, &"ours".into(), &ours.into());
+            let _ = js_sys::Reflect::set(&js_err, &"theirs".into(), &theirs);
+        }
+        js_err.into()
+    }
+}
+
+/// Serialize a value into a plain JS object
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsValue> {
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| ArkeError::Serialization(serde::ser::Error::custom(e)).into())
+}
+
+/// Markdown parser exposed to JavaScript
+#[wasm_bindgen(js_name = MarkdownParser)]
+pub struct WasmMarkdownParser {
+    inner: MarkdownParser,
+}
+
+#[wasm_bindgen(js_class = MarkdownParser)]
+impl WasmMarkdownParser {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            inner: MarkdownParser::new(),
+        }
+    }
+
+    /// Parse markdown and extract its structure
+    #[wasm_bindgen(unchecked_return_type = "ParsedMarkdown")]
+    pub fn parse(&self, markdown: &str) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.parse(markdown)?)
+    }
+
+    /// Render markdown to HTML
+    #[wasm_bindgen(js_name = parseToHtml)]
+    pub fn parse_to_html(&self, markdown: &str) -> String {
+        self.inner.parse_to_html(markdown)
+    }
+}
+
+impl Default for WasmMarkdownParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wikilink extractor exposed to JavaScript
+#[wasm_bindgen(js_name = LinkExtractor)]
+pub struct WasmLinkExtractor {
+    inner: LinkExtractor,
+}
+
+#[wasm_bindgen(js_class = LinkExtractor)]
+impl WasmLinkExtractor {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            inner: LinkExtractor::new(),
+        }
+    }
+
+    /// Extract all wikilinks from markdown content
+    #[wasm_bindgen(unchecked_return_type = "WikiLink[]")]
+    pub fn extract(&self, content: &str) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.extract(content))
+    }
+
+    /// Resolve a wikilink target against a list of vault paths
+    #[wasm_bindgen(js_name = resolveLink)]
+    pub fn resolve_link(&self, target: &str, vault_files: Vec<String>) -> Option<String> {
+        let files: Vec<PathBuf> = vault_files.into_iter().map(PathBuf::from).collect();
+        self.inner
+            .resolve_link(target, &files)
+            .map(|p| p.to_string_lossy().into_owned())
+    }
+}
+
+impl Default for WasmLinkExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Three-way merge of two versions of a note that share `base`
+#[wasm_bindgen(js_name = mergeNotes, unchecked_return_type = "MergeResult")]
+pub fn merge_notes(base: &str, ours: &str, theirs: &str) -> Result<JsValue, JsValue> {
+    to_js(&merge::merge(base, ours, theirs))
+}
+
+/// JS object implementing file storage for the web build
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "StorageBackend")]
+    pub type StorageBackend;
+
+    #[wasm_bindgen(method, catch, js_name = list)]
+    fn js_list(this: &StorageBackend, dir: &str) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = read)]
+    fn js_read(this: &StorageBackend, path: &str) -> Result<Vec<u8>, JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = write)]
+    fn js_write(this: &StorageBackend, path: &str, contents: &[u8]) -> Result<(), JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = rename)]
+    fn js_rename(this: &StorageBackend, from: &str, to: &str) -> Result<(), JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = remove)]
+    fn js_remove(this: &StorageBackend, path: &str) -> Result<(), JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = createDir)]
+    fn js_create_dir(this: &StorageBackend, path: &str) -> Result<(), JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = removeDir)]
+    fn js_remove_dir(this: &StorageBackend, path: &str) -> Result<(), JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = metadata)]
+    fn js_metadata(this: &StorageBackend, path: &str) -> Result<JsValue, JsValue>;
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsDirEntry {
+    path: String,
+    is_dir: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsFileMetadata {
+    is_dir: bool,
+    size: u64,
+    /// Milliseconds since the Unix epoch
+    modified: Option<f64>,
+}
+
+/// [`Storage`] backed by synchronous JS callbacks
+///
+/// Suitable for OPFS sync access handles in a worker, or a JS-side cache
+/// kept in step with the File System Access API.
+pub struct JsStorage {
+    backend: StorageBackend,
+}
+
+impl JsStorage {
+    /// Wrap a JS storage backend object
+    pub fn new(backend: StorageBackend) -> Self {
+        Self { backend }
+    }
+}
+
+/// Convert a JS exception into an engine error
+fn from_js_error(err: JsValue, path: &Path) -> ArkeError {
+    if let Some(err) = err.dyn_ref::<js_sys::Error>() {
+        if err.name() == "NotFoundError" {
+            return ArkeError::FileNotFound(path.display().to_string());
+        }
+        return ArkeError::Io(std::io::Error::other(String::from(err.message())));
+    }
+
+    let message = err.as_string().unwrap_or_else(|| format!("{:?}", err));
+    ArkeError::Io(std::io::Error::other(message))
+}
+
+fn path_str(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+impl Storage for JsStorage {
+    fn list(&self, dir: &Path) -> crate::Result<Vec<DirEntry>> {
+        let value = self
+            .backend
+            .js_list(&path_str(dir))
+            .map_err(|e| from_js_error(e, dir))?;
+        let entries: Vec<JsDirEntry> =
+            serde_wasm_bindgen::from_value(value).map_err(|e| ArkeError::Parse(e.to_string()))?;
+
+        Ok(entries
+            .into_iter()
+            .map(|e| DirEntry {
+                path: PathBuf::from(e.path),
+                is_dir: e.is_dir,
+            })
+            .collect())
+    }
+
+    fn read(&self, path: &Path) -> crate::Result<Vec<u8>> {
+        self.backend
+            .js_read(&path_str(path))
+            .map_err(|e| from_js_error(e, path))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> crate::Result<()> {
+        self.backend
+            .js_write(&path_str(path), contents)
+            .map_err(|e| from_js_error(e, path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> crate::Result<()> {
+        self.backend
+            .js_rename(&path_str(from), &path_str(to))
+            .map_err(|e| from_js_error(e, from))
+    }
+
+    fn remove(&self, path: &Path) -> crate::Result<()> {
+        self.backend
+            .js_remove(&path_str(path))
+            .map_err(|e| from_js_error(e, path))
+    }
+
+    fn create_dir(&self, path: &Path) -> crate::Result<()> {
+        self.backend
+            .js_create_dir(&path_str(path))
+            .map_err(|e| from_js_error(e, path))
+    }
+
+    fn remove_dir(&self, path: &Path) -> crate::Result<()> {
+        self.backend
+            .js_remove_dir(&path_str(path))
+            .map_err(|e| from_js_error(e, path))
+    }
+
+    fn metadata(&self, path: &Path) -> crate::Result<FileMetadata> {
+        let value = self
+            .backend
+            .js_metadata(&path_str(path))
+            .map_err(|e| from_js_error(e, path))?;
+        if value.is_null() || value.is_undefined() {
+            return Err(ArkeError::FileNotFound(path.display().to_string()));
+        }
+
+        let metadata: JsFileMetadata =
+            serde_wasm_bindgen::from_value(value).map_err(|e| ArkeError::Parse(e.to_string()))?;
+        Ok(FileMetadata {
+            is_dir: metadata.is_dir,
+            size: metadata.size,
+            modified: metadata
+                .modified
+                .map(|ms| UNIX_EPOCH + Duration::from_millis(ms as u64)),
+        })
+    }
+}
+
+/// A search hit, shaped like the native index's `SearchResult`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchHit {
+    path: PathBuf,
+    score: f32,
+    snippet: String,
+}
+
+/// Vault for the web build
+///
+/// Backed by in-memory storage by default, or by a JS [`StorageBackend`]
+/// (e.g. over OPFS) via `Vault.withStorage`.
+#[wasm_bindgen(js_name = Vault)]
+pub struct WasmVault {
+    inner: Vault<Box<dyn Storage>>,
+    links: LinkExtractor,
+}
+
+#[wasm_bindgen(js_class = Vault)]
+impl WasmVault {
+    /// Create a vault held in memory; the host hands notes over with `setNote`
+    #[wasm_bindgen(constructor)]
+    pub fn new(name: &str) -> Self {
+        Self::from_storage(
+            name,
+            Box::new(MemoryStorage::new()),
+            VaultSettings::default(),
+        )
+    }
+
+    /// Create a vault whose files live in a JS storage backend, loading its saved settings
+    #[wasm_bindgen(js_name = withStorage)]
+    pub fn with_storage(name: &str, backend: StorageBackend) -> Result<WasmVault, JsValue> {
+        let storage: Box<dyn Storage> = Box::new(JsStorage::new(backend));
+        let settings = VaultSettings::load(&storage)?;
+        let mut vault = Self::from_storage(name, storage, settings);
+        vault.inner.recover_batch()?;
+        Ok(vault)
+    }
+
+    /// Vault name
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> String {
+        self.inner.config().name.clone()
+    }
+
+    /// Add or replace a note's content
+    ///
+    /// With `expectedHash` (from `noteHash`), throws a `CONFLICT` error instead
+    /// of overwriting a note that changed since. Returns the new content hash.
+    #[wasm_bindgen(js_name = setNote)]
+    pub fn set_note(
+        &mut self,
+        path: &str,
+        content: &str,
+        #[wasm_bindgen(js_name = expectedHash)] expected_hash: Option<String>,
+    ) -> Result<String, JsValue> {
+        let path = Path::new(path);
+        match expected_hash {
+            Some(hash) => {
+                self.inner
+                    .write_note_if(path, content, &NoteVersion::Hash(hash))?;
+            }
+            None => self.inner.write_note(path, content)?,
+        }
+        Ok(content_hash(content.as_bytes()))
+    }
+
+    /// Add a note that must not exist yet, throwing a `CONFLICT` error if it does
+    ///
+    /// Returns the new content hash.
+    #[wasm_bindgen(js_name = addNote)]
+    pub fn add_note(&mut self, path: &str, content: &str) -> Result<String, JsValue> {
+        self.inner
+            .write_note_if(Path::new(path), content, &NoteVersion::Absent)?;
+        Ok(content_hash(content.as_bytes()))
+    }
+
+    /// Content hash of a note as currently stored
+    #[wasm_bindgen(js_name = noteHash)]
+    pub fn note_hash(&mut self, path: &str) -> Result<String, JsValue> {
+        Ok(self.inner.read_note(path)?.hash.clone())
+    }
+
+    /// Get a note's content
+    #[wasm_bindgen(js_name = getNote)]
+    pub fn get_note(&mut self, path: &str) -> Result<String, JsValue> {
+        Ok(self.inner.read_note(path)?.content.clone())
+    }
+
+    /// Apply writes, renames and deletes together, rolling all of them back if any fails
+    ///
+    /// Returns the trash entries of deleted notes.
+    #[wasm_bindgen(js_name = applyBatch, unchecked_return_type = "TrashEntry[]")]
+    pub fn apply_batch(
+        &mut self,
+        #[wasm_bindgen(unchecked_param_type = "BatchOp[]")] ops: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let ops: Vec<BatchOp> =
+            serde_wasm_bindgen::from_value(ops).map_err(|e| ArkeError::Parse(e.to_string()))?;
+        to_js(&self.inner.apply_batch(&Batch { ops })?)
+    }
+
+    /// Move a note to the vault trash
+    #[wasm_bindgen(js_name = removeNote, unchecked_return_type = "TrashEntry")]
+    pub fn remove_note(&mut self, path: &str) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.delete_note(Path::new(path))?)
+    }
+
+    /// Notes in the trash, most recently deleted first
+    #[wasm_bindgen(js_name = listTrash, unchecked_return_type = "TrashEntry[]")]
+    pub fn list_trash(&self) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.list_trash()?)
+    }
+
+    /// Restore a note from the trash, returning the path it was restored to
+    #[wasm_bindgen(js_name = restoreFromTrash)]
+    pub fn restore_from_trash(&mut self, id: &str) -> Result<String, JsValue> {
+        Ok(path_str(&self.inner.restore_from_trash(id)?))
+    }
+
+    /// Permanently delete everything in the trash
+    #[wasm_bindgen(js_name = emptyTrash, unchecked_return_type = "TrashEntry[]")]
+    pub fn empty_trash(&mut self) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.empty_trash()?)
+    }
+
+    /// Permanently delete trash entries deleted before `cutoff` (seconds since the Unix epoch)
+    #[wasm_bindgen(js_name = purgeTrash, unchecked_return_type = "TrashEntry[]")]
+    pub fn purge_trash(&mut self, cutoff: f64) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.purge_trash(cutoff as u64)?)
+    }
+
+    /// Links and embeds whose target is in the trash
+    #[wasm_bindgen(js_name = linksToTrash, unchecked_return_type = "TrashedLink[]")]
+    pub fn links_to_trash(&mut self) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.links_to_trash()?)
+    }
+
+    /// Rename or move a note
+    #[wasm_bindgen(js_name = renameNote)]
+    pub fn rename_note(&mut self, old_path: &str, new_path: &str) -> Result<(), JsValue> {
+        Ok(self
+            .inner
+            .rename_note(Path::new(old_path), Path::new(new_path))?)
+    }
+
+    /// Recorded versions of a note, newest first
+    #[wasm_bindgen(js_name = noteHistory, unchecked_return_type = "Revision[]")]
+    pub fn note_history(&self, path: &str) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.note_history(Path::new(path))?)
+    }
+
+    /// Content of a recorded version of a note
+    #[wasm_bindgen(js_name = readRevision)]
+    pub fn read_revision(&self, path: &str, id: f64) -> Result<String, JsValue> {
+        Ok(self.inner.read_revision(Path::new(path), id as u64)?)
+    }
+
+    /// Line diff between two versions of a note; omit a version for the current content
+    #[wasm_bindgen(js_name = diffRevisions, unchecked_return_type = "DiffLine[]")]
+    pub fn diff_revisions(
+        &self,
+        path: &str,
+        from: Option<f64>,
+        to: Option<f64>,
+    ) -> Result<JsValue, JsValue> {
+        let (from, to) = (from.map(|id| id as u64), to.map(|id| id as u64));
+        to_js(&self.inner.diff_revisions(Path::new(path), from, to)?)
+    }
+
+    /// Replace a note's content with a recorded version
+    #[wasm_bindgen(js_name = restoreRevision)]
+    pub fn restore_revision(&mut self, path: &str, id: f64) -> Result<(), JsValue> {
+        Ok(self.inner.restore_revision(Path::new(path), id as u64)?)
+    }
+
+    /// Create an empty folder, along with any missing parents
+    #[wasm_bindgen(js_name = createFolder)]
+    pub fn create_folder(&mut self, path: &str) -> Result<(), JsValue> {
+        Ok(self.inner.create_folder(Path::new(path))?)
+    }
+
+    /// Rename or move a folder, updating links that spell out moved paths
+    #[wasm_bindgen(js_name = renameFolder, unchecked_return_type = "FolderReport")]
+    pub fn rename_folder(&mut self, from: &str, to: &str) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.rename_folder(Path::new(from), Path::new(to))?)
+    }
+
+    /// Move a folder into `parent` (`""` for the vault root), keeping its name
+    #[wasm_bindgen(js_name = moveFolder, unchecked_return_type = "FolderReport")]
+    pub fn move_folder(&mut self, from: &str, parent: &str) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.move_folder(Path::new(from), Path::new(parent))?)
+    }
+
+    /// Move every file in a folder to the trash, then remove the folder
+    #[wasm_bindgen(js_name = deleteFolder, unchecked_return_type = "FolderReport")]
+    pub fn delete_folder(&mut self, path: &str) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.delete_folder(Path::new(path))?)
+    }
+
+    /// Open the periodic note for the period containing `date`, creating it if needed
+    ///
+    /// Returns the note's path.
+    #[wasm_bindgen(js_name = openPeriodicNote)]
+    pub fn open_periodic_note(
+        &mut self,
+        #[wasm_bindgen(unchecked_param_type = "Period")] period: JsValue,
+        date: &str,
+    ) -> Result<String, JsValue> {
+        let note = self
+            .inner
+            .open_periodic_note(parse_period(period)?, parse_date(date)?)?;
+        Ok(path_str(&note.path))
+    }
+
+    /// Existing periodic notes for periods overlapping `from` through `to`, oldest first
+    #[wasm_bindgen(js_name = periodicNotes, unchecked_return_type = "PeriodicNote[]")]
+    pub fn periodic_notes(
+        &self,
+        #[wasm_bindgen(unchecked_param_type = "Period")] period: JsValue,
+        from: &str,
+        to: &str,
+    ) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.periodic_notes(
+            parse_period(period)?,
+            parse_date(from)?,
+            parse_date(to)?,
+        )?)
+    }
+
+    /// The closest existing periodic note before the period containing `date`
+    #[wasm_bindgen(
+        js_name = previousPeriodicNote,
+        unchecked_return_type = "PeriodicNote | null"
+    )]
+    pub fn previous_periodic_note(
+        &self,
+        #[wasm_bindgen(unchecked_param_type = "Period")] period: JsValue,
+        date: &str,
+    ) -> Result<JsValue, JsValue> {
+        to_js(
+            &self
+                .inner
+                .previous_periodic_note(parse_period(period)?, parse_date(date)?)?,
+        )
+    }
+
+    /// The closest existing periodic note after the period containing `date`
+    #[wasm_bindgen(js_name = nextPeriodicNote, unchecked_return_type = "PeriodicNote | null")]
+    pub fn next_periodic_note(
+        &self,
+        #[wasm_bindgen(unchecked_param_type = "Period")] period: JsValue,
+        date: &str,
+    ) -> Result<JsValue, JsValue> {
+        to_js(
+            &self
+                .inner
+                .next_periodic_note(parse_period(period)?, parse_date(date)?)?,
+        )
+    }
+
+    /// Names of the templates in the template folder, without `.md`, sorted
+    #[wasm_bindgen(js_name = listTemplates)]
+    pub fn list_templates(&self) -> Result<Vec<String>, JsValue> {
+        Ok(self.inner.list_templates()?)
+    }
+
+    /// Names of the prompts a template and its includes ask for, in order of first use
+    #[wasm_bindgen(js_name = templatePrompts)]
+    pub fn template_prompts(&self, template: &str) -> Result<Vec<String>, JsValue> {
+        Ok(self.inner.template_prompts(template)?)
+    }
+
+    /// Create a note from a title without overwriting anything
+    ///
+    /// Goes in `folder`, or the default new note folder if it's omitted.
+    #[wasm_bindgen(js_name = createNote, unchecked_return_type = "NewNote")]
+    pub fn create_note(
+        &mut self,
+        title: &str,
+        content: &str,
+        folder: Option<String>,
+    ) -> Result<JsValue, JsValue> {
+        let note = match folder {
+            Some(folder) => self
+                .inner
+                .create_note_in(Path::new(&folder), title, content)?,
+            None => self.inner.create_note(title, content)?,
+        };
+        to_js(&note)
+    }
+
+    /// Create a note in `folder` from a template, returning its path
+    ///
+    /// `name` is the file name pattern and `now` a local `YYYY-MM-DDTHH:MM:SS` time.
+    #[wasm_bindgen(js_name = createNoteFromTemplate)]
+    pub fn create_note_from_template(
+        &mut self,
+        template: &str,
+        folder: &str,
+        name: &str,
+        now: &str,
+        #[wasm_bindgen(unchecked_param_type = "Record<string, string>")] prompts: JsValue,
+    ) -> Result<String, JsValue> {
+        let now = NaiveDateTime::parse_from_str(now, "%Y-%m-%dT%H:%M:%S")
+            .map_err(|e| ArkeError::Parse(format!("Invalid time {:?}: {}", now, e)))?;
+        let mut values = TemplateValues::new(now);
+        if !prompts.is_undefined() && !prompts.is_null() {
+            values.prompts = serde_wasm_bindgen::from_value(prompts)
+                .map_err(|e| ArkeError::Parse(e.to_string()))?;
+        }
+        let path =
+            self.inner
+                .create_note_from_template(template, Path::new(folder), name, &values)?;
+        Ok(path_str(&path))
+    }
+
+    /// Call `listener` with every change event, returning an id for `offEvent`
+    #[wasm_bindgen(js_name = onEvent)]
+    pub fn on_event(
+        &self,
+        #[wasm_bindgen(unchecked_param_type = "(event: VaultEvent) => void")]
+        listener: js_sys::Function,
+    ) -> u32 {
+        let listener = JsListener::new(listener);
+        let id = self
+            .inner
+            .events()
+            .subscribe(move |event| listener.call(event));
+        id as u32
+    }
+
+    /// Stop a subscription made with `onEvent`; returns whether it existed
+    #[wasm_bindgen(js_name = offEvent)]
+    pub fn off_event(&self, id: u32) -> bool {
+        self.inner.events().unsubscribe(id as u64)
+    }
+
+    /// Paths of all notes, sorted
+    #[wasm_bindgen(js_name = listFiles)]
+    pub fn list_files(&self) -> Result<Vec<String>, JsValue> {
+        let mut files = self.inner.list_files()?;
+        files.sort();
+        Ok(files.iter().map(|p| path_str(p)).collect())
+    }
+
+    /// Vault settings
+    #[wasm_bindgen(unchecked_return_type = "VaultSettings")]
+    pub fn settings(&self) -> Result<JsValue, JsValue> {
+        to_js(self.inner.settings())
+    }
+
+    /// Replace the vault settings and save them to `.arke/config.json`
+    ///
+    /// Missing keys are reset to their defaults, not kept.
+    #[wasm_bindgen(js_name = updateSettings)]
+    pub fn update_settings(
+        &mut self,
+        #[wasm_bindgen(unchecked_param_type = "VaultSettings")] settings: JsValue,
+    ) -> Result<(), JsValue> {
+        // Round-trip through JSON so invalid values are reported by key
+        let json = String::from(js_sys::JSON::stringify(&settings)?);
+        let settings = VaultSettings::from_json(&json)?;
+        Ok(self.inner.update_settings(settings)?)
+    }
+
+    /// Paths of all attachments (non-markdown files), sorted
+    #[wasm_bindgen(js_name = listAttachments)]
+    pub fn list_attachments(&self) -> Result<Vec<String>, JsValue> {
+        let mut files = self.inner.list_attachments()?;
+        files.sort();
+        Ok(files.iter().map(|p| path_str(p)).collect())
+    }
+
+    /// Save a file into the attachment folder under a free name, returning its path
+    #[wasm_bindgen(js_name = importAttachment)]
+    pub fn import_attachment(&mut self, name: &str, contents: &[u8]) -> Result<String, JsValue> {
+        Ok(path_str(&self.inner.import_attachment(name, contents)?))
+    }
+
+    /// Orphaned attachments and references to missing ones
+    #[wasm_bindgen(js_name = attachmentReport, unchecked_return_type = "AttachmentReport")]
+    pub fn attachment_report(&mut self) -> Result<JsValue, JsValue> {
+        to_js(&self.inner.attachment_report()?)
+    }
+
+    /// Whether a path is excluded by the vault's ignore rules
+    #[wasm_bindgen(js_name = isIgnored)]
+    pub fn is_ignored(&self, path: &str) -> bool {
+        self.inner.is_ignored(Path::new(path))
+    }
+
+    /// Outbound wikilinks of a note
+    #[wasm_bindgen(unchecked_return_type = "WikiLink[]")]
+    pub fn links(&mut self, path: &str) -> Result<JsValue, JsValue> {
+        let content = self.get_note(path)?;
+        to_js(&self.links.extract(&content))
+    }
+
+    /// Paths of the notes linking to `path`
+    pub fn backlinks(&mut self, path: &str) -> Result<Vec<String>, JsValue> {
+        let (files, links_map) = self.load_links()?;
+        let backlinks = self.links.build_backlinks_map(&links_map, &files);
+
+        Ok(self
+            .links
+            .get_backlinks(&PathBuf::from(path), &backlinks)
+            .iter()
+            .map(|p| path_str(p))
+            .collect())
+    }
+
+    /// Unresolved link targets, keyed by source note path
+    #[wasm_bindgen(js_name = brokenLinks, unchecked_return_type = "Record<string, string[]>")]
+    pub fn broken_links(&mut self) -> Result<JsValue, JsValue> {
+        let (files, links_map) = self.load_links()?;
+        let broken: BTreeMap<String, Vec<String>> = self
+            .links
+            .find_broken_links(&links_map, &files)
+            .into_iter()
+            .map(|(p, targets)| (path_str(&p), targets))
+            .collect();
+        to_js(&broken)
+    }
+
+    /// Case-insensitive search requiring every query term, best matches first
+    #[wasm_bindgen(unchecked_return_type = "SearchResult[]")]
+    pub fn search(&mut self, query: &str, limit: Option<usize>) -> Result<JsValue, JsValue> {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        if terms.is_empty() {
+            return to_js(&Vec::<SearchHit>::new());
+        }
+
+        let mut hits: Vec<SearchHit> = self
+            .load_notes()?
+            .into_iter()
+            .filter_map(|(path, content)| {
+                let haystack = format!("{}\n{}", path.to_string_lossy(), content).to_lowercase();
+                let counts: Vec<usize> =
+                    terms.iter().map(|t| haystack.matches(t).count()).collect();
+                if counts.contains(&0) {
+                    return None;
+                }
+
+                Some(SearchHit {
+                    snippet: snippet(&content, &terms[0]),
+                    path,
+                    score: counts.iter().sum::<usize>() as f32,
+                })
+            })
+            .collect();
+
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        hits.truncate(limit.unwrap_or(usize::MAX));
+        to_js(&hits)
+    }
+}
+
+impl WasmVault {
+    fn from_storage(name: &str, storage: Box<dyn Storage>, settings: VaultSettings) -> Self {
+        let config = VaultConfig {
+            path: PathBuf::new(),
+            name: name.to_string(),
+            watch: false,
+            settings,
+        };
+
+        Self {
+            inner: Vault::with_storage(config, storage),
+            links: LinkExtractor::new(),
+        }
+    }
+
+    /// Read every note in the vault
+    fn load_notes(&mut self) -> crate::Result<Vec<(PathBuf, String)>> {
+        let mut notes = Vec::new();
+        for path in self.inner.list_files()? {
+            let content = self.inner.read_note(&path)?.content.clone();
+            notes.push((path, content));
+        }
+        Ok(notes)
+    }
+
+    /// Vault file list (notes, then attachments) and outbound links of every note
+    fn load_links(&mut self) -> crate::Result<(Vec<PathBuf>, LinksMap)> {
+        let notes = self.load_notes()?;
+        let mut files: Vec<PathBuf> = notes.iter().map(|(p, _)| p.clone()).collect();
+        files.extend(self.inner.list_attachments()?);
+        Ok((files, self.links.build_links_map(&notes)))
+    }
+}
+
+fn parse_period(period: JsValue) -> Result<Period, JsValue> {
+    serde_wasm_bindgen::from_value(period)
+        .map_err(|e| ArkeError::Parse(format!("Invalid period: {}", e)).into())
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, JsValue> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|e| ArkeError::Parse(format!("Invalid date {:?}: {}", date, e)).into())
+}
+
+thread_local! {
+    /// Functions passed to `onEvent`, by key; JS values can't leave the thread that made them
+    static JS_LISTENERS: RefCell<HashMap<u64, js_sys::Function>> = RefCell::default();
+    static NEXT_JS_LISTENER: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A JS event listener, held in [`JS_LISTENERS`] until this is dropped
+///
+/// Only the key is stored on the event bus, which requires `Send + Sync`.
+struct JsListener(u64);
+
+impl JsListener {
+    fn new(function: js_sys::Function) -> Self {
+        let key = NEXT_JS_LISTENER.with(|next| next.replace(next.get() + 1));
+        JS_LISTENERS.with(|listeners| listeners.borrow_mut().insert(key, function));
+        Self(key)
+    }
+
+    fn call(&self, event: &VaultEvent) {
+        // Cloned out, so the listener can subscribe or unsubscribe while it runs
+        let function = JS_LISTENERS.with(|listeners| listeners.borrow().get(&self.0).cloned());
+        // A listener that throws shouldn't fail the change that triggered it
+        if let (Some(function), Ok(event)) = (function, to_js(event)) {
+            let _ = function.call1(&JsValue::NULL, &event);
+        }
+    }
+}
+
+impl Drop for JsListener {
+    fn drop(&mut self) {
+        let _ = JS_LISTENERS.try_with(|listeners| listeners.borrow_mut().remove(&self.0));
+    }
+}
+
+/// A short excerpt of `content` around the first occurrence of `term`
+fn snippet(content: &str, term: &str) -> String {
+    const CONTEXT: usize = 60;
+
+    // Lowercase char by char, remembering which original char each folded one
+    // came from, since lowercasing can turn one char into several
+    let chars: Vec<char> = content.chars().collect();
+    let folded: Vec<(usize, char)> = chars
+        .iter()
+        .enumerate()
+        .flat_map(|(i, c)| c.to_lowercase().map(move |l| (i, l)))
+        .collect();
+    let term: Vec<char> = term.chars().collect();
+    let (match_start, match_end) = folded
+        .windows(term.len().max(1))
+        .find(|window| window.iter().map(|(_, c)| *c).eq(term.iter().copied()))
+        .map_or((0, 0), |window| {
+            (window[0].0, window[window.len() - 1].0 + 1)
+        });
+
+    let start = match_start.saturating_sub(CONTEXT);
+    let end = (match_end + CONTEXT).min(chars.len());
+
+    let mut snippet: String = chars[start..end].iter().collect();
+    snippet = snippet.split_whitespace().collect::<Vec<_>>().join(" ");
+    if start > 0 {
+        snippet.insert(0, '…');
+    }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snippet() {
+        let content = format!("{} Needle here {}", "a ".repeat(50), "b ".repeat(50));
+        let snip = snippet(&content, "needle");
+
+        assert!(snip.starts_with('…'));
+        assert!(snip.ends_with('…'));
+        assert!(snip.contains("Needle here"));
+        assert_eq!(snippet("short text", "text"), "short text");
+
+        // `İ` lowercases to two chars, which used to shift the match past the text
+        let content = format!("{}x needle", "İ".repeat(100));
+        assert!(snippet(&content, "needle").ends_with("x needle"));
+    }
+}